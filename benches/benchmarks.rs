@@ -1,3 +1,5 @@
+#[cfg(all(test, feature = "async-fs"))]
+use async_fs as afs;
 #[cfg(feature = "async-std")]
 use async_std::fs as afs;
 #[cfg(feature = "link_to")]
@@ -5,27 +7,13 @@ use std::path::PathBuf;
 #[cfg(all(test, feature = "tokio"))]
 use tokio::fs as afs;
 
-#[cfg(all(test, feature = "async-std"))]
-pub use async_std::task::block_on;
-
-#[cfg(all(test, feature = "tokio"))]
-lazy_static::lazy_static! {
-    static ref TOKIO_RUNTIME: tokio::runtime::Runtime = tokio::runtime::Runtime::new().unwrap();
-}
-
-#[cfg(all(test, feature = "tokio"))]
-#[inline]
-pub fn block_on<F, T>(future: F) -> T
-where
-    F: std::future::Future<Output = T>,
-{
-    TOKIO_RUNTIME.block_on(future)
-}
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use cacache::runtime::block_on;
 
 use std::fs::{self, File};
 use std::io::prelude::*;
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 
 const NUM_REPEATS: usize = 10;
 
@@ -62,7 +50,7 @@ fn baseline_read_many_sync(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn baseline_read_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let path = tmp.path().join("test_file");
@@ -75,7 +63,7 @@ fn baseline_read_async(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn baseline_read_many_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let paths: Vec<_> = (0..)
@@ -190,7 +178,7 @@ fn read_hash_sync_big_data_xxh3(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn read_hash_many_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let cache = tmp.path().to_owned();
@@ -212,7 +200,7 @@ fn read_hash_many_async(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn read_hash_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let cache = tmp.path().to_owned();
@@ -223,7 +211,7 @@ fn read_hash_async(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn read_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let cache = tmp.path().to_owned();
@@ -234,7 +222,18 @@ fn read_async(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+fn reader_open_async(c: &mut Criterion) {
+    let tmp = tempfile::tempdir().unwrap();
+    let cache = tmp.path().to_owned();
+    let data = b"hello world".to_vec();
+    cacache::write_sync(&cache, "hello", data).unwrap();
+    c.bench_function("get::reader_open", move |b| {
+        b.iter(|| block_on(cacache::Reader::open(black_box(&cache), black_box("hello"))).unwrap())
+    });
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn read_hash_async_big_data(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let cache = tmp.path().to_owned();
@@ -278,7 +277,101 @@ fn write_hash_xxh3(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+fn write_tiny_chunks_sync(c: &mut Criterion) {
+    let tmp = tempfile::tempdir().unwrap();
+    let cache = tmp.path().to_owned();
+    let mut group = c.benchmark_group("put::tiny_chunks::sync");
+    for buf_size in [0, 8 * 1024] {
+        group.bench_function(format!("{}KiB", buf_size / 1024), |b| {
+            b.iter(|| {
+                let mut writer = cacache::WriteOpts::new()
+                    .write_buffer_size(buf_size)
+                    .open_hash_sync(&cache)
+                    .unwrap();
+                for i in 0..4096u32 {
+                    writer.write_all(&[i as u8]).unwrap();
+                }
+                writer.commit().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn write_many_tiny_sync(c: &mut Criterion) {
+    let entries: Vec<_> = (0..1000)
+        .map(|i| (format!("key-{i}"), format!("data-{i}").into_bytes()))
+        .collect();
+    let mut group = c.benchmark_group("put::many_tiny::sync");
+    group.sample_size(10);
+    group.bench_function("1000", |b| {
+        b.iter_batched(
+            || tempfile::tempdir().unwrap(),
+            |tmp| {
+                for (key, data) in &entries {
+                    cacache::write_sync(tmp.path(), key, data).unwrap();
+                }
+                // Hand the directory back so cleaning it up isn't measured.
+                tmp
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("1000/write_many_sync", |b| {
+        b.iter_batched(
+            || (tempfile::tempdir().unwrap(), entries.clone()),
+            |(tmp, entries)| {
+                cacache::write_many_sync(tmp.path(), entries).unwrap();
+                tmp
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+fn exists_many_sync(c: &mut Criterion) {
+    let tmp = tempfile::tempdir().unwrap();
+    let sparse = tmp.path().join("sparse");
+    let sparse_sris: Vec<_> = (0..3000)
+        .map(|i| cacache::write_hash_sync(&sparse, format!("test_file_{i}")).unwrap())
+        .collect();
+    // A cache with millions of entries has several in each content
+    // directory. Fake that by laying out content for made-up hashes that
+    // share directories.
+    let dense = tmp.path().join("dense");
+    let dense_sris: Vec<_> = (0..3000)
+        .map(|i| {
+            let hex = format!("{:04x}{:060x}", i % 200, i);
+            let dir = dense
+                .join("content-v2/sha256")
+                .join(&hex[0..2])
+                .join(&hex[2..4]);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(&hex[4..]), b"").unwrap();
+            cacache::Integrity::from_hex(&hex, cacache::Algorithm::Sha256).unwrap()
+        })
+        .collect();
+    let mut group = c.benchmark_group("get::exists_many::sync");
+    for (name, cache, sris) in [
+        ("sparse", &sparse, &sparse_sris),
+        ("dense", &dense, &dense_sris),
+    ] {
+        group.bench_function(format!("{name}/exists_sync"), |b| {
+            b.iter(|| {
+                for sri in sris.iter() {
+                    black_box(cacache::exists_sync(black_box(cache), black_box(sri)));
+                }
+            })
+        });
+        group.bench_function(format!("{name}/exists_many_sync"), |b| {
+            b.iter(|| black_box(cacache::exists_many_sync(black_box(cache), black_box(sris))))
+        });
+    }
+    group.finish();
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn write_hash_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let cache = tmp.path().to_owned();
@@ -293,7 +386,34 @@ fn write_hash_async(c: &mut Criterion) {
     });
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+fn write_big_data_async(c: &mut Criterion) {
+    let data = vec![1; 4 * 1024 * 1024];
+    let mut group = c.benchmark_group("put::big_data");
+    group.bench_function("write", |b| {
+        b.iter_batched(
+            || (tempfile::tempdir().unwrap(), data.clone()),
+            |(tmp, data)| {
+                block_on(cacache::write(tmp.path(), "key", &data)).unwrap();
+                tmp
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("write_owned", |b| {
+        b.iter_batched(
+            || (tempfile::tempdir().unwrap(), data.clone()),
+            |(tmp, data)| {
+                block_on(cacache::write_owned(tmp.path(), "key", data)).unwrap();
+                tmp
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn write_hash_async_xxh3(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let cache = tmp.path().to_owned();
@@ -317,7 +437,7 @@ fn write_hash_async_xxh3(c: &mut Criterion) {
 fn create_tmpfile(tmp: &tempfile::TempDir, buf: &[u8]) -> PathBuf {
     let dir = tmp.path().to_owned();
     let target = dir.join("target-file");
-    std::fs::create_dir_all(&target.parent().unwrap()).unwrap();
+    std::fs::create_dir_all(target.parent().unwrap()).unwrap();
     let mut file = File::create(target.clone()).unwrap();
     file.write_all(buf).unwrap();
     file.flush().unwrap();
@@ -325,7 +445,7 @@ fn create_tmpfile(tmp: &tempfile::TempDir, buf: &[u8]) -> PathBuf {
 }
 
 #[cfg(feature = "link_to")]
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 fn link_to_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let target = create_tmpfile(&tmp, b"hello world");
@@ -348,7 +468,10 @@ fn link_to_async(c: &mut Criterion) {
     });
 }
 
-#[cfg(all(feature = "link_to", any(feature = "async-std", feature = "tokio")))]
+#[cfg(all(
+    feature = "link_to",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
 fn link_to_hash_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
     let target = create_tmpfile(&tmp, b"hello world");
@@ -390,12 +513,38 @@ fn link_to_hash_sync(c: &mut Criterion) {
     });
 }
 
+#[cfg(feature = "link_to")]
+fn link_to_sync_big_data(c: &mut Criterion) {
+    let tmp = tempfile::tempdir().unwrap();
+    let target = create_tmpfile(&tmp, &vec![1; 1024 * 1024 * 64]);
+
+    let tmp = tempfile::tempdir().unwrap();
+    let cache = tmp.path().to_owned();
+    let mut group = c.benchmark_group("link_to::file_sync_big_data");
+    for buf_size in [16 * 1024, 256 * 1024] {
+        group.bench_function(format!("{}KiB", buf_size / 1024), |b| {
+            b.iter(|| {
+                cacache::WriteOpts::new()
+                    .read_buffer_size(buf_size)
+                    .link_to_hash_sync(&cache, target.clone())
+                    .unwrap()
+                    .commit()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     baseline_read_sync,
     baseline_read_many_sync,
     write_hash,
     write_hash_xxh3,
+    write_tiny_chunks_sync,
+    write_many_tiny_sync,
+    exists_many_sync,
     read_hash_sync,
     read_hash_sync_xxh3,
     read_hash_many_sync,
@@ -405,7 +554,7 @@ criterion_group!(
     read_hash_sync_big_data_xxh3,
 );
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 criterion_group!(
     benches_async,
     baseline_read_async,
@@ -413,28 +562,41 @@ criterion_group!(
     read_hash_async,
     read_hash_many_async,
     read_async,
+    reader_open_async,
     write_hash_async,
     write_hash_async_xxh3,
+    write_big_data_async,
     read_hash_async_big_data,
 );
 
-#[cfg(all(feature = "link_to", any(feature = "async-std", feature = "tokio")))]
+#[cfg(all(
+    feature = "link_to",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
 criterion_group!(link_to_benches_async, link_to_async, link_to_hash_async,);
 
 #[cfg(feature = "link_to")]
-criterion_group!(link_to_benches, link_to_sync, link_to_hash_sync);
+criterion_group!(
+    link_to_benches,
+    link_to_sync,
+    link_to_hash_sync,
+    link_to_sync_big_data
+);
 
 #[cfg(all(
     feature = "link_to",
-    not(any(feature = "async-std", feature = "tokio"))
+    not(any(feature = "async-std", feature = "tokio", feature = "async-fs"))
 ))]
 criterion_main!(benches, link_to_benches);
 #[cfg(all(
     not(feature = "link_to"),
-    any(feature = "async-std", feature = "tokio")
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
 ))]
 criterion_main!(benches, benches_async);
-#[cfg(all(feature = "link_to", any(feature = "async-std", feature = "tokio")))]
+#[cfg(all(
+    feature = "link_to",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
 criterion_main!(
     benches,
     benches_async,
@@ -443,6 +605,6 @@ criterion_main!(
 );
 #[cfg(all(
     not(feature = "link_to"),
-    not(any(feature = "async-std", feature = "tokio"))
+    not(any(feature = "async-std", feature = "tokio", feature = "async-fs"))
 ))]
 criterion_main!(benches);
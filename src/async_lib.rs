@@ -1,91 +1,110 @@
+#[cfg(feature = "async-fs")]
+pub use async_fs::File;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::File;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::File;
 
-#[cfg(feature = "async-std")]
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
 pub use futures::io::AsyncRead;
 #[cfg(feature = "tokio")]
 pub use tokio::io::AsyncRead;
 
-#[cfg(feature = "async-std")]
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
 pub use futures::io::AsyncReadExt;
 #[cfg(feature = "tokio")]
 pub use tokio::io::AsyncReadExt;
 
-#[cfg(feature = "async-std")]
-pub use futures::io::AsyncBufReadExt;
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
+pub use futures::io::AsyncSeekExt;
 #[cfg(feature = "tokio")]
-pub use tokio::io::AsyncBufReadExt;
+pub use tokio::io::AsyncSeekExt;
 
-#[cfg(feature = "async-std")]
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
 pub use futures::io::AsyncWrite;
 #[cfg(feature = "tokio")]
 pub use tokio::io::AsyncWrite;
 
-#[cfg(feature = "async-std")]
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
 pub use futures::io::AsyncWriteExt;
 #[cfg(feature = "tokio")]
 pub use tokio::io::AsyncWriteExt;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::read;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::read;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::read;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::copy;
+
 #[cfg(feature = "async-std")]
 pub use async_std::fs::copy;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::copy;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::rename;
+#[cfg(feature = "async-std")]
+pub use async_std::fs::rename;
+#[cfg(feature = "tokio")]
+pub use tokio::fs::rename;
+
+#[cfg(feature = "async-fs")]
+pub use async_fs::metadata;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::metadata;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::metadata;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::remove_file;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::remove_file;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::remove_file;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::create_dir_all;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::create_dir_all;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::create_dir_all;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::remove_dir;
+#[cfg(feature = "async-std")]
+pub use async_std::fs::remove_dir;
+#[cfg(feature = "tokio")]
+pub use tokio::fs::remove_dir;
+
+#[cfg(feature = "async-fs")]
+pub use async_fs::remove_dir_all;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::remove_dir_all;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::remove_dir_all;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::DirBuilder;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::DirBuilder;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::DirBuilder;
 
+#[cfg(feature = "async-fs")]
+pub use async_fs::OpenOptions;
 #[cfg(feature = "async-std")]
 pub use async_std::fs::OpenOptions;
 #[cfg(feature = "tokio")]
 pub use tokio::fs::OpenOptions;
 
-#[cfg(feature = "async-std")]
-pub use async_std::io::BufReader;
-#[cfg(feature = "tokio")]
-pub use tokio::io::BufReader;
-
-#[cfg(feature = "async-std")]
-#[inline]
-pub fn lines_to_stream<R>(lines: futures::io::Lines<R>) -> futures::io::Lines<R> {
-    lines
-}
-#[cfg(feature = "tokio")]
-#[inline]
-pub fn lines_to_stream<R>(lines: tokio::io::Lines<R>) -> tokio_stream::wrappers::LinesStream<R> {
-    tokio_stream::wrappers::LinesStream::new(lines)
-}
-
 #[cfg(feature = "async-std")]
 pub use async_std::task::spawn_blocking;
+#[cfg(feature = "async-fs")]
+pub use blocking::unblock as spawn_blocking;
 #[cfg(feature = "tokio")]
 pub use tokio::task::spawn_blocking;
 
@@ -103,35 +122,87 @@ pub use tokio::task::JoinHandle;
 pub fn unwrap_joinhandle_value<T>(value: T) -> T {
     value
 }
+#[cfg(feature = "async-fs")]
+pub use blocking::Task as JoinHandle;
+#[cfg(feature = "async-fs")]
+#[inline]
+pub fn unwrap_joinhandle_value<T>(value: T) -> T {
+    value
+}
 
-use tempfile::NamedTempFile;
-
+#[cfg(feature = "tokio")]
 use crate::errors::IoErrorExt;
 
-#[cfg(feature = "async-std")]
-#[inline]
-pub async fn create_named_tempfile(
-    tmp_path: std::path::PathBuf,
-) -> Option<crate::Result<NamedTempFile>> {
-    let cloned = tmp_path.clone();
-
-    Some(
-        spawn_blocking(|| NamedTempFile::new_in(tmp_path))
-            .await
-            .with_context(|| format!("Failed to create a temp file at {}", cloned.display())),
-    )
+/// Runs `f` on the runtime's blocking thread pool and waits for its result.
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
+pub async fn run_blocking<F, T>(f: F) -> crate::Result<T>
+where
+    F: FnOnce() -> crate::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocking(f).await
 }
 
+/// Runs `f` on the runtime's blocking thread pool and waits for its result.
+#[cfg(feature = "tokio")]
+pub async fn run_blocking<F, T>(f: F) -> crate::Result<T>
+where
+    F: FnOnce() -> crate::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::from)
+        .with_context(|| "Blocking cache task failed to complete".into())?
+}
+
+/// Lists the entries of `dir`, sorted by file name, along with whether each
+/// one is a directory.
 #[cfg(feature = "tokio")]
-#[inline]
-pub async fn create_named_tempfile(
-    tmp_path: std::path::PathBuf,
-) -> Option<crate::Result<NamedTempFile>> {
-    let cloned = tmp_path.clone();
-    match spawn_blocking(|| NamedTempFile::new_in(tmp_path)).await {
-        Ok(ctx) => Some(
-            ctx.with_context(|| format!("Failed to create a temp file at {}", cloned.display())),
-        ),
-        _ => None,
+pub async fn read_dir_sorted(
+    dir: &std::path::Path,
+) -> std::io::Result<Vec<(std::path::PathBuf, bool)>> {
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    let mut entries = Vec::new();
+    while let Some(entry) = read_dir.next_entry().await? {
+        entries.push((entry.path(), entry.file_type().await?.is_dir()));
+    }
+    entries.sort_by(|(a, _), (b, _)| a.file_name().cmp(&b.file_name()));
+    Ok(entries)
+}
+
+/// Lists the entries of `dir`, sorted by file name, along with whether each
+/// one is a directory.
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
+pub async fn read_dir_sorted(
+    dir: &std::path::Path,
+) -> std::io::Result<Vec<(std::path::PathBuf, bool)>> {
+    use futures::stream::TryStreamExt;
+
+    #[cfg(feature = "async-std")]
+    let mut read_dir = async_std::fs::read_dir(dir).await?;
+    #[cfg(feature = "async-fs")]
+    let mut read_dir = async_fs::read_dir(dir).await?;
+    let mut entries = Vec::new();
+    while let Some(entry) = read_dir.try_next().await? {
+        #[cfg(feature = "async-std")]
+        let path = entry.path().into();
+        #[cfg(feature = "async-fs")]
+        let path = entry.path();
+        entries.push((path, entry.file_type().await?.is_dir()));
+    }
+    entries
+        .sort_by(|(a, _): &(std::path::PathBuf, bool), (b, _)| a.file_name().cmp(&b.file_name()));
+    Ok(entries)
+}
+
+/// Stands in for `async_std` in tests built with `async-fs`, which isn't tied
+/// to any runtime: `async_attributes::test` runs each test body with
+/// `async_std::task::block_on`, so this points that at `async-io`'s, which,
+/// like async-std's, can be nested.
+#[cfg(all(test, feature = "async-fs"))]
+pub mod test_runtime {
+    pub mod task {
+        pub use async_io::block_on;
     }
 }
@@ -0,0 +1,529 @@
+//! A handle to a single cache directory.
+use std::borrow::Cow;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use ssri::{Algorithm, Integrity};
+
+use crate::config::{CacheConfig, CacheDir, Layout};
+use crate::content::read;
+use crate::errors::{Error, IoErrorExt, Result};
+use crate::index::{self, KeyNormalizer, Metadata};
+use crate::ls::ListIter;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use crate::ls::ListStream;
+use crate::put::WriteOpts;
+
+/// A handle to a cache directory, carrying its resolved root path and
+/// configuration so they don't need to be passed around with every call.
+///
+/// Handles are cheap to clone, and clones share any state that's been
+/// probed, such as whether the cache's filesystem supports reflinks.
+///
+/// A handle can also be layered over other, read-only caches with
+/// `fallback()`, so that content they already hold doesn't need to be
+/// duplicated. Each cache keeps its own index, and writes only ever go to
+/// this one.
+///
+/// Opening a handle reads the cache's `CacheConfig`, if it has one, so the
+/// handle honours any custom layout it sets.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let cache = cacache::Cache::open("./my-cache")?
+///         .algorithm(cacache::Algorithm::Xxh3);
+///     cache.write("my-key", b"hello").await?;
+///     let data = cache.read("my-key").await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cache {
+    root: PathBuf,
+    layout: Layout,
+    algorithm: Algorithm,
+    key_normalizer: Option<KeyNormalizer>,
+    fallbacks: Vec<(PathBuf, Layout)>,
+    reflink: Arc<OnceLock<bool>>,
+}
+
+impl Cache {
+    /// Opens a handle to the cache at `path`, creating the directory if it
+    /// doesn't exist yet and resolving it to an absolute path. Fails if the
+    /// cache has a `CacheConfig` that can't be read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Cache> {
+        fn inner(path: &Path) -> Result<Cache> {
+            fs::create_dir_all(path)
+                .with_context(|| format!("Failed to create cache directory at {path:?}"))?;
+            let root = fs::canonicalize(path)
+                .with_context(|| format!("Failed to resolve cache directory at {path:?}"))?;
+            let layout = CacheConfig::load(&root)?.layout();
+            Ok(Cache {
+                root,
+                layout,
+                algorithm: Algorithm::Sha256,
+                key_normalizer: None,
+                fallbacks: Vec::new(),
+                reflink: Arc::new(OnceLock::new()),
+            })
+        }
+        inner(path.as_ref())
+    }
+
+    /// Sets the algorithm new content is hashed with. Defaults to sha256.
+    pub fn algorithm(mut self, algo: Algorithm) -> Self {
+        self.algorithm = algo;
+        self
+    }
+
+    /// Sets a function used to normalize keys before they're hashed into an
+    /// index bucket, so logically-equal keys (say, differing only in case)
+    /// end up in the same entry. It applies to every read, write, and removal
+    /// made through this handle, and to writers opened with `write_opts()`.
+    /// The free functions use keys exactly as given, so entries written this
+    /// way should only be accessed through a handle with the same normalizer.
+    pub fn key_normalizer(mut self, normalizer: KeyNormalizer) -> Self {
+        self.key_normalizer = Some(normalizer);
+        self
+    }
+
+    /// Adds a cache to read content from when it's missing from this one.
+    /// Fallbacks are checked in the order they were added, and are never
+    /// written to. Only their content is used: keys are always looked up in
+    /// this cache's own index. Fails if the fallback has a `CacheConfig` that
+    /// can't be read.
+    pub fn fallback<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let layout = CacheConfig::load(path)?.layout();
+        self.fallbacks.push((path.to_path_buf(), layout));
+        Ok(self)
+    }
+
+    /// Returns a handle scoped to `namespace`, a separate cache nested inside
+    /// this one, at `namespaces/{namespace}`. Namespaces keep their own index
+    /// and content, so identical data written to two of them is stored twice,
+    /// and content in one can't be read through another, even by hash.
+    /// Integrity strings are the same as in any other cache.
+    ///
+    /// Namespace names must be non-empty and made up only of ASCII letters,
+    /// digits, `-`, `_`, and `.`, and can't be `.` or `..`. Namespacing an
+    /// already namespaced handle nests the new namespace inside it. Garbage
+    /// collecting or verifying the outer cache leaves its namespaces alone,
+    /// but `clear()` removes them along with everything else.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// fn main() -> cacache::Result<()> {
+    ///     let cache = cacache::Cache::open("./my-cache")?;
+    ///     let tenant = cache.namespace("tenant-a")?;
+    ///     tenant.write_sync("my-key", b"hello")?;
+    ///     assert!(cache.metadata_sync("my-key")?.is_none());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn namespace<S: AsRef<str>>(&self, namespace: S) -> Result<Cache> {
+        fn inner(cache: &Cache, namespace: &str) -> Result<Cache> {
+            if !crate::config::is_valid_dir_name(namespace) {
+                return Err(Error::IoError(
+                    std::io::Error::new(ErrorKind::InvalidInput, "invalid cache namespace"),
+                    format!("Invalid cache namespace {namespace:?}"),
+                ));
+            }
+            let root = cache.root.join("namespaces").join(namespace);
+            fs::create_dir_all(&root)
+                .with_context(|| format!("Failed to create cache namespace at {root:?}"))?;
+            let layout = CacheConfig::load(&root)?.layout();
+            Ok(Cache {
+                root,
+                layout,
+                ..cache.clone()
+            })
+        }
+        inner(self, namespace.as_ref())
+    }
+
+    /// Root directory of this cache.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns write options preconfigured with this cache's settings, for
+    /// when more control over a write is needed.
+    pub fn write_opts(&self) -> WriteOpts {
+        let opts = WriteOpts::new()
+            .algorithm(self.algorithm)
+            .layout(self.layout.clone());
+        match self.key_normalizer {
+            Some(normalizer) => opts.key_normalizer(normalizer),
+            None => opts,
+        }
+    }
+
+    /// Returns true if this cache's filesystem supports reflinks. The probe
+    /// only runs once per handle.
+    pub fn supports_reflink(&self) -> bool {
+        *self
+            .reflink
+            .get_or_init(|| read::supports_reflink(&self.root))
+    }
+
+    /// Reads the entire contents of a cache file into a bytes vector, looking
+    /// the data up by key.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn read<K: AsRef<str>>(&self, key: K) -> Result<Vec<u8>> {
+        let key = self.key(key.as_ref());
+        match index::find_in_async(self.dir(), &key).await? {
+            Some(entry) => {
+                let root = self.content_root_async(&entry.integrity).await;
+                read::read_sized_async(root, &entry.integrity, entry.size)
+                    .await
+                    .map_err(|e| e.with_key(&key))
+            }
+            None => Err(Error::EntryNotFound(self.root.clone(), key.into_owned())),
+        }
+    }
+
+    /// Reads the entire contents of a cache file into a bytes vector, looking
+    /// the data up by its content address.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        read::read_async(self.content_root_async(sri).await, sri).await
+    }
+
+    /// Returns true if the given hash exists in this cache or any of its
+    /// fallbacks.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn exists(&self, sri: &Integrity) -> bool {
+        let root = self.content_root_async(sri).await;
+        read::has_content_async(root, sri).await.is_some()
+    }
+
+    /// Writes `data` to the cache, indexing it under `key`.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn write<K, D>(&self, key: K, data: D) -> Result<Integrity>
+    where
+        K: AsRef<str>,
+        D: AsRef<[u8]>,
+    {
+        use crate::async_lib::AsyncWriteExt;
+        let (key, data) = (key.as_ref(), data.as_ref());
+        let mut writer = self
+            .write_opts()
+            .size(data.len() as u64)
+            .open(&self.root, key)
+            .await?;
+        writer.write_all(data).await.with_context(|| {
+            format!(
+                "Failed to write to cache data for key {key} for cache at {:?}",
+                self.root
+            )
+        })?;
+        writer.commit().await
+    }
+
+    /// Writes `data` to the cache, skipping associating an index key with it.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn write_hash<D: AsRef<[u8]>>(&self, data: D) -> Result<Integrity> {
+        use crate::async_lib::AsyncWriteExt;
+        let data = data.as_ref();
+        let mut writer = self
+            .write_opts()
+            .size(data.len() as u64)
+            .open_hash(&self.root)
+            .await?;
+        writer.write_all(data).await.with_context(|| {
+            format!("Failed to write to cache data for cache at {:?}", self.root)
+        })?;
+        writer.commit().await
+    }
+
+    /// Gets the metadata entry for a certain key.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn metadata<K: AsRef<str>>(&self, key: K) -> Result<Option<Metadata>> {
+        index::find_in_async(self.dir(), &self.key(key.as_ref())).await
+    }
+
+    /// Removes an individual index entry. The associated content will be
+    /// left in the cache.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn remove<K: AsRef<str>>(&self, key: K) -> Result<()> {
+        index::delete_in_async(self.dir(), &self.key(key.as_ref())).await
+    }
+
+    /// Returns a stream that lists all entries in this cache's own index.
+    /// Keys are listed as they're stored, so with a key normalizer, they're
+    /// the normalized keys.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub fn list(&self) -> ListStream {
+        crate::ls::list_in_async(self.dir())
+    }
+
+    /// Reads the entire contents of a cache file synchronously into a bytes
+    /// vector, looking the data up by key.
+    pub fn read_sync<K: AsRef<str>>(&self, key: K) -> Result<Vec<u8>> {
+        let key = self.key(key.as_ref());
+        match index::find_in(self.dir(), &key)? {
+            Some(entry) => read::read_sized(
+                self.content_root(&entry.integrity),
+                &entry.integrity,
+                entry.size,
+            )
+            .map_err(|e| e.with_key(&key)),
+            None => Err(Error::EntryNotFound(self.root.clone(), key.into_owned())),
+        }
+    }
+
+    /// Reads the entire contents of a cache file synchronously into a bytes
+    /// vector, looking the data up by its content address.
+    pub fn read_hash_sync(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        read::read(self.content_root(sri), sri)
+    }
+
+    /// Returns true if the given hash exists in this cache or any of its
+    /// fallbacks.
+    pub fn exists_sync(&self, sri: &Integrity) -> bool {
+        read::has_content(self.content_root(sri), sri).is_some()
+    }
+
+    /// Writes `data` to the cache synchronously, indexing it under `key`.
+    pub fn write_sync<K, D>(&self, key: K, data: D) -> Result<Integrity>
+    where
+        K: AsRef<str>,
+        D: AsRef<[u8]>,
+    {
+        use std::io::Write;
+        let (key, data) = (key.as_ref(), data.as_ref());
+        let mut writer = self
+            .write_opts()
+            .size(data.len() as u64)
+            .open_sync(&self.root, key)?;
+        writer.write_all(data).with_context(|| {
+            format!(
+                "Failed to write to cache data for key {key} for cache at {:?}",
+                self.root
+            )
+        })?;
+        writer.commit()
+    }
+
+    /// Writes `data` to the cache synchronously, skipping associating an
+    /// index key with it.
+    pub fn write_hash_sync<D: AsRef<[u8]>>(&self, data: D) -> Result<Integrity> {
+        use std::io::Write;
+        let data = data.as_ref();
+        let mut writer = self
+            .write_opts()
+            .size(data.len() as u64)
+            .open_hash_sync(&self.root)?;
+        writer.write_all(data).with_context(|| {
+            format!("Failed to write to cache data for cache at {:?}", self.root)
+        })?;
+        writer.commit()
+    }
+
+    /// Gets the metadata entry for a certain key synchronously.
+    pub fn metadata_sync<K: AsRef<str>>(&self, key: K) -> Result<Option<Metadata>> {
+        index::find_in(self.dir(), &self.key(key.as_ref()))
+    }
+
+    /// Removes an individual index entry synchronously. The associated
+    /// content will be left in the cache.
+    pub fn remove_sync<K: AsRef<str>>(&self, key: K) -> Result<()> {
+        index::delete_in(self.dir(), &self.key(key.as_ref()))
+    }
+
+    /// Returns a synchronous iterator that lists all entries in this cache's
+    /// own index. Keys are listed as they're stored, so with a key
+    /// normalizer, they're the normalized keys.
+    pub fn list_sync(&self) -> ListIter {
+        crate::ls::list_in(self.dir())
+    }
+
+    /// Root of the first cache, out of this one and its fallbacks, holding
+    /// content for `sri`. If none of them have it, this is this cache's root,
+    /// so errors about missing content point at it.
+    fn content_root(&self, sri: &Integrity) -> CacheDir<'_> {
+        self.dirs()
+            .find(|root| read::has_content(*root, sri).is_some())
+            .unwrap_or(self.dir())
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    async fn content_root_async(&self, sri: &Integrity) -> CacheDir<'_> {
+        for root in self.dirs() {
+            if read::has_content_async(root, sri).await.is_some() {
+                return root;
+            }
+        }
+        self.dir()
+    }
+
+    fn dir(&self) -> CacheDir<'_> {
+        CacheDir::new(&self.root, &self.layout)
+    }
+
+    /// This cache, followed by its fallbacks.
+    fn dirs(&self) -> impl Iterator<Item = CacheDir<'_>> {
+        std::iter::once(self.dir()).chain(
+            self.fallbacks
+                .iter()
+                .map(|(root, layout)| CacheDir::new(root, layout)),
+        )
+    }
+
+    fn key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match self.key_normalizer {
+            Some(normalize) => Cow::Owned(normalize(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
+    use async_attributes::test as async_test;
+    #[cfg(feature = "tokio")]
+    use tokio::test as async_test;
+
+    #[test]
+    fn round_trip_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = Cache::open(tmp.path().join("cache"))
+            .unwrap()
+            .algorithm(Algorithm::Xxh3)
+            .key_normalizer(|key| key.to_lowercase());
+        let sri = cache.write_sync("Hello", b"hello").unwrap();
+        assert_eq!(sri.pick_algorithm(), Algorithm::Xxh3);
+        assert_eq!(cache.read_sync("HELLO").unwrap(), b"hello");
+        assert_eq!(cache.read_hash_sync(&sri).unwrap(), b"hello");
+        assert_eq!(cache.metadata_sync("hello").unwrap().unwrap().key, "hello");
+        cache.remove_sync("hELLO").unwrap();
+        assert!(cache.metadata_sync("hello").unwrap().is_none());
+    }
+
+    #[test]
+    fn namespace_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = Cache::open(tmp.path()).unwrap();
+        let a = cache.namespace("tenant-a").unwrap();
+        let b = cache.namespace("tenant-b").unwrap();
+        let sri = a.write_sync("hello", b"hello").unwrap();
+        assert_eq!(b.write_sync("hello", b"hello").unwrap(), sri);
+
+        // Identical data is stored once per namespace, and not at all in the
+        // outer cache.
+        let a_path = crate::content_path(a.path(), &sri);
+        let b_path = crate::content_path(b.path(), &sri);
+        assert_ne!(a_path, b_path);
+        assert!(a_path.exists() && b_path.exists());
+        assert!(!cache.exists_sync(&sri));
+        assert!(cache.metadata_sync("hello").unwrap().is_none());
+        let private = a.write_hash_sync(b"only in a").unwrap();
+        assert!(b.read_hash_sync(&private).is_err());
+
+        // Removing content from one namespace doesn't affect the other.
+        crate::remove_hash_sync(a.path(), &sri).unwrap();
+        assert!(a.read_hash_sync(&sri).is_err());
+        assert!(a.read_sync("hello").is_err());
+        assert_eq!(b.read_sync("hello").unwrap(), b"hello");
+
+        assert!(cache.namespace("../escape").is_err());
+        assert!(cache.namespace("..").is_err());
+        assert!(cache.namespace("").is_err());
+    }
+
+    #[test]
+    fn fallback_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Cache::open(tmp.path().join("base")).unwrap();
+        let sri = base.write_sync("shared", b"hello").unwrap();
+        let overlay = Cache::open(tmp.path().join("overlay"))
+            .unwrap()
+            .fallback(base.path())
+            .unwrap();
+        index::insert(
+            overlay.path(),
+            "shared",
+            WriteOpts::new().integrity(sri.clone()),
+        )
+        .unwrap();
+
+        // Reads fall through to the base cache without copying its content.
+        assert_eq!(overlay.read_sync("shared").unwrap(), b"hello");
+        assert_eq!(overlay.read_hash_sync(&sri).unwrap(), b"hello");
+        assert!(overlay.exists_sync(&sri));
+        assert!(!crate::exists_sync(overlay.path(), &sri));
+
+        // Writes stay in the overlay.
+        let local = overlay.write_sync("local", b"world").unwrap();
+        assert!(crate::exists_sync(overlay.path(), &local));
+        assert!(!base.exists_sync(&local));
+        assert!(base.metadata_sync("local").unwrap().is_none());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn fallback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Cache::open(tmp.path().join("base")).unwrap();
+        let sri = base.write("shared", b"hello").await.unwrap();
+        let overlay = Cache::open(tmp.path().join("overlay"))
+            .unwrap()
+            .fallback(base.path())
+            .unwrap();
+        index::insert_async(
+            overlay.path(),
+            "shared",
+            WriteOpts::new().integrity(sri.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(overlay.read("shared").await.unwrap(), b"hello");
+        assert!(overlay.exists(&sri).await);
+        assert!(!crate::exists(overlay.path(), &sri).await);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = Cache::open(tmp.path())
+            .unwrap()
+            .key_normalizer(|key| key.to_lowercase());
+        cache.write("Hello", b"hello").await.unwrap();
+        assert_eq!(cache.read("HELLO").await.unwrap(), b"hello");
+        assert!(cache.metadata("hello").await.unwrap().is_some());
+        cache.remove("hELLO").await.unwrap();
+        assert!(cache.metadata("hello").await.unwrap().is_none());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn list_custom_layout() {
+        use futures::stream::TryStreamExt;
+        let tmp = tempfile::tempdir().unwrap();
+        CacheConfig::new()
+            .content_dir("blobs")
+            .index_dir("entries")
+            .apply(tmp.path())
+            .unwrap();
+        let cache = Cache::open(tmp.path()).unwrap();
+        cache.write("hello", b"hello").await.unwrap();
+        let entry = cache.list().try_next().await.unwrap().unwrap();
+        assert_eq!(entry.key, "hello");
+        assert_eq!(cache.read_hash(&entry.integrity).await.unwrap(), b"hello");
+        let listed = cache.list_sync().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(listed, [entry]);
+    }
+}
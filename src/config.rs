@@ -0,0 +1,366 @@
+//! Per-cache settings for where things are stored inside a cache directory.
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::content::path::CONTENT_VERSION;
+use crate::errors::{Error, IoErrorExt, Result};
+use crate::index::INDEX_VERSION;
+
+/// Name of the file a cache's `CacheConfig` is recorded in, at its root.
+pub(crate) const CONFIG_FILE: &str = "cacache-config.json";
+
+/// Overrides for the names of the directories a cache keeps its content and
+/// index in, which default to `content-v2` and `index-v5`. Useful for letting
+/// several layouts coexist under one root, or for pinning one.
+///
+/// The config is recorded in the cache directory itself and read by
+/// `Cache::open`, so every handle to the cache agrees on it. Reads, writes,
+/// and listings honour it when they go through a `Cache` handle: the free
+/// functions for those, like `cacache::write_sync` and `cacache::list_sync`,
+/// always use the default layout. Maintenance functions that work on a whole
+/// cache, like `GcOpts::gc_sync`, `stats_sync`, and `dedup_across_sync`,
+/// read the config themselves. It should be applied
+/// before the cache is first used, since changing it doesn't move anything
+/// that's already been written, and handles that are already open keep the
+/// layout they were opened with.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::CacheConfig::new()
+///         .content_dir("blobs")
+///         .index_dir("entries")
+///         .apply("./my-cache")?;
+///     let cache = cacache::Cache::open("./my-cache")?;
+///     cache.write_sync("my-key", b"hello")?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CacheConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index_dir: Option<String>,
+}
+
+impl CacheConfig {
+    /// Creates a config that uses the default layout.
+    pub fn new() -> CacheConfig {
+        Default::default()
+    }
+
+    /// Sets the name of the directory content is stored in.
+    pub fn content_dir<S: Into<String>>(mut self, name: S) -> Self {
+        self.content_dir = Some(name.into());
+        self
+    }
+
+    /// Sets the name of the directory the index is stored in.
+    pub fn index_dir<S: Into<String>>(mut self, name: S) -> Self {
+        self.index_dir = Some(name.into());
+        self
+    }
+
+    /// Reads the config recorded for `cache`, or the default one if it
+    /// doesn't have any. Fails if the recorded config can't be parsed or
+    /// names invalid directories.
+    pub fn load<P: AsRef<Path>>(cache: P) -> Result<CacheConfig> {
+        fn inner(cache: &Path) -> Result<CacheConfig> {
+            let path = cache.join(CONFIG_FILE);
+            match fs::read(&path) {
+                Ok(data) => {
+                    let config: CacheConfig = serde_json::from_slice(&data)
+                        .with_context(|| format!("Failed to parse cache config at {path:?}"))?;
+                    config.validate()?;
+                    Ok(config)
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(CacheConfig::new()),
+                Err(e) => {
+                    Err(e).with_context(|| format!("Failed to read cache config at {path:?}"))
+                }
+            }
+        }
+        inner(cache.as_ref())
+    }
+
+    /// Records this config for `cache`, creating the cache directory if it
+    /// doesn't exist yet.
+    ///
+    /// Directory names must be non-empty and made up only of ASCII letters,
+    /// digits, `-`, `_`, and `.`. They can't be `.`, `..`, `tmp`,
+    /// `namespaces`, or `cacache-config.json`, which the cache uses for
+    /// itself, and the content and index directories must differ.
+    pub fn apply<P: AsRef<Path>>(self, cache: P) -> Result<()> {
+        fn inner(config: CacheConfig, cache: &Path) -> Result<()> {
+            config.validate()?;
+            fs::create_dir_all(cache)
+                .with_context(|| format!("Failed to create cache directory at {cache:?}"))?;
+            let data = serde_json::to_vec(&config)
+                .with_context(|| "Failed to serialize cache config".into())?;
+            let path = cache.join(CONFIG_FILE);
+            let mut tmpfile = tempfile::NamedTempFile::new_in(cache)
+                .with_context(|| format!("Failed to create cache config at {path:?}"))?;
+            tmpfile
+                .write_all(&data)
+                .with_context(|| format!("Failed to write cache config at {path:?}"))?;
+            tmpfile
+                .persist(&path)
+                .map_err(|e| e.error)
+                .with_context(|| format!("Failed to write cache config at {path:?}"))?;
+            Ok(())
+        }
+        inner(self, cache.as_ref())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let layout = self.layout();
+        let (content, index) = (layout.content_name(), layout.index_name());
+        for name in [&content, &index] {
+            if !is_valid_dir_name(name) || matches!(&**name, "tmp" | "namespaces" | CONFIG_FILE) {
+                return Err(invalid_name(format!(
+                    "Invalid cache directory name {name:?}"
+                )));
+            }
+        }
+        if content == index {
+            return Err(invalid_name(format!(
+                "Cache content and index can't share the directory {content:?}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn layout(&self) -> Layout {
+        Layout {
+            content: self.content_dir.clone(),
+            index: self.index_dir.clone(),
+        }
+    }
+}
+
+/// Runs `f` on `cache` with the layout its recorded `CacheConfig` sets, for
+/// free functions that walk a whole cache and would otherwise miss its
+/// content or index.
+pub(crate) fn with_configured_dir<T>(
+    cache: &Path,
+    f: impl FnOnce(CacheDir<'_>) -> Result<T>,
+) -> Result<T> {
+    let layout = CacheConfig::load(cache)?.layout();
+    f(CacheDir::new(cache, &layout))
+}
+
+/// Whether `name` is safe to use as a single directory inside a cache.
+pub(crate) fn is_valid_dir_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn invalid_name(message: String) -> Error {
+    Error::IoError(
+        std::io::Error::new(ErrorKind::InvalidInput, "invalid cache directory name"),
+        message,
+    )
+}
+
+/// Names of the directories a cache keeps its content and index in, where
+/// `None` stands for the default, versioned, name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Layout {
+    content: Option<String>,
+    index: Option<String>,
+}
+
+impl Layout {
+    /// The layout of a cache without a config.
+    pub(crate) const DEFAULT: Layout = Layout {
+        content: None,
+        index: None,
+    };
+
+    fn content_name(&self) -> Cow<'_, str> {
+        match &self.content {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(format!("content-v{CONTENT_VERSION}")),
+        }
+    }
+
+    fn index_name(&self) -> Cow<'_, str> {
+        match &self.index {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(format!("index-v{INDEX_VERSION}")),
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::DEFAULT
+    }
+}
+
+/// A cache root along with the layout of its contents, so internals can
+/// find content and index files without having to look the layout up.
+/// Converting a plain path gives it the default layout.
+#[derive(Clone, Copy)]
+pub(crate) struct CacheDir<'a> {
+    root: &'a Path,
+    layout: &'a Layout,
+}
+
+static DEFAULT_LAYOUT: Layout = Layout::DEFAULT;
+
+impl<'a> CacheDir<'a> {
+    pub(crate) fn new(root: &'a Path, layout: &'a Layout) -> Self {
+        CacheDir { root, layout }
+    }
+
+    pub(crate) fn layout(&self) -> &'a Layout {
+        self.layout
+    }
+
+    /// Directory all content of this cache is stored under.
+    pub(crate) fn content_dir(&self) -> PathBuf {
+        self.root.join(&*self.layout.content_name())
+    }
+
+    /// Directory the index of this cache is stored under.
+    pub(crate) fn index_dir(&self) -> PathBuf {
+        self.root.join(&*self.layout.index_name())
+    }
+}
+
+impl<'a> From<&'a Path> for CacheDir<'a> {
+    fn from(root: &'a Path) -> Self {
+        CacheDir::new(root, &DEFAULT_LAYOUT)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for CacheDir<'a> {
+    fn from(root: &'a PathBuf) -> Self {
+        CacheDir::new(root, &DEFAULT_LAYOUT)
+    }
+}
+
+impl Deref for CacheDir<'_> {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.root
+    }
+}
+
+impl AsRef<Path> for CacheDir<'_> {
+    fn as_ref(&self) -> &Path {
+        self.root
+    }
+}
+
+impl fmt::Debug for CacheDir<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_layout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        let config = CacheConfig::new().content_dir("blobs").index_dir("entries");
+        config.clone().apply(&dir).unwrap();
+        assert_eq!(CacheConfig::load(&dir).unwrap(), config);
+
+        let cache = crate::Cache::open(&dir).unwrap();
+        let sri = cache.write_sync("hello", b"hello").unwrap();
+        assert_eq!(cache.read_sync("hello").unwrap(), b"hello");
+        assert_eq!(cache.read_hash_sync(&sri).unwrap(), b"hello");
+        assert!(cache.exists_sync(&sri));
+        assert!(dir.join("blobs").is_dir());
+        assert!(dir.join("entries").is_dir());
+        assert!(!dir.join("content-v2").exists());
+        assert!(!dir.join("index-v5").exists());
+        let listed = cache
+            .list_sync()
+            .map(|entry| entry.unwrap().key)
+            .collect::<Vec<_>>();
+        assert_eq!(listed, ["hello"]);
+
+        // Whole-cache maintenance finds the content and index too.
+        assert_eq!(crate::stats_sync(&dir).unwrap().entries, 1);
+        assert_eq!(crate::content_stats_sync(&dir).unwrap().len(), 1);
+        let stats = crate::GcOpts::new().gc_sync(&dir).unwrap();
+        assert_eq!(stats.removed_content, 0);
+        assert_eq!(
+            crate::VerifyOpts::new()
+                .verify_sync(&dir)
+                .unwrap()
+                .verified_content,
+            1
+        );
+        cache.remove_sync("hello").unwrap();
+        assert_eq!(
+            crate::GcOpts::new()
+                .grace_period(std::time::Duration::ZERO)
+                .gc_sync(&dir)
+                .unwrap()
+                .removed_content,
+            1
+        );
+        assert!(cache.metadata_sync("hello").unwrap().is_none());
+
+        // Clearing the cache keeps its layout.
+        crate::clear_sync(&dir).unwrap();
+        assert_eq!(CacheConfig::load(&dir).unwrap(), config);
+
+        assert!(CacheConfig::new().content_dir("../x").apply(&dir).is_err());
+        assert!(CacheConfig::new().index_dir("tmp").apply(&dir).is_err());
+        assert!(CacheConfig::new()
+            .index_dir("namespaces")
+            .apply(&dir)
+            .is_err());
+        assert!(CacheConfig::new()
+            .content_dir(CONFIG_FILE)
+            .apply(&dir)
+            .is_err());
+        assert!(CacheConfig::new()
+            .content_dir("same")
+            .index_dir("same")
+            .apply(&dir)
+            .is_err());
+    }
+
+    #[test]
+    fn bad_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(CONFIG_FILE), b"{not json").unwrap();
+        assert!(CacheConfig::load(&dir).is_err());
+        assert!(crate::Cache::open(&dir).is_err());
+
+        fs::write(dir.join(CONFIG_FILE), br#"{"content_dir":"../x"}"#).unwrap();
+        assert!(CacheConfig::load(&dir).is_err());
+        assert!(crate::Cache::open(&dir).is_err());
+    }
+
+    #[test]
+    fn default_layout() {
+        let root = Path::new("cache");
+        let dir = CacheDir::from(root);
+        assert_eq!(dir.content_dir(), root.join("content-v2"));
+        assert_eq!(dir.index_dir(), root.join("index-v5"));
+        assert_eq!(CacheConfig::new().layout(), Layout::DEFAULT);
+    }
+}
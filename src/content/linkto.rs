@@ -2,13 +2,14 @@ use ssri::{Algorithm, Integrity, IntegrityOpts};
 use std::fs::DirBuilder;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::pin::Pin;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::task::{Context, Poll};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::AsyncRead;
+use crate::config::{CacheDir, Layout};
 use crate::content::path;
 use crate::errors::{IoErrorExt, Result};
 
@@ -32,8 +33,8 @@ where
     }
 }
 
-fn create_symlink(sri: Integrity, cache: &PathBuf, target: &PathBuf) -> Result<Integrity> {
-    let cpath = path::content_path(cache.as_ref(), &sri);
+fn create_symlink(sri: Integrity, cache: CacheDir<'_>, target: &Path) -> Result<Integrity> {
+    let cpath = path::content_path(cache, &sri);
     DirBuilder::new()
         .recursive(true)
         // Safe unwrap. cpath always has multiple segments
@@ -69,6 +70,8 @@ pub struct ToLinker {
     target: PathBuf,
     /// The path to the root of the cache directory.
     cache: PathBuf,
+    /// The layout of the cache directory.
+    layout: Layout,
     /// The file descriptor to the target file.
     fd: File,
     /// The integrity builder for calculating the target file's integrity.
@@ -76,12 +79,13 @@ pub struct ToLinker {
 }
 
 impl ToLinker {
-    pub fn new(cache: &Path, algo: Algorithm, target: &Path) -> Result<Self> {
+    pub fn new(cache: CacheDir<'_>, algo: Algorithm, target: &Path) -> Result<Self> {
         let file = File::open(target)
             .with_context(|| format!("Failed to open reader to {}", target.display()))?;
         Ok(Self {
             target: target.to_path_buf(),
             cache: cache.to_path_buf(),
+            layout: cache.layout().clone(),
             fd: file,
             builder: IntegrityOpts::new().algorithm(algo),
         })
@@ -89,7 +93,8 @@ impl ToLinker {
 
     /// Add the symlink to the target file from the cache.
     pub fn commit(self) -> Result<Integrity> {
-        create_symlink(self.builder.result(), &self.cache, &self.target)
+        let cache = CacheDir::new(&self.cache, &self.layout);
+        create_symlink(self.builder.result(), cache, &self.target)
     }
 }
 
@@ -106,21 +111,23 @@ impl std::io::Read for ToLinker {
 /// An `AsyncRead`-like type that calculates the integrity of a file as it is
 /// read. When the linker is committed, a symlink is created from the cache to
 /// the target file using the integrity computed from the file's contents.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub struct AsyncToLinker {
     /// The path to the target file that will be symlinked from the cache.
     target: PathBuf,
     /// The path to the root of the cache directory.
     cache: PathBuf,
+    /// The layout of the cache directory.
+    layout: Layout,
     /// The async-enabled file descriptor to the target file.
     fd: crate::async_lib::File,
     /// The integrity builder for calculating the target file's integrity.
     builder: IntegrityOpts,
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncRead for AsyncToLinker {
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -148,15 +155,16 @@ impl AsyncRead for AsyncToLinker {
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncToLinker {
-    pub async fn new(cache: &Path, algo: Algorithm, target: &Path) -> Result<Self> {
+    pub async fn new(cache: CacheDir<'_>, algo: Algorithm, target: &Path) -> Result<Self> {
         let file = crate::async_lib::File::open(target)
             .await
             .with_context(|| format!("Failed to open reader to {}", target.display()))?;
         Ok(Self {
             target: target.to_path_buf(),
             cache: cache.to_path_buf(),
+            layout: cache.layout().clone(),
             fd: file,
             builder: IntegrityOpts::new().algorithm(algo),
         })
@@ -164,7 +172,8 @@ impl AsyncToLinker {
 
     /// Add the symlink to the target file from the cache.
     pub async fn commit(self) -> Result<Integrity> {
-        create_symlink(self.builder.result(), &self.cache, &self.target)
+        let cache = CacheDir::new(&self.cache, &self.layout);
+        create_symlink(self.builder.result(), cache, &self.target)
     }
 }
 
@@ -174,12 +183,14 @@ mod tests {
 
     use super::*;
 
-    #[cfg(feature = "async-std")]
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use async_attributes::test as async_test;
     #[cfg(feature = "tokio")]
     use tokio::test as async_test;
 
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use futures::io::AsyncReadExt;
     #[cfg(feature = "tokio")]
     use tokio::io::AsyncReadExt;
@@ -187,7 +198,7 @@ mod tests {
     fn create_tmpfile(tmp: &tempfile::TempDir, buf: &[u8]) -> PathBuf {
         let dir = tmp.path().to_owned();
         let target = dir.join("target-file");
-        std::fs::create_dir_all(&target.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
         let mut file = File::create(&target).unwrap();
         file.write_all(buf).unwrap();
         file.flush().unwrap();
@@ -201,7 +212,7 @@ mod tests {
 
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let mut linker = ToLinker::new(&dir, Algorithm::Sha256, &target).unwrap();
+        let mut linker = ToLinker::new(dir.as_path().into(), Algorithm::Sha256, &target).unwrap();
 
         // read all of the data from the linker, which will calculate the integrity
         // hash.
@@ -214,7 +225,7 @@ mod tests {
         let sri = linker.commit().unwrap();
         assert_eq!(sri.to_string(), Integrity::from(b"hello world").to_string());
 
-        let cpath = path::content_path(&dir, &sri);
+        let cpath = path::content_path(dir.as_path().into(), &sri);
         assert!(cpath.exists());
         let metadata = std::fs::symlink_metadata(&cpath).unwrap();
         let file_type = metadata.file_type();
@@ -222,7 +233,7 @@ mod tests {
         assert_eq!(std::fs::read(cpath).unwrap(), b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn basic_async_link() {
         let tmp = tempfile::tempdir().unwrap();
@@ -230,7 +241,7 @@ mod tests {
 
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let mut linker = AsyncToLinker::new(&dir, Algorithm::Sha256, &target)
+        let mut linker = AsyncToLinker::new(dir.as_path().into(), Algorithm::Sha256, &target)
             .await
             .unwrap();
 
@@ -247,7 +258,7 @@ mod tests {
         let sri = linker.commit().await.unwrap();
         assert_eq!(sri.to_string(), Integrity::from(b"hello world").to_string());
 
-        let cpath = path::content_path(&dir, &sri);
+        let cpath = path::content_path(dir.as_path().into(), &sri);
         assert!(cpath.exists());
         let metadata = std::fs::symlink_metadata(&cpath).unwrap();
         let file_type = metadata.file_type();
@@ -1,6 +1,7 @@
 pub mod path;
 pub mod read;
 pub mod rm;
+pub mod tmpfile;
 pub mod write;
 
 #[cfg(feature = "link_to")]
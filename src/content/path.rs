@@ -1,18 +1,19 @@
 use ssri::Integrity;
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 
-const CONTENT_VERSION: &str = "2";
+use crate::config::CacheDir;
+
+pub(crate) const CONTENT_VERSION: &str = "2";
 
 // Current format of content file path:
 //
 // sha512-BaSE64Hex= ->
 // ~/.my-cache/content-v2/sha512/ba/da/55deadbeefc0ffee
 //
-pub fn content_path(cache: &Path, sri: &Integrity) -> PathBuf {
-    let mut path = PathBuf::new();
+pub fn content_path(cache: CacheDir<'_>, sri: &Integrity) -> PathBuf {
+    let mut path = content_dir(cache);
     let (algo, hex) = sri.to_hex();
-    path.push(cache);
-    path.push(format!("content-v{CONTENT_VERSION}"));
     path.push(algo.to_string());
     path.push(&hex[0..2]);
     path.push(&hex[2..4]);
@@ -20,6 +21,54 @@ pub fn content_path(cache: &Path, sri: &Integrity) -> PathBuf {
     path
 }
 
+/// Narrows `sri` down to the hash its content is actually stored under.
+/// Content is only ever written under a single algorithm, but an `Integrity`
+/// recorded in the index may carry extra hashes of the same data, so each one
+/// is tried in order and the first whose content is present wins.
+/// Single-hash integrities are returned as they are, without touching the
+/// filesystem.
+pub fn stored_integrity<'a>(cache: CacheDir<'_>, sri: &'a Integrity) -> Cow<'a, Integrity> {
+    if sri.hashes.len() < 2 {
+        return Cow::Borrowed(sri);
+    }
+    sri.hashes
+        .iter()
+        .map(|hash| Integrity {
+            hashes: vec![hash.clone()],
+        })
+        .find(|single| content_path(cache, single).exists())
+        .map_or(Cow::Borrowed(sri), Cow::Owned)
+}
+
+/// Like [`stored_integrity`], but takes ownership of `sri` so the common
+/// single-hash case is handed back without being cloned.
+pub fn into_stored_integrity(cache: CacheDir<'_>, sri: Integrity) -> Integrity {
+    match stored_integrity(cache, &sri) {
+        Cow::Owned(stored) => stored,
+        Cow::Borrowed(_) => sri,
+    }
+}
+
+/// Directory all content of the current format is stored under.
+pub fn content_dir(cache: CacheDir<'_>) -> PathBuf {
+    cache.content_dir()
+}
+
+/// Recovers the integrity of a content file from its path, if it is laid out
+/// like one produced by [`content_path`].
+pub fn path_integrity(path: &Path) -> Option<Integrity> {
+    let rest = path.file_name()?.to_str()?;
+    let yy = path.parent()?;
+    let xx = yy.parent()?;
+    let algo = xx.parent()?.file_name()?.to_str()?.parse().ok()?;
+    let hex = format!(
+        "{}{}{rest}",
+        xx.file_name()?.to_str()?,
+        yy.file_name()?.to_str()?
+    );
+    Integrity::from_hex(hex, algo).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -29,7 +78,7 @@ mod tests {
     #[test]
     fn basic_test() {
         let sri = Integrity::from(b"hello world");
-        let cpath = content_path(Path::new("~/.my-cache"), &sri);
+        let cpath = content_path(Path::new("~/.my-cache").into(), &sri);
         let mut wanted = PathBuf::new();
         wanted.push("~/.my-cache");
         wanted.push(format!("content-v{CONTENT_VERSION}"));
@@ -38,5 +87,6 @@ mod tests {
         wanted.push("4d");
         wanted.push("27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
         assert_eq!(cpath.to_str().unwrap(), wanted.to_str().unwrap());
+        assert_eq!(path_integrity(&cpath), Some(sri));
     }
 }
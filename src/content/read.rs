@@ -1,49 +1,110 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, File};
-use std::io::Read;
-use std::path::Path;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::pin::Pin;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::sync::{Mutex, OnceLock};
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::task::{Context, Poll};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::AsyncReadExt;
 
-use ssri::{Algorithm, Integrity, IntegrityChecker};
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use ssri::IntegrityChecker;
+use ssri::{Algorithm, Integrity, IntegrityOpts};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::AsyncRead;
+use crate::config::CacheDir;
 use crate::content::path;
-use crate::errors::{IoErrorExt, Result};
+use crate::errors::{Error, IntegrityErrorExt, IoErrorExt, Result};
 
 pub struct Reader {
     fd: File,
-    checker: IntegrityChecker,
+    cache: PathBuf,
+    // These make up an `IntegrityChecker`, which can't be cloned or built
+    // from a running hash. Keeping them apart lets the hash be saved and
+    // picked back up later.
+    sri: Integrity,
+    hasher: IntegrityOpts,
 }
 
 impl std::io::Read for Reader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let amt = self.fd.read(buf)?;
-        self.checker.input(&buf[..amt]);
+        self.hasher.input(&buf[..amt]);
         Ok(amt)
     }
 }
 
 impl Reader {
+    fn new(fd: File, cache: CacheDir<'_>, sri: Integrity) -> Reader {
+        Reader {
+            fd,
+            cache: cache.to_path_buf(),
+            hasher: IntegrityOpts::new().algorithm(sri.pick_algorithm()),
+            sri,
+        }
+    }
+
+    /// Reopens the content for `sri`, picking up from `position` with the
+    /// hash of everything before it in `hasher`.
+    pub fn resume(
+        cache: CacheDir<'_>,
+        sri: Integrity,
+        position: u64,
+        hasher: IntegrityOpts,
+    ) -> Result<Reader> {
+        let cpath = path::content_path(cache, &sri);
+        let mut fd = File::open(&cpath)
+            .with_context(|| format!("Failed to open reader to {}", cpath.display()))?;
+        fd.seek(SeekFrom::Start(position))
+            .with_context(|| format!("Failed to seek to {position} in {}", cpath.display()))?;
+        Ok(Reader {
+            hasher,
+            ..Reader::new(fd, cache, sri)
+        })
+    }
+
+    /// Integrity the content is checked against.
+    pub fn integrity(&self) -> &Integrity {
+        &self.sri
+    }
+
+    /// Hash of everything read so far.
+    pub fn hasher(&self) -> &IntegrityOpts {
+        &self.hasher
+    }
+
+    pub fn stream_position(&mut self) -> std::io::Result<u64> {
+        self.fd.stream_position()
+    }
+
     pub fn check(self) -> Result<Algorithm> {
-        Ok(self.checker.result()?)
+        // Same as `IntegrityChecker::result()`.
+        let actual = self.hasher.result();
+        match actual.matches(&self.sri) {
+            Some(algo) => Ok(algo),
+            None => Err(ssri::Error::IntegrityCheckError(self.sri, actual))
+                .with_location(&self.cache, None),
+        }
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub struct AsyncReader {
     fd: crate::async_lib::File,
+    cache: PathBuf,
+    algorithm: Algorithm,
     checker: IntegrityChecker,
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncRead for AsyncReader {
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -71,28 +132,49 @@ impl AsyncRead for AsyncReader {
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncReader {
+    /// The algorithm `check()` will verify the data with.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
     pub fn check(self) -> Result<Algorithm> {
-        Ok(self.checker.result()?)
+        self.checker.result().with_location(&self.cache, None)
     }
 }
 
-pub fn open(cache: &Path, sri: Integrity) -> Result<Reader> {
+pub fn open(cache: CacheDir<'_>, sri: Integrity) -> Result<Reader> {
+    let sri = path::into_stored_integrity(cache, sri);
     let cpath = path::content_path(cache, &sri);
-    Ok(Reader {
-        fd: File::open(cpath).with_context(|| {
-            format!(
-                "Failed to open reader to {}",
-                path::content_path(cache, &sri).display()
-            )
-        })?,
-        checker: IntegrityChecker::new(sri),
-    })
+    let fd = File::open(cpath).with_context(|| {
+        format!(
+            "Failed to open reader to {}",
+            path::content_path(cache, &sri).display()
+        )
+    })?;
+    Ok(Reader::new(fd, cache, sri))
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn open_async(cache: &Path, sri: Integrity) -> Result<AsyncReader> {
+/// Reads the content stored for `sri` and checks it against `check`, which
+/// may use a different algorithm than the one the content is stored under.
+pub fn check_with(cache: CacheDir<'_>, sri: &Integrity, check: Integrity) -> Result<Algorithm> {
+    let cpath = path::content_path(cache, &path::stored_integrity(cache, sri));
+    let fd = File::open(&cpath)
+        .with_context(|| format!("Failed to open reader to {}", cpath.display()))?;
+    let mut reader = Reader::new(fd, cache, check);
+    std::io::copy(&mut reader, &mut std::io::sink()).with_context(|| {
+        format!(
+            "Failed to read cache contents while verifying integrity for {}",
+            cpath.display()
+        )
+    })?;
+    reader.check()
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn open_async(cache: CacheDir<'_>, sri: Integrity) -> Result<AsyncReader> {
+    let sri = path::into_stored_integrity(cache, sri);
     let cpath = path::content_path(cache, &sri);
     Ok(AsyncReader {
         fd: crate::async_lib::File::open(cpath).await.with_context(|| {
@@ -101,11 +183,21 @@ pub async fn open_async(cache: &Path, sri: Integrity) -> Result<AsyncReader> {
                 path::content_path(cache, &sri).display()
             )
         })?,
+        cache: cache.to_path_buf(),
+        algorithm: sri.pick_algorithm(),
         checker: IntegrityChecker::new(sri),
     })
 }
 
-pub fn read(cache: &Path, sri: &Integrity) -> Result<Vec<u8>> {
+pub fn read(cache: CacheDir<'_>, sri: &Integrity) -> Result<Vec<u8>> {
+    read_sized(cache, sri, 0)
+}
+
+/// Same as `read()`, but fails with `Error::SizeMismatch` instead of an
+/// integrity error if fewer than `size` bytes are found, which means the
+/// content was only partially written.
+pub fn read_sized(cache: CacheDir<'_>, sri: &Integrity, size: u64) -> Result<Vec<u8>> {
+    let sri = &path::stored_integrity(cache, sri);
     let cpath = path::content_path(cache, sri);
     let ret = fs::read(cpath).with_context(|| {
         format!(
@@ -113,12 +205,22 @@ pub fn read(cache: &Path, sri: &Integrity) -> Result<Vec<u8>> {
             path::content_path(cache, sri).display()
         )
     })?;
-    sri.check(&ret)?;
+    check_sized(cache, sri, size, &ret)?;
     Ok(ret)
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn read_async<'a>(cache: &'a Path, sri: &'a Integrity) -> Result<Vec<u8>> {
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_async<'a>(cache: CacheDir<'a>, sri: &'a Integrity) -> Result<Vec<u8>> {
+    read_sized_async(cache, sri, 0).await
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_sized_async<'a>(
+    cache: CacheDir<'a>,
+    sri: &'a Integrity,
+    size: u64,
+) -> Result<Vec<u8>> {
+    let sri = &path::stored_integrity(cache, sri);
     let cpath = path::content_path(cache, sri);
     let ret = crate::async_lib::read(&cpath).await.with_context(|| {
         format!(
@@ -126,11 +228,51 @@ pub async fn read_async<'a>(cache: &'a Path, sri: &'a Integrity) -> Result<Vec<u
             path::content_path(cache, sri).display()
         )
     })?;
-    sri.check(&ret)?;
+    check_sized(cache, sri, size, &ret)?;
     Ok(ret)
 }
 
-pub fn reflink_unchecked(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+fn check_sized(cache: CacheDir<'_>, sri: &Integrity, size: u64, data: &[u8]) -> Result<()> {
+    let actual = data.len() as u64;
+    if actual < size {
+        return Err(Error::SizeMismatch(size, actual));
+    }
+    sri.check(data).with_location(&cache, None)?;
+    Ok(())
+}
+
+pub fn read_limited(cache: CacheDir<'_>, sri: &Integrity, max_bytes: u64) -> Result<Vec<u8>> {
+    let sri = &path::stored_integrity(cache, sri);
+    let cpath = path::content_path(cache, sri);
+    let len = fs::metadata(&cpath)
+        .with_context(|| format!("Failed to stat contents for file at {}", cpath.display()))?
+        .len();
+    if len > max_bytes {
+        return Err(Error::ContentTooLarge(max_bytes, len));
+    }
+    read(cache, sri)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_limited_async<'a>(
+    cache: CacheDir<'a>,
+    sri: &'a Integrity,
+    max_bytes: u64,
+) -> Result<Vec<u8>> {
+    let sri = &path::stored_integrity(cache, sri);
+    let cpath = path::content_path(cache, sri);
+    let len = crate::async_lib::metadata(&cpath)
+        .await
+        .with_context(|| format!("Failed to stat contents for file at {}", cpath.display()))?
+        .len();
+    if len > max_bytes {
+        return Err(Error::ContentTooLarge(max_bytes, len));
+    }
+    read_async(cache, sri).await
+}
+
+pub fn reflink_unchecked(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<()> {
+    let sri = &path::stored_integrity(cache, sri);
     let cpath = path::content_path(cache, sri);
     reflink_copy::reflink(cpath, to).with_context(|| {
         format!(
@@ -142,9 +284,13 @@ pub fn reflink_unchecked(cache: &Path, sri: &Integrity, to: &Path) -> Result<()>
     Ok(())
 }
 
-pub fn reflink(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+/// Reads through the content for `sri` to verify it, returning its size and
+/// the algorithm it was verified with. If `size` is known (non-zero), content
+/// shorter than that is reported as a size mismatch.
+fn verify(cache: CacheDir<'_>, sri: &Integrity, size: u64) -> Result<(u64, Algorithm)> {
     let mut reader = open(cache, sri.clone())?;
-    let mut buf: [u8; 1024] = [0; 1024];
+    let mut buf = [0u8; 1024 * 8];
+    let mut read_total = 0u64;
     loop {
         let read = reader.read(&mut buf).with_context(|| {
             format!(
@@ -155,15 +301,20 @@ pub fn reflink(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
         if read == 0 {
             break;
         }
+        read_total += read as u64;
     }
-    reader.check()?;
-    reflink_unchecked(cache, sri, to)
+    if read_total < size {
+        return Err(Error::SizeMismatch(size, read_total));
+    }
+    let algo = reader.check()?;
+    Ok((read_total, algo))
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn reflink_async(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn verify_async(cache: CacheDir<'_>, sri: &Integrity, size: u64) -> Result<(u64, Algorithm)> {
     let mut reader = open_async(cache, sri.clone()).await?;
     let mut buf = [0u8; 1024 * 8];
+    let mut read_total = 0u64;
     loop {
         let read = AsyncReadExt::read(&mut reader, &mut buf)
             .await
@@ -176,12 +327,74 @@ pub async fn reflink_async(cache: &Path, sri: &Integrity, to: &Path) -> Result<(
         if read == 0 {
             break;
         }
+        read_total += read as u64;
     }
-    reader.check()?;
+    if read_total < size {
+        return Err(Error::SizeMismatch(size, read_total));
+    }
+    let algo = reader.check()?;
+    Ok((read_total, algo))
+}
+
+/// Reads through the content for `sri` without keeping any of it, returning
+/// the algorithm it was verified with.
+pub fn check(cache: CacheDir<'_>, sri: &Integrity) -> Result<Algorithm> {
+    verify(cache, sri, 0).map(|(_, algo)| algo)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn check_async(cache: CacheDir<'_>, sri: &Integrity) -> Result<Algorithm> {
+    verify_async(cache, sri, 0).await.map(|(_, algo)| algo)
+}
+
+/// Reads through the content for `sri`, returning whether it matches `sri`.
+/// Failing to read it at all is still an error.
+pub fn is_intact(cache: CacheDir<'_>, sri: &Integrity) -> Result<bool> {
+    match verify(cache, sri, 0) {
+        Ok(_) => Ok(true),
+        Err(Error::ReadIntegrityMismatch(..)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn is_intact_async(cache: CacheDir<'_>, sri: &Integrity) -> Result<bool> {
+    match verify_async(cache, sri, 0).await {
+        Ok(_) => Ok(true),
+        Err(Error::ReadIntegrityMismatch(..)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn reflink(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<()> {
+    reflink_sized(cache, sri, to, 0)
+}
+
+/// Like `reflink()`, but also fails if the content is shorter than the
+/// `size` its index entry recorded.
+pub fn reflink_sized(cache: CacheDir<'_>, sri: &Integrity, to: &Path, size: u64) -> Result<()> {
+    verify(cache, sri, size)?;
     reflink_unchecked(cache, sri, to)
 }
 
-pub fn copy_unchecked(cache: &Path, sri: &Integrity, to: &Path) -> Result<u64> {
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn reflink_async(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<()> {
+    reflink_sized_async(cache, sri, to, 0).await
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn reflink_sized_async(
+    cache: CacheDir<'_>,
+    sri: &Integrity,
+    to: &Path,
+    size: u64,
+) -> Result<()> {
+    verify_async(cache, sri, size).await?;
+    reflink_unchecked(cache, sri, to)
+}
+
+pub fn copy_unchecked(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<u64> {
+    let sri = &path::stored_integrity(cache, sri);
     let cpath = path::content_path(cache, sri);
     std::fs::copy(cpath, to).with_context(|| {
         format!(
@@ -192,34 +405,47 @@ pub fn copy_unchecked(cache: &Path, sri: &Integrity, to: &Path) -> Result<u64> {
     })
 }
 
-pub fn copy(cache: &Path, sri: &Integrity, to: &Path) -> Result<u64> {
-    let mut reader = open(cache, sri.clone())?;
-    let mut buf: [u8; 1024] = [0; 1024];
-    let mut size = 0;
-    loop {
-        let read = reader.read(&mut buf).with_context(|| {
-            format!(
-                "Failed to read cache contents while verifying integrity for {}",
-                path::content_path(cache, sri).display()
-            )
-        })?;
-        size += read;
-        if read == 0 {
-            break;
-        }
-    }
-    reader.check()?;
+pub fn copy(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<u64> {
+    copy_sized(cache, sri, to, 0)
+}
+
+/// Like `copy()`, but also fails if the content is shorter than the `size`
+/// its index entry recorded.
+pub fn copy_sized(cache: CacheDir<'_>, sri: &Integrity, to: &Path, size: u64) -> Result<u64> {
+    let (size, _) = verify(cache, sri, size)?;
     copy_unchecked(cache, sri, to)?;
+    Ok(size)
+}
 
-    Ok(size as u64)
+/// Reads content into memory once, verifies it, and writes it out to `to`,
+/// returning the data. Nothing is written if verification fails.
+pub fn extract(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<Vec<u8>> {
+    let mut reader = open(cache, sri.clone())?;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).with_context(|| {
+        format!(
+            "Failed to read cache contents while verifying integrity for {}",
+            path::content_path(cache, sri).display()
+        )
+    })?;
+    reader.check()?;
+    fs::write(to, &data).with_context(|| {
+        format!(
+            "Failed to write cache contents from {} to {}",
+            path::content_path(cache, sri).display(),
+            to.display()
+        )
+    })?;
+    Ok(data)
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn copy_unchecked_async<'a>(
-    cache: &'a Path,
+    cache: CacheDir<'a>,
     sri: &'a Integrity,
     to: &'a Path,
 ) -> Result<u64> {
+    let sri = &path::stored_integrity(cache, sri);
     let cpath = path::content_path(cache, sri);
     crate::async_lib::copy(&cpath, to).await.with_context(|| {
         format!(
@@ -230,31 +456,158 @@ pub async fn copy_unchecked_async<'a>(
     })
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn copy_async<'a>(cache: &'a Path, sri: &'a Integrity, to: &'a Path) -> Result<u64> {
-    let mut reader = open_async(cache, sri.clone()).await?;
-    let mut buf: [u8; 1024] = [0; 1024];
-    let mut size = 0;
-    loop {
-        let read = AsyncReadExt::read(&mut reader, &mut buf)
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn copy_async<'a>(cache: CacheDir<'a>, sri: &'a Integrity, to: &'a Path) -> Result<u64> {
+    copy_sized_async(cache, sri, to, 0).await
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn copy_sized_async(
+    cache: CacheDir<'_>,
+    sri: &Integrity,
+    to: &Path,
+    size: u64,
+) -> Result<u64> {
+    let (size, _) = verify_async(cache, sri, size).await?;
+    copy_unchecked_async(cache, sri, to).await?;
+    Ok(size)
+}
+
+/// A temporary location next to `to`, where a destination file can be
+/// fully written before being renamed into place. Anything left behind in it
+/// is removed when it's dropped.
+pub struct Staged {
+    dir: tempfile::TempDir,
+    path: PathBuf,
+}
+
+impl Staged {
+    pub fn new(to: &Path) -> Result<Staged> {
+        let parent = match to.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dir = tempfile::Builder::new()
+            .prefix(".cacache-")
+            .tempdir_in(parent)
+            .with_context(|| {
+                format!(
+                    "Failed to create staging directory for {} in {}",
+                    to.display(),
+                    parent.display()
+                )
+            })?;
+        let path = dir.path().join("staged");
+        Ok(Staged { dir, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn persist(self, to: &Path) -> Result<()> {
+        fs::rename(&self.path, to).with_context(|| {
+            format!(
+                "Failed to move staged file {} into place at {}",
+                self.path.display(),
+                to.display()
+            )
+        })?;
+        drop(self.dir);
+        Ok(())
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn persist_async(self, to: &Path) -> Result<()> {
+        crate::async_lib::rename(&self.path, to)
             .await
             .with_context(|| {
                 format!(
-                    "Failed to read cache contents while verifying integrity for {}",
-                    path::content_path(cache, sri).display()
+                    "Failed to move staged file {} into place at {}",
+                    self.path.display(),
+                    to.display()
                 )
             })?;
-        size += read;
-        if read == 0 {
-            break;
-        }
+        drop(self.dir);
+        Ok(())
     }
-    reader.check()?;
-    copy_unchecked_async(cache, sri, to).await?;
-    Ok(size as u64)
 }
 
-pub fn hard_link_unchecked(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+pub fn copy_atomic(
+    cache: CacheDir<'_>,
+    sri: &Integrity,
+    to: &Path,
+    size: u64,
+    mode: Option<u32>,
+) -> Result<u64> {
+    let staged = Staged::new(to)?;
+    let size = copy_sized(cache, sri, staged.path(), size)?;
+    set_mode(staged.path(), mode)?;
+    staged.persist(to)?;
+    Ok(size)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn copy_atomic_async<'a>(
+    cache: CacheDir<'a>,
+    sri: &'a Integrity,
+    to: &'a Path,
+    size: u64,
+    mode: Option<u32>,
+) -> Result<u64> {
+    let staged = Staged::new(to)?;
+    let size = copy_sized_async(cache, sri, staged.path(), size).await?;
+    set_mode(staged.path(), mode)?;
+    staged.persist_async(to).await?;
+    Ok(size)
+}
+
+pub fn reflink_atomic(
+    cache: CacheDir<'_>,
+    sri: &Integrity,
+    to: &Path,
+    size: u64,
+    mode: Option<u32>,
+) -> Result<()> {
+    let staged = Staged::new(to)?;
+    reflink_sized(cache, sri, staged.path(), size)?;
+    set_mode(staged.path(), mode)?;
+    staged.persist(to)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn reflink_atomic_async(
+    cache: CacheDir<'_>,
+    sri: &Integrity,
+    to: &Path,
+    size: u64,
+    mode: Option<u32>,
+) -> Result<()> {
+    let staged = Staged::new(to)?;
+    reflink_sized_async(cache, sri, staged.path(), size).await?;
+    set_mode(staged.path(), mode)?;
+    staged.persist_async(to).await
+}
+
+/// Sets the permission bits of a file copied out of the cache, if asked to.
+#[cfg(unix)]
+pub fn set_mode(to: &Path, mode: Option<u32>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match mode {
+        Some(mode) => fs::set_permissions(to, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set permissions of {}", to.display())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_: &Path, _: Option<u32>) -> Result<()> {
+    Ok(())
+}
+
+pub fn hard_link_unchecked(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<()> {
+    let sri = &path::stored_integrity(cache, sri);
     let cpath = path::content_path(cache, sri);
     std::fs::hard_link(cpath, to).with_context(|| {
         format!(
@@ -266,7 +619,7 @@ pub fn hard_link_unchecked(cache: &Path, sri: &Integrity, to: &Path) -> Result<(
     Ok(())
 }
 
-pub fn hard_link(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+pub fn hard_link(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<()> {
     hard_link_unchecked(cache, sri, to)?;
     let mut reader = open(cache, sri.clone())?;
     let mut buf = [0u8; 1024 * 8];
@@ -285,8 +638,8 @@ pub fn hard_link(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
     Ok(())
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn hard_link_async(cache: &Path, sri: &Integrity, to: &Path) -> Result<()> {
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn hard_link_async(cache: CacheDir<'_>, sri: &Integrity, to: &Path) -> Result<()> {
     let mut reader = open_async(cache, sri.clone()).await?;
     let mut buf = [0u8; 1024 * 8];
     loop {
@@ -307,19 +660,159 @@ pub async fn hard_link_async(cache: &Path, sri: &Integrity, to: &Path) -> Result
     Ok(())
 }
 
-pub fn has_content(cache: &Path, sri: &Integrity) -> Option<Integrity> {
-    if path::content_path(cache, sri).exists() {
+pub fn supports_reflink(cache: &Path) -> bool {
+    static PROBED: OnceLock<Mutex<HashMap<PathBuf, bool>>> = OnceLock::new();
+    let probed = PROBED.get_or_init(Default::default);
+    if let Some(supported) = probed.lock().unwrap().get(cache) {
+        return *supported;
+    }
+    let supported = probe_reflink(cache).is_ok();
+    probed
+        .lock()
+        .unwrap()
+        .insert(cache.to_path_buf(), supported);
+    supported
+}
+
+fn probe_reflink(cache: &Path) -> std::io::Result<()> {
+    let tmp_path = cache.join("tmp");
+    fs::create_dir_all(&tmp_path)?;
+    // The probe directory and everything in it is removed when dropped.
+    let probe = tempfile::tempdir_in(tmp_path)?;
+    let src = probe.path().join("src");
+    fs::write(&src, b"cacache")?;
+    reflink_copy::reflink(&src, probe.path().join("dest"))
+}
+
+pub fn has_content(cache: CacheDir<'_>, sri: &Integrity) -> Option<Integrity> {
+    if path::content_path(cache, &path::stored_integrity(cache, sri)).exists() {
         Some(sri.clone())
     } else {
         None
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn has_content_async(cache: &Path, sri: &Integrity) -> Option<Integrity> {
-    if crate::async_lib::metadata(path::content_path(cache, sri))
+/// Returns the size of the content for `sri`, or `None` if there isn't any.
+pub fn content_size(cache: CacheDir<'_>, sri: &Integrity) -> Result<Option<u64>> {
+    if has_content(cache, sri).is_none() {
+        return Ok(None);
+    }
+    let cpath = path::content_path(cache, &path::stored_integrity(cache, sri));
+    let meta = fs::metadata(&cpath)
+        .with_context(|| format!("Failed to stat content at {}", cpath.display()))?;
+    Ok(Some(meta.len()))
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn content_size_async(cache: CacheDir<'_>, sri: &Integrity) -> Result<Option<u64>> {
+    if has_content_async(cache, sri).await.is_none() {
+        return Ok(None);
+    }
+    let cpath = path::content_path(cache, &path::stored_integrity(cache, sri));
+    let meta = crate::async_lib::metadata(&cpath)
         .await
-        .is_ok()
+        .with_context(|| format!("Failed to stat content at {}", cpath.display()))?;
+    Ok(Some(meta.len()))
+}
+
+/// Checks which of `sris` have content in the cache. Content directories
+/// that enough of them would live in are listed once, and each is looked up
+/// in the listing, instead of being checked with a `stat()` each.
+pub fn has_content_many(cache: CacheDir<'_>, sris: &[Integrity]) -> Vec<bool> {
+    // Every hash an integrity carries could be the one its content is
+    // stored under, so all of them are candidates.
+    let candidates = sris
+        .iter()
+        .map(|sri| {
+            sri.hashes
+                .iter()
+                .map(|hash| {
+                    path::content_path(
+                        cache,
+                        &Integrity {
+                            hashes: vec![hash.clone()],
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    // Keyed by the raw path, since hashing a `Path` parses its components.
+    let mut probes = HashMap::<&OsStr, usize>::new();
+    for cpath in candidates.iter().flatten() {
+        if let Some(dir) = cpath.parent() {
+            *probes.entry(dir.as_os_str()).or_default() += 1;
+        }
+    }
+    let mut listings = HashMap::<&OsStr, HashSet<OsString>>::new();
+    candidates
+        .iter()
+        .map(|cpaths| {
+            cpaths
+                .iter()
+                .any(|cpath| match (cpath.parent(), cpath.file_name()) {
+                    (Some(dir), Some(name)) if probes[dir.as_os_str()] >= MIN_LISTED_PROBES => {
+                        listings
+                            .entry(dir.as_os_str())
+                            .or_insert_with(|| list_dir(dir))
+                            .contains(name)
+                    }
+                    _ => cpath.exists(),
+                })
+        })
+        .collect()
+}
+
+/// Listing a directory costs a few syscalls of its own, so it's only worth
+/// it over individual `stat()`s when at least this many entries are probed.
+const MIN_LISTED_PROBES: usize = 8;
+
+fn list_dir(dir: &Path) -> HashSet<OsString> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Content files up to this size are re-hashed by `has_valid_content()`.
+const REHASH_MAX_SIZE: u64 = 1024 * 1024;
+
+pub fn has_valid_content(cache: CacheDir<'_>, sri: &Integrity) -> bool {
+    has_valid_sized_content(cache, sri, 0)
+}
+
+/// Like `has_valid_content()`, but also rejects content whose length isn't
+/// `size`, as recorded by the index entry pointing at it. A `size` of 0 means
+/// it isn't known, same as for `read_sized()`.
+pub fn has_valid_sized_content(cache: CacheDir<'_>, sri: &Integrity, size: u64) -> bool {
+    let sri = &path::stored_integrity(cache, sri);
+    let len = match fs::metadata(path::content_path(cache, sri)) {
+        Ok(meta) if meta.is_file() => meta.len(),
+        _ => return false,
+    };
+    if size != 0 && len != size {
+        false
+    } else if len == 0 {
+        // Probably left behind by a crash, unless this really is empty data.
+        sri.check(b"").is_ok()
+    } else if len <= REHASH_MAX_SIZE {
+        read(cache, sri).is_ok()
+    } else {
+        true
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn has_content_async(cache: CacheDir<'_>, sri: &Integrity) -> Option<Integrity> {
+    if crate::async_lib::metadata(path::content_path(
+        cache,
+        &path::stored_integrity(cache, sri),
+    ))
+    .await
+    .is_ok()
     {
         Some(sri.clone())
     } else {
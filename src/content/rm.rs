@@ -1,12 +1,13 @@
 use std::fs;
-use std::path::Path;
 
 use ssri::Integrity;
 
+use crate::config::CacheDir;
 use crate::content::path;
 use crate::errors::{IoErrorExt, Result};
 
-pub fn rm(cache: &Path, sri: &Integrity) -> Result<()> {
+pub fn rm(cache: CacheDir<'_>, sri: &Integrity) -> Result<()> {
+    let sri = &path::stored_integrity(cache, sri);
     fs::remove_file(path::content_path(cache, sri)).with_context(|| {
         format!(
             "Failed to remove cache file {}",
@@ -16,8 +17,9 @@ pub fn rm(cache: &Path, sri: &Integrity) -> Result<()> {
     Ok(())
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-pub async fn rm_async(cache: &Path, sri: &Integrity) -> Result<()> {
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn rm_async(cache: CacheDir<'_>, sri: &Integrity) -> Result<()> {
+    let sri = &path::stored_integrity(cache, sri);
     crate::async_lib::remove_file(path::content_path(cache, sri))
         .await
         .with_context(|| {
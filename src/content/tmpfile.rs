@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+
+use tempfile::NamedTempFile;
+
+/// A temp file that content gets written into before it's moved into its
+/// final place in the cache.
+///
+/// On Linux, this is an anonymous `O_TMPFILE` file when the filesystem
+/// supports them. Those never show up in the cache's `tmp` directory, so a
+/// crash mid-write leaves nothing behind to clean up, and they're linked
+/// straight into place once they're done. Everywhere else, and on kernels or
+/// filesystems without `O_TMPFILE`, a regular named temp file is used.
+pub enum TempFile {
+    Named(NamedTempFile),
+    #[cfg(target_os = "linux")]
+    Anonymous {
+        file: File,
+        dir: PathBuf,
+    },
+}
+
+impl TempFile {
+    /// Creates a new temp file inside `dir`, which must be on the same
+    /// filesystem as the cache's content.
+    pub fn new_in(dir: &Path) -> io::Result<TempFile> {
+        #[cfg(target_os = "linux")]
+        if let Ok(file) = open_anonymous(dir) {
+            return Ok(TempFile::Anonymous {
+                file,
+                dir: dir.to_path_buf(),
+            });
+        }
+        NamedTempFile::new_in(dir).map(TempFile::Named)
+    }
+
+    pub fn as_file(&self) -> &File {
+        match self {
+            TempFile::Named(tmpfile) => tmpfile.as_file(),
+            #[cfg(target_os = "linux")]
+            TempFile::Anonymous { file, .. } => file,
+        }
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut File {
+        match self {
+            TempFile::Named(tmpfile) => tmpfile.as_file_mut(),
+            #[cfg(target_os = "linux")]
+            TempFile::Anonymous { file, .. } => file,
+        }
+    }
+
+    /// Where the temp file lives, for error messages. Anonymous temp files
+    /// don't have a path of their own, so this is their directory instead.
+    pub fn path(&self) -> &Path {
+        match self {
+            TempFile::Named(tmpfile) => tmpfile.path(),
+            #[cfg(target_os = "linux")]
+            TempFile::Anonymous { dir, .. } => dir,
+        }
+    }
+
+    /// Deletes the temp file, returning any error from removing it. Anonymous
+    /// temp files have nothing on disk to remove.
+    pub fn close(self) -> io::Result<()> {
+        match self {
+            TempFile::Named(tmpfile) => tmpfile.close(),
+            #[cfg(target_os = "linux")]
+            TempFile::Anonymous { .. } => Ok(()),
+        }
+    }
+
+    /// Moves the temp file to `path`. Named temp files replace anything
+    /// that's already there, while anonymous ones fail to link over it. On
+    /// failure, the temp file is handed back so it can be tried again.
+    pub fn persist(self, path: &Path) -> Result<(), (io::Error, TempFile)> {
+        match self {
+            TempFile::Named(tmpfile) => tmpfile
+                .persist(path)
+                .map(|_| ())
+                .map_err(|e| (e.error, TempFile::Named(e.file))),
+            #[cfg(target_os = "linux")]
+            TempFile::Anonymous { mut file, dir } => match link_anonymous(&file, path) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    Err((e, TempFile::Anonymous { file, dir }))
+                }
+                // Linking can still fail if /proc isn't available, so copy
+                // the data over to a named temp file and go through that.
+                Err(_) => match copy_to_named(&mut file, &dir) {
+                    Ok(tmpfile) => TempFile::Named(tmpfile).persist(path),
+                    Err(e) => Err((e, TempFile::Anonymous { file, dir })),
+                },
+            },
+        }
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.as_file_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.as_file_mut().flush()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_anonymous(dir: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .mode(0o600)
+        .custom_flags(libc::O_TMPFILE)
+        .open(dir)
+}
+
+#[cfg(target_os = "linux")]
+fn link_anonymous(file: &File, path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::ffi::OsStrExt;
+
+    let fd = file.as_raw_fd();
+    let dest = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `fd` is open for as long as `file` is borrowed, and both paths
+    // are NUL-terminated strings that outlive the call.
+    let linked = unsafe {
+        libc::linkat(
+            fd,
+            b"\0".as_ptr().cast(),
+            libc::AT_FDCWD,
+            dest.as_ptr(),
+            libc::AT_EMPTY_PATH,
+        )
+    };
+    if linked == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::AlreadyExists {
+        return Err(err);
+    }
+    // AT_EMPTY_PATH needs CAP_DAC_READ_SEARCH. Linking through /proc works
+    // without it.
+    let src = CString::new(format!("/proc/self/fd/{fd}"))?;
+    // SAFETY: both paths are NUL-terminated strings that outlive the call.
+    let linked = unsafe {
+        libc::linkat(
+            libc::AT_FDCWD,
+            src.as_ptr(),
+            libc::AT_FDCWD,
+            dest.as_ptr(),
+            libc::AT_SYMLINK_FOLLOW,
+        )
+    };
+    if linked == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn copy_to_named(file: &mut File, dir: &Path) -> io::Result<NamedTempFile> {
+    let mut tmpfile = NamedTempFile::new_in(dir)?;
+    tmpfile
+        .as_file()
+        .set_permissions(file.metadata()?.permissions())?;
+    file.rewind()?;
+    io::copy(file, &mut tmpfile)?;
+    Ok(tmpfile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persist_temp_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut tmpfile = TempFile::new_in(&dir).unwrap();
+        #[cfg(target_os = "linux")]
+        if matches!(tmpfile, TempFile::Anonymous { .. }) {
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        }
+        tmpfile.write_all(b"hello").unwrap();
+        let dest = dir.join("dest");
+        assert!(tmpfile.persist(&dest).is_ok());
+        assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+
+        let mut tmpfile = TempFile::new_in(&dir).unwrap();
+        tmpfile.write_all(b"world").unwrap();
+        match tmpfile.persist(&dest) {
+            Ok(()) => assert_eq!(std::fs::read(&dest).unwrap(), b"world"),
+            // Anonymous temp files don't replace existing files.
+            Err((e, _)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn anonymous_copy_fallback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut file = match open_anonymous(&dir) {
+            Ok(file) => file,
+            // Not supported here, so there's nothing to fall back from.
+            Err(_) => return,
+        };
+        file.write_all(b"hello").unwrap();
+        let tmpfile = copy_to_named(&mut file, &dir).unwrap();
+        tmpfile.persist(dir.join("dest")).unwrap();
+        assert_eq!(std::fs::read(dir.join("dest")).unwrap(), b"hello");
+    }
+}
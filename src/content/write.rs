@@ -1,25 +1,27 @@
 use std::fs::DirBuilder;
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::pin::Pin;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::sync::Mutex;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::task::{Context, Poll};
+use std::time::Duration;
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use futures::prelude::*;
 #[cfg(feature = "mmap")]
 use memmap2::MmapMut;
 use ssri::{Algorithm, Integrity, IntegrityOpts};
-use tempfile::NamedTempFile;
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::{AsyncWrite, JoinHandle};
-use crate::content::path;
+use crate::config::{CacheDir, Layout};
+use crate::content::tmpfile::TempFile;
+use crate::content::{path, read};
 use crate::errors::{IoErrorExt, Result};
-use crate::Error;
 
 #[cfg(feature = "mmap")]
 pub const MAX_MMAP_SIZE: usize = 1024 * 1024;
@@ -33,21 +35,77 @@ impl MmapMut {
     fn flush_async(&self) -> std::io::Result<()> {
         panic!()
     }
+}
 
-    fn copy_from_slice(&self, _: &[u8]) {
-        panic!()
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Computes the integrity of written data, or skips hashing entirely when
+/// the caller has vouched for the integrity up front.
+enum Hasher {
+    /// Hashes data with the algorithm it's stored under, plus any extra
+    /// algorithms that should be recorded alongside it.
+    Compute(IntegrityOpts, Algorithm),
+    Trusted(Integrity),
+}
+
+impl Hasher {
+    fn input(&mut self, data: &[u8]) {
+        if let Hasher::Compute(builder, _) = self {
+            builder.input(data);
+        }
+    }
+
+    fn add_algorithm(&mut self, algo: Algorithm) {
+        if let Hasher::Compute(builder, _) = self {
+            *builder = std::mem::take(builder).algorithm(algo);
+        }
+    }
+
+    /// Returns the full integrity of the written data, along with the
+    /// single-hash integrity its content should be stored under.
+    fn result(self) -> (Integrity, Integrity) {
+        match self {
+            Hasher::Compute(builder, algo) => {
+                let sri = builder.result();
+                let stored = Integrity {
+                    hashes: sri
+                        .hashes
+                        .iter()
+                        .filter(|hash| hash.algorithm == algo)
+                        .cloned()
+                        .collect(),
+                };
+                (sri, stored)
+            }
+            Hasher::Trusted(sri) => (sri.clone(), sri),
+        }
     }
 }
 
 pub struct Writer {
     cache: PathBuf,
-    builder: IntegrityOpts,
+    layout: Layout,
+    builder: Hasher,
     mmap: Option<MmapMut>,
-    tmpfile: NamedTempFile,
+    mmap_pos: usize,
+    tmpfile: BufWriter<TempFile>,
+    preallocated: bool,
+    verify_existing: bool,
 }
 
 impl Writer {
-    pub fn new(cache: &Path, algo: Algorithm, size: Option<usize>) -> Result<Writer> {
+    pub fn new(cache: CacheDir<'_>, algo: Algorithm, size: Option<usize>) -> Result<Writer> {
+        Self::with_buffer_size(cache, algo, size, DEFAULT_WRITE_BUFFER_SIZE)
+    }
+
+    /// Same as `new()`, but coalesces writes to the temp file into chunks of
+    /// up to `buffer_size` bytes. A `buffer_size` of 0 disables buffering.
+    pub fn with_buffer_size(
+        cache: CacheDir<'_>,
+        algo: Algorithm,
+        size: Option<usize>,
+        buffer_size: usize,
+    ) -> Result<Writer> {
         let cache_path = cache.to_path_buf();
         let mut tmp_path = cache_path.clone();
         tmp_path.push("tmp");
@@ -61,7 +119,7 @@ impl Writer {
                 )
             })?;
         let tmp_path_clone = tmp_path.clone();
-        let mut tmpfile = NamedTempFile::new_in(tmp_path).with_context(|| {
+        let mut tmpfile = TempFile::new_in(&tmp_path).with_context(|| {
             format!(
                 "Failed to create temp file while initializing a writer, inside {}",
                 tmp_path_clone.display()
@@ -70,15 +128,101 @@ impl Writer {
         let mmap = make_mmap(&mut tmpfile, size)?;
         Ok(Writer {
             cache: cache_path,
-            builder: IntegrityOpts::new().algorithm(algo),
-            tmpfile,
+            layout: cache.layout().clone(),
+            builder: Hasher::Compute(IntegrityOpts::new().algorithm(algo), algo),
+            tmpfile: BufWriter::with_capacity(buffer_size, tmpfile),
             mmap,
+            mmap_pos: 0,
+            preallocated: false,
+            verify_existing: false,
         })
     }
 
+    /// Also hashes written data with `algo`, if given, and includes that hash
+    /// in the returned integrity. The content is still stored under the hash
+    /// of the algorithm the writer was created with.
+    pub fn with_extra_algorithm(mut self, algo: Option<Algorithm>) -> Self {
+        if let Some(algo) = algo {
+            self.builder.add_algorithm(algo);
+        }
+        self
+    }
+
+    /// Skips hashing written data, and places it under `sri` instead, if
+    /// given. The data is never checked against `sri`.
+    pub fn with_trusted_integrity(mut self, sri: Option<Integrity>) -> Self {
+        if let Some(sri) = sri {
+            self.builder = Hasher::Trusted(sri);
+        }
+        self
+    }
+
+    /// Allocates `size` bytes for the temp file up front, if given, so large
+    /// sequential writes that don't go through a memory map end up less
+    /// fragmented on disk. The file is trimmed back down to what was actually
+    /// written when the writer is closed.
+    pub fn with_preallocation(mut self, size: Option<u64>) -> Result<Self> {
+        if let (None, Some(size)) = (&self.mmap, size) {
+            let tmpfile = self.tmpfile.get_ref();
+            allocate_file(tmpfile.as_file(), size).with_context(|| {
+                format!(
+                    "Failed to preallocate temp file at {}",
+                    tmpfile.path().display()
+                )
+            })?;
+            self.preallocated = true;
+        }
+        Ok(self)
+    }
+
+    /// Sets the permissions of the content file to `mode`, if given. Only
+    /// has an effect on unix.
+    pub fn with_mode(self, mode: Option<u32>) -> Result<Self> {
+        let tmpfile = self.tmpfile.get_ref();
+        set_mode(tmpfile.as_file(), mode).with_context(|| {
+            format!(
+                "Failed to set permissions of temp file at {}",
+                tmpfile.path().display()
+            )
+        })?;
+        Ok(self)
+    }
+
+    /// If set, content that's already in the cache under the same hash is
+    /// read back and checked before it's trusted, instead of being assumed to
+    /// be identical. Content that fails the check is replaced.
+    pub fn with_verify_existing(mut self, verify: bool) -> Self {
+        self.verify_existing = verify;
+        self
+    }
+
     pub fn close(self) -> Result<Integrity> {
-        let sri = self.builder.result();
-        let cpath = path::content_path(&self.cache, &sri);
+        self.close_detailed().map(|(sri, _)| sri)
+    }
+
+    /// Throws away everything written so far, deleting the temp file.
+    pub fn abort(mut self) -> Result<()> {
+        // Unmap before removing, which Windows won't do to a mapped file.
+        self.mmap = None;
+        // Whatever's still buffered is being thrown out anyway, so there's no
+        // point in flushing it.
+        let (tmpfile, _) = self.tmpfile.into_parts();
+        let tmp_path = tmpfile.path().to_path_buf();
+        tmpfile
+            .close()
+            .with_context(|| format!("Failed to remove temp file in {}", tmp_path.display()))
+    }
+
+    /// Same as `close()`, but also returns whether identical content was
+    /// already present in the cache.
+    pub fn close_detailed(self) -> Result<(Integrity, bool)> {
+        let (sri, stored) = self.builder.result();
+        let cache = CacheDir::new(&self.cache, &self.layout);
+        let cpath = path::content_path(cache, &stored);
+        if already_stored(cache, &stored, &cpath, self.verify_existing)? {
+            // Dropping the tempfile cleans it up.
+            return Ok((sri, true));
+        }
         DirBuilder::new()
             .recursive(true)
             // Safe unwrap. cpath always has multiple segments
@@ -86,40 +230,56 @@ impl Writer {
             .with_context(|| {
                 format!(
                     "Failed to create destination directory for cache contents, at {}",
-                    path::content_path(&self.cache, &sri)
+                    path::content_path(cache, &stored)
                         .parent()
                         .unwrap()
                         .display()
                 )
             })?;
-        let res = self.tmpfile.persist(&cpath);
+        let mut tmpfile = self
+            .tmpfile
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .with_context(|| "Failed to flush buffered cache contents".into())?;
+        if self.preallocated {
+            trim_preallocated(tmpfile.as_file_mut()).with_context(|| {
+                format!(
+                    "Failed to trim preallocated temp file at {}",
+                    tmpfile.path().display()
+                )
+            })?;
+        }
+        let mut deduplicated = false;
+        let res = persist(tmpfile, &cpath);
         match res {
             Ok(_) => {}
             Err(e) => {
+                deduplicated = true;
                 // We might run into conflicts sometimes when persisting files.
                 // This is ok. We can deal. Let's just make sure the destination
                 // file actually exists, and we can move on.
-                if !cpath.exists() {
-                    return Err(e.error).with_context(|| {
+                if !cpath.exists() || (self.verify_existing && !read::is_intact(cache, &stored)?) {
+                    return Err(e).with_context(|| {
                         format!(
                             "Failed to persist cache contents while closing writer, at {}",
-                            path::content_path(&self.cache, &sri).display()
+                            path::content_path(cache, &stored).display()
                         )
                     })?;
                 }
             }
         }
-        Ok(sri)
+        Ok((sri, deduplicated))
     }
 }
 
 impl Write for Writer {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.builder.input(buf);
         if let Some(mmap) = &mut self.mmap {
-            mmap.copy_from_slice(buf);
-            Ok(buf.len())
+            let n = write_mmap(mmap, &mut self.mmap_pos, buf)?;
+            self.builder.input(buf);
+            Ok(n)
         } else {
+            self.builder.input(buf);
             self.tmpfile.write(buf)
         }
     }
@@ -129,36 +289,53 @@ impl Write for Writer {
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+/// Async counterpart to [`Writer`]. The state is kept behind a `Mutex` so
+/// that the writer stays `Send + Sync` even while a blocking write is in
+/// flight, letting it move freely between runtime worker threads.
+///
+/// Blocking operations own the writer's state while they run, and hand it
+/// back when they're done. If the writer is dropped in the meantime, the
+/// state is dropped along with the finished operation's result instead, which
+/// removes the temp file.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub struct AsyncWriter(Mutex<State>);
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 enum State {
     Idle(Option<Inner>),
     Busy(JoinHandle<State>),
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 struct Inner {
     cache: PathBuf,
-    builder: IntegrityOpts,
-    tmpfile: NamedTempFile,
+    // Boxed to keep `State::Idle` from growing much bigger than `Busy`.
+    layout: Box<Layout>,
+    builder: Hasher,
+    tmpfile: TempFile,
     mmap: Option<MmapMut>,
+    mmap_pos: usize,
+    preallocated: bool,
+    verify_existing: bool,
     buf: Vec<u8>,
     last_op: Option<Operation>,
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 enum Operation {
     Write(std::io::Result<usize>),
     Flush(std::io::Result<()>),
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncWriter {
     #[allow(clippy::new_ret_no_self)]
     #[allow(clippy::needless_lifetimes)]
-    pub async fn new(cache: &Path, algo: Algorithm, size: Option<usize>) -> Result<AsyncWriter> {
+    pub async fn new(
+        cache: CacheDir<'_>,
+        algo: Algorithm,
+        size: Option<usize>,
+    ) -> Result<AsyncWriter> {
         let cache_path = cache.to_path_buf();
         let mut tmp_path = cache_path.clone();
         tmp_path.push("tmp");
@@ -173,27 +350,133 @@ impl AsyncWriter {
                 )
             })?;
 
-        match crate::async_lib::create_named_tempfile(tmp_path).await {
-            Some(tmpfile) => {
-                let mut tmpfile = tmpfile?;
-                let mmap = make_mmap(&mut tmpfile, size)?;
-                Ok(AsyncWriter(Mutex::new(State::Idle(Some(Inner {
-                    cache: cache_path,
-                    builder: IntegrityOpts::new().algorithm(algo),
-                    mmap,
-                    tmpfile,
-                    buf: vec![],
-                    last_op: None,
-                })))))
+        let mut tmpfile = crate::async_lib::run_blocking(move || {
+            TempFile::new_in(&tmp_path)
+                .with_context(|| format!("Failed to create a temp file at {}", tmp_path.display()))
+        })
+        .await?;
+        let mmap = make_mmap(&mut tmpfile, size)?;
+        Ok(AsyncWriter(Mutex::new(State::Idle(Some(Inner {
+            cache: cache_path,
+            layout: Box::new(cache.layout().clone()),
+            builder: Hasher::Compute(IntegrityOpts::new().algorithm(algo), algo),
+            mmap,
+            mmap_pos: 0,
+            tmpfile,
+            preallocated: false,
+            verify_existing: false,
+            buf: vec![],
+            last_op: None,
+        })))))
+    }
+
+    /// Also hashes written data with `algo`, if given, and includes that hash
+    /// in the returned integrity. The content is still stored under the hash
+    /// of the algorithm the writer was created with.
+    pub fn with_extra_algorithm(self, algo: Option<Algorithm>) -> Self {
+        if let Some(algo) = algo {
+            if let State::Idle(Some(inner)) = &mut *self.0.lock().unwrap() {
+                inner.builder.add_algorithm(algo);
+            }
+        }
+        self
+    }
+
+    /// Skips hashing written data, and places it under `sri` instead, if
+    /// given. The data is never checked against `sri`.
+    pub fn with_trusted_integrity(self, sri: Option<Integrity>) -> Self {
+        if let Some(sri) = sri {
+            if let State::Idle(Some(inner)) = &mut *self.0.lock().unwrap() {
+                inner.builder = Hasher::Trusted(sri);
             }
-            _ => Err(Error::IoError(
-                std::io::Error::new(std::io::ErrorKind::Other, "temp file create error"),
-                "Possible memory issues for file handle".into(),
-            )),
         }
+        self
+    }
+
+    /// Allocates `size` bytes for the temp file up front, if given, so large
+    /// sequential writes that don't go through a memory map end up less
+    /// fragmented on disk. The file is trimmed back down to what was actually
+    /// written when the writer is closed.
+    pub fn with_preallocation(self, size: Option<u64>) -> Result<Self> {
+        if let State::Idle(Some(inner)) = &mut *self.0.lock().unwrap() {
+            if let (None, Some(size)) = (&inner.mmap, size) {
+                allocate_file(inner.tmpfile.as_file(), size).with_context(|| {
+                    format!(
+                        "Failed to preallocate temp file at {}",
+                        inner.tmpfile.path().display()
+                    )
+                })?;
+                inner.preallocated = true;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Sets the permissions of the content file to `mode`, if given. Only
+    /// has an effect on unix.
+    pub fn with_mode(self, mode: Option<u32>) -> Result<Self> {
+        if let State::Idle(Some(inner)) = &mut *self.0.lock().unwrap() {
+            set_mode(inner.tmpfile.as_file(), mode).with_context(|| {
+                format!(
+                    "Failed to set permissions of temp file at {}",
+                    inner.tmpfile.path().display()
+                )
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// If set, content that's already in the cache under the same hash is
+    /// read back and checked before it's trusted, instead of being assumed to
+    /// be identical. Content that fails the check is replaced.
+    pub fn with_verify_existing(self, verify: bool) -> Self {
+        if let State::Idle(Some(inner)) = &mut *self.0.lock().unwrap() {
+            inner.verify_existing = verify;
+        }
+        self
     }
 
     pub async fn close(self) -> Result<Integrity> {
+        self.close_detailed().await.map(|(sri, _)| sri)
+    }
+
+    /// Throws away everything written so far, deleting the temp file once
+    /// any write that's still in flight is done.
+    pub async fn abort(self) -> Result<()> {
+        let inner = futures::future::poll_fn(|cx| {
+            let state = &mut *self.0.lock().unwrap();
+            loop {
+                match state {
+                    State::Idle(opt) => return Poll::Ready(opt.take()),
+                    State::Busy(task) => {
+                        let next_state = crate::async_lib::unwrap_joinhandle_value(
+                            futures::ready!(Pin::new(task).poll(cx)),
+                        );
+                        update_state(state, next_state);
+                    }
+                }
+            }
+        })
+        .await;
+        match inner {
+            Some(mut inner) => {
+                inner.mmap = None;
+                let tmpfile = inner.tmpfile;
+                crate::async_lib::run_blocking(move || {
+                    let tmp_path = tmpfile.path().to_path_buf();
+                    tmpfile.close().with_context(|| {
+                        format!("Failed to remove temp file in {}", tmp_path.display())
+                    })
+                })
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Same as `close()`, but also returns whether identical content was
+    /// already present in the cache.
+    pub async fn close_detailed(self) -> Result<(Integrity, bool)> {
         // NOTE: How do I even get access to `inner` safely???
         // let inner = ???;
         // Blocking, but should be a very fast op.
@@ -206,12 +489,43 @@ impl AsyncWriter {
                         None => return Poll::Ready(None),
                         Some(inner) => {
                             let (s, r) = futures::channel::oneshot::channel();
-                            let tmpfile = inner.tmpfile;
-                            let sri = inner.builder.result();
-                            let cpath = path::content_path(&inner.cache, &sri);
+                            let mut tmpfile = inner.tmpfile;
+                            let preallocated = inner.preallocated;
+                            let verify_existing = inner.verify_existing;
+                            let (sri, stored) = inner.builder.result();
+                            let (cache, layout) = (inner.cache, inner.layout);
+                            let cpath = path::content_path(CacheDir::new(&cache, &layout), &stored);
 
                             // Start the operation asynchronously.
-                            *state = State::Busy(crate::async_lib::spawn_blocking(|| {
+                            *state = State::Busy(crate::async_lib::spawn_blocking(move || {
+                                let cache = CacheDir::new(&cache, &layout);
+                                match already_stored(cache, &stored, &cpath, verify_existing) {
+                                    Ok(true) => {
+                                        // Already there. Just let the tempfile go.
+                                        drop(tmpfile);
+                                        let _ = s.send(Ok((sri, true)));
+                                        return State::Idle(None);
+                                    }
+                                    Ok(false) => {}
+                                    Err(e) => {
+                                        let _ = s.send(Err(e));
+                                        return State::Idle(None);
+                                    }
+                                }
+                                let trimmed = if preallocated {
+                                    trim_preallocated(tmpfile.as_file_mut()).with_context(|| {
+                                        format!(
+                                            "trimming preallocated file {} failed",
+                                            tmpfile.path().display()
+                                        )
+                                    })
+                                } else {
+                                    Ok(())
+                                };
+                                if trimmed.is_err() {
+                                    let _ = s.send(trimmed.map(|_| (sri, false)));
+                                    return State::Idle(None);
+                                }
                                 let res = std::fs::DirBuilder::new()
                                     .recursive(true)
                                     // Safe unwrap. cpath always has multiple segments
@@ -223,30 +537,36 @@ impl AsyncWriter {
                                         )
                                     });
                                 if res.is_err() {
-                                    let _ = s.send(res.map(|_| sri));
+                                    let _ = s.send(res.map(|_| (sri, false)));
                                 } else {
-                                    let res = tmpfile
-                                        .persist(&cpath)
-                                        .map_err(|e| e.error)
-                                        .with_context(|| {
-                                            format!("persisting file {} failed", cpath.display())
-                                        });
+                                    let res = persist(tmpfile, &cpath).with_context(|| {
+                                        format!("persisting file {} failed", cpath.display())
+                                    });
                                     if res.is_err() {
                                         // We might run into conflicts
                                         // sometimes when persisting files.
                                         // This is ok. We can deal. Let's just
                                         // make sure the destination file
-                                        // actually exists, and we can move
-                                        // on.
+                                        // actually exists (and, if asked,
+                                        // holds the right data), and we can
+                                        // move on.
                                         let _ = s.send(
-                                            std::fs::metadata(cpath)
+                                            std::fs::metadata(&cpath)
                                                 .with_context(|| {
                                                     String::from("File still doesn't exist")
                                                 })
-                                                .map(|_| sri),
+                                                .and_then(|_| {
+                                                    if !verify_existing
+                                                        || read::is_intact(cache, &stored)?
+                                                    {
+                                                        Ok((sri, true))
+                                                    } else {
+                                                        res.map(|_| (sri, false))
+                                                    }
+                                                }),
                                         );
                                     } else {
-                                        let _ = s.send(res.map(|_| sri));
+                                        let _ = s.send(res.map(|_| (sri, false)));
                                     }
                                 }
                                 State::Idle(None)
@@ -275,7 +595,7 @@ impl AsyncWriter {
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncWrite for AsyncWriter {
     fn poll_write(
         self: Pin<&mut Self>,
@@ -320,12 +640,15 @@ impl AsyncWrite for AsyncWriter {
 
                                 // Start the operation asynchronously.
                                 *state = State::Busy(crate::async_lib::spawn_blocking(|| {
-                                    inner.builder.input(&inner.buf);
                                     if let Some(mmap) = &mut inner.mmap {
-                                        mmap.copy_from_slice(&inner.buf);
-                                        inner.last_op = Some(Operation::Write(Ok(inner.buf.len())));
+                                        let res = write_mmap(mmap, &mut inner.mmap_pos, &inner.buf);
+                                        if res.is_ok() {
+                                            inner.builder.input(&inner.buf);
+                                        }
+                                        inner.last_op = Some(Operation::Write(res));
                                         State::Idle(Some(inner))
                                     } else {
+                                        inner.builder.input(&inner.buf);
                                         let res = inner.tmpfile.write(&inner.buf);
                                         inner.last_op = Some(Operation::Write(res));
                                         State::Idle(Some(inner))
@@ -398,7 +721,7 @@ impl AsyncWrite for AsyncWriter {
         }
     }
 
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
         self.poll_close_impl(cx)
     }
@@ -425,13 +748,13 @@ fn update_state(
     }
 }
 
-#[cfg(not(feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "async-fs"))]
 /// Update the state.
 fn update_state(current_state: &mut State, next_state: State) {
     *current_state = next_state;
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncWriter {
     #[inline]
     fn poll_close_impl(
@@ -473,10 +796,119 @@ impl AsyncWriter {
     }
 }
 
+/// How many more times persisting a temp file is tried after it fails in a
+/// way that's likely to be temporary.
+const PERSIST_RETRIES: u32 = 5;
+
+/// How long to wait before the first retry. Each retry waits twice as long
+/// as the previous one.
+const PERSIST_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Whether content for `sri` is already stored at `cpath`. Content is
+/// immutable by hash, so an existing file is normally trusted to hold exactly
+/// this data. With `verify`, it's read back first, and removed if it turns out
+/// not to, so it can be replaced. Failing to read it back is an error, rather
+/// than a reason to replace it.
+fn already_stored(
+    cache: CacheDir<'_>,
+    sri: &Integrity,
+    cpath: &Path,
+    verify: bool,
+) -> Result<bool> {
+    if !cpath.exists() {
+        return Ok(false);
+    }
+    if !verify || read::is_intact(cache, sri)? {
+        return Ok(true);
+    }
+    match std::fs::remove_file(cpath) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).with_context(|| {
+            format!(
+                "Failed to remove corrupted cache contents at {}",
+                cpath.display()
+            )
+        }),
+        _ => Ok(false),
+    }
+}
+
+/// Moves `tmpfile` to `cpath`. On Windows, antivirus software and indexers
+/// can briefly hold newly written files open, making the rename fail with
+/// access denied, so those failures are retried after a short wait.
+fn persist(tmpfile: TempFile, cpath: &Path) -> std::io::Result<()> {
+    let mut tmpfile = Some(tmpfile);
+    retry_persist(is_file_locked, std::thread::sleep, || {
+        // Safe unwrap. A failed attempt always puts the temp file back.
+        match tmpfile.take().unwrap().persist(cpath) {
+            Ok(()) => Ok(()),
+            Err((e, file)) => {
+                tmpfile = Some(file);
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Runs `attempt`, retrying it with a backoff for as long as it fails with
+/// errors `retryable` accepts, up to `PERSIST_RETRIES` times.
+fn retry_persist<T>(
+    retryable: impl Fn(&std::io::Error) -> bool,
+    mut sleep: impl FnMut(Duration),
+    mut attempt: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut delay = PERSIST_RETRY_DELAY;
+    for _ in 0..PERSIST_RETRIES {
+        match attempt() {
+            Err(e) if retryable(&e) => {
+                sleep(delay);
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+    attempt()
+}
+
+#[cfg(windows)]
+fn is_file_locked(err: &std::io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    err.kind() == std::io::ErrorKind::PermissionDenied
+        || err.raw_os_error() == Some(ERROR_SHARING_VIOLATION)
+}
+
+#[cfg(not(windows))]
+fn is_file_locked(_: &std::io::Error) -> bool {
+    false
+}
+
+/// Copies `buf` into `mmap` at `pos`, and moves `pos` past it. Data can come
+/// in over any number of writes, but can't go past the size the temp file was
+/// mapped with.
 #[cfg(feature = "mmap")]
-fn make_mmap(tmpfile: &mut NamedTempFile, size: Option<usize>) -> Result<Option<MmapMut>> {
+fn write_mmap(mmap: &mut MmapMut, pos: &mut usize, buf: &[u8]) -> std::io::Result<usize> {
+    let end = pos
+        .checked_add(buf.len())
+        .filter(|end| *end <= mmap.len())
+        .ok_or_else(|| {
+            crate::errors::io_error(format!(
+                "Tried to write past the expected size of {} bytes",
+                mmap.len()
+            ))
+        })?;
+    mmap[*pos..end].copy_from_slice(buf);
+    *pos = end;
+    Ok(buf.len())
+}
+
+#[cfg(not(feature = "mmap"))]
+fn write_mmap(_: &mut MmapMut, _: &mut usize, _: &[u8]) -> std::io::Result<usize> {
+    panic!()
+}
+
+#[cfg(feature = "mmap")]
+fn make_mmap(tmpfile: &mut TempFile, size: Option<usize>) -> Result<Option<MmapMut>> {
     if let Some(size @ 0..=MAX_MMAP_SIZE) = size {
-        allocate_file(tmpfile.as_file(), size).with_context(|| {
+        allocate_file(tmpfile.as_file(), size as u64).with_context(|| {
             format!(
                 "Failed to configure file length for temp file at {}",
                 tmpfile.path().display()
@@ -488,9 +920,8 @@ fn make_mmap(tmpfile: &mut NamedTempFile, size: Option<usize>) -> Result<Option<
     }
 }
 
-#[cfg(feature = "mmap")]
-#[cfg(target_os = "linux")]
-fn allocate_file(file: &std::fs::File, size: usize) -> std::io::Result<()> {
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+fn allocate_file(file: &std::fs::File, size: u64) -> std::io::Result<()> {
     use std::io::{Error, ErrorKind};
     use std::os::fd::AsRawFd;
 
@@ -508,57 +939,251 @@ fn allocate_file(file: &std::fs::File, size: usize) -> std::io::Result<()> {
     }
 }
 
-#[cfg(feature = "mmap")]
-#[cfg(not(target_os = "linux"))]
-fn allocate_file(file: &std::fs::File, size: usize) -> std::io::Result<()> {
-    file.set_len(size as u64)
+#[cfg(not(all(feature = "mmap", target_os = "linux")))]
+fn allocate_file(file: &std::fs::File, size: u64) -> std::io::Result<()> {
+    file.set_len(size)
+}
+
+#[cfg(unix)]
+fn set_mode(file: &std::fs::File, mode: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match mode {
+        Some(mode) => file.set_permissions(std::fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &std::fs::File, _: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Returns how many bytes unprivileged users can still write to the
+/// filesystem `path` is on, or `None` if this platform can't tell. If `path`
+/// doesn't exist yet, its closest existing ancestor is asked instead.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> std::io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = match path.ancestors().find(|dir| dir.exists()) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a NUL-terminated string that outlives the call, and
+    // `stat` points to space for exactly one `statvfs` for it to fill in.
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: statvfs returned 0, so it initialized all of `stat`.
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::useless_conversion)]
+    let available = u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize));
+    Ok(Some(available))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_: &Path) -> std::io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Trims a preallocated file down to what was actually written to it.
+fn trim_preallocated(file: &mut std::fs::File) -> std::io::Result<()> {
+    let written = file.stream_position()?;
+    file.set_len(written)
 }
 
 #[cfg(not(feature = "mmap"))]
-fn make_mmap(_: &mut NamedTempFile, _: Option<usize>) -> Result<Option<MmapMut>> {
+fn make_mmap(_: &mut TempFile, _: Option<usize>) -> Result<Option<MmapMut>> {
     Ok(None)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     use crate::async_lib::AsyncWriteExt;
     use tempfile;
 
-    #[cfg(feature = "async-std")]
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use async_attributes::test as async_test;
     #[cfg(feature = "tokio")]
     use tokio::test as async_test;
 
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    static_assertions::assert_impl_all!(AsyncWriter: Send, Sync);
+
+    #[test]
+    fn persist_retries_locked_files() {
+        use std::io::{Error, ErrorKind};
+        let locked = |e: &Error| e.kind() == ErrorKind::PermissionDenied;
+
+        let mut sleeps = Vec::new();
+        let mut failures = 2;
+        let res = retry_persist(
+            locked,
+            |delay| sleeps.push(delay),
+            || {
+                if failures > 0 {
+                    failures -= 1;
+                    Err(Error::from(ErrorKind::PermissionDenied))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+        assert!(res.is_ok());
+        assert_eq!(sleeps, [PERSIST_RETRY_DELAY, PERSIST_RETRY_DELAY * 2]);
+
+        let mut attempts = 0;
+        let res = retry_persist(
+            locked,
+            |_| {},
+            || -> std::io::Result<()> {
+                attempts += 1;
+                Err(Error::from(ErrorKind::PermissionDenied))
+            },
+        );
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(attempts, PERSIST_RETRIES + 1);
+
+        let mut attempts = 0;
+        let res = retry_persist(
+            locked,
+            |_| {},
+            || -> std::io::Result<()> {
+                attempts += 1;
+                Err(Error::from(ErrorKind::NotFound))
+            },
+        );
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn buffered_small_writes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let mut writer =
+            Writer::with_buffer_size(dir.as_path().into(), Algorithm::Sha256, None, 1024).unwrap();
+        for byte in &data {
+            writer.write_all(std::slice::from_ref(byte)).unwrap();
+        }
+        let sri = writer.close().unwrap();
+        assert_eq!(sri, Integrity::from(&data));
+        assert_eq!(
+            std::fs::read(path::content_path(dir.as_path().into(), &sri)).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn preallocated_write() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let data = vec![7u8; 2 * 1024 * 1024];
+        let mut writer = Writer::new(dir.as_path().into(), Algorithm::Sha256, Some(data.len()))
+            .unwrap()
+            .with_preallocation(Some(data.len() as u64 + 1024))
+            .unwrap();
+        writer.write_all(&data).unwrap();
+        let sri = writer.close().unwrap();
+        assert_eq!(sri, Integrity::from(&data));
+        assert_eq!(
+            std::fs::read(path::content_path(dir.as_path().into(), &sri)).unwrap(),
+            data
+        );
+    }
+
     #[test]
     fn basic_write() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let mut writer = Writer::new(&dir, Algorithm::Sha256, None).unwrap();
+        let mut writer = Writer::new(dir.as_path().into(), Algorithm::Sha256, None).unwrap();
         writer.write_all(b"hello world").unwrap();
         let sri = writer.close().unwrap();
         assert_eq!(sri.to_string(), Integrity::from(b"hello world").to_string());
         assert_eq!(
-            std::fs::read(path::content_path(&dir, &sri)).unwrap(),
+            std::fs::read(path::content_path(dir.as_path().into(), &sri)).unwrap(),
             b"hello world"
         );
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(unix)]
+    #[test]
+    fn deduplicated_write_skips_persist() {
+        use std::os::unix::fs::MetadataExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = Writer::new(dir.as_path().into(), Algorithm::Sha256, None).unwrap();
+        writer.write_all(b"hello world").unwrap();
+        let sri = writer.close().unwrap();
+        let cpath = path::content_path(dir.as_path().into(), &sri);
+        let ino = std::fs::metadata(&cpath).unwrap().ino();
+
+        let mut writer = Writer::new(dir.as_path().into(), Algorithm::Sha256, None).unwrap();
+        writer.write_all(b"hello world").unwrap();
+        assert_eq!(writer.close_detailed().unwrap(), (sri, true));
+        assert_eq!(std::fs::metadata(&cpath).unwrap().ino(), ino);
+        assert_eq!(std::fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn dropped_mid_write() {
+        use futures::FutureExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = AsyncWriter::new(dir.as_path().into(), Algorithm::Sha256, None)
+            .await
+            .unwrap();
+        let data = vec![1u8; 1024 * 1024];
+        // Start a write, but give up on it while it's still in flight.
+        assert!(writer.write_all(&data).now_or_never().is_none());
+        drop(writer);
+
+        let tmp_dir = dir.join("tmp");
+        for _ in 0..500 {
+            if std::fs::read_dir(&tmp_dir).unwrap().count() == 0 {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("temp file was left behind by a dropped writer");
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn basic_async_write() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let mut writer = AsyncWriter::new(&dir, Algorithm::Sha256, None)
+        let mut writer = AsyncWriter::new(dir.as_path().into(), Algorithm::Sha256, None)
             .await
             .unwrap();
         writer.write_all(b"hello world").await.unwrap();
         let sri = writer.close().await.unwrap();
         assert_eq!(sri.to_string(), Integrity::from(b"hello world").to_string());
         assert_eq!(
-            std::fs::read(path::content_path(&dir, &sri)).unwrap(),
+            std::fs::read(path::content_path(dir.as_path().into(), &sri)).unwrap(),
             b"hello world"
         );
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn available_space_of_missing_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let existing = available_space(tmp.path()).unwrap().unwrap();
+        assert!(existing > 0);
+        // Paths that don't exist yet are measured on their closest ancestor.
+        let missing = available_space(&tmp.path().join("a/b/c")).unwrap();
+        assert!(missing.is_some());
+    }
 }
@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use miette::Diagnostic;
+use ssri::{Algorithm, Integrity};
 use thiserror::Error;
 
+use crate::content::path::content_path;
+
 /// Error type returned by all API calls.
 #[derive(Error, Debug, Diagnostic)]
 pub enum Error {
@@ -15,7 +18,33 @@ pub enum Error {
     /// Returned when a size check has failed.
     #[error("Size check failed.\n\tWanted: {0}\n\tActual: {1}")]
     #[diagnostic(code(cacache::size_mismatch), url(docsrs))]
-    SizeMismatch(usize, usize),
+    SizeMismatch(u64, u64),
+
+    /// Returned when content is larger than the maximum size a caller was
+    /// willing to read.
+    #[error("Content too large.\n\tMaximum: {0}\n\tActual: {1}")]
+    #[diagnostic(code(cacache::content_too_large), url(docsrs))]
+    ContentTooLarge(u64, u64),
+
+    /// Returned when the filesystem a cache is on doesn't have room for a
+    /// write of a declared size. See
+    /// [`WriteOpts::check_space`](crate::WriteOpts::check_space).
+    #[error("Not enough space for cache contents in {0:?}.\n\tNeeded: {1}\n\tAvailable: {2}")]
+    #[diagnostic(code(cacache::insufficient_space), url(docsrs))]
+    InsufficientSpace(PathBuf, u64, u64),
+
+    /// Returned when writing under a key longer than
+    /// [`MAX_KEY_LENGTH`](crate::index::MAX_KEY_LENGTH) bytes, after
+    /// normalization.
+    #[error("Key too long.\n\tMaximum: {0}\n\tActual: {1}")]
+    #[diagnostic(code(cacache::key_too_long), url(docsrs))]
+    KeyTooLong(usize, usize),
+
+    /// Returned when an index entry doesn't record a hash for the algorithm
+    /// it was asked to be verified with.
+    #[error("Entry for key {1:?} in cache {0:?} has no {2} hash to verify against")]
+    #[diagnostic(code(cacache::missing_hash), url(docsrs))]
+    MissingHash(PathBuf, String, Algorithm),
 
     /// Returned when a general IO error has occurred.
     #[error("{1}")]
@@ -27,6 +56,43 @@ pub enum Error {
     #[diagnostic(code(cacache::serde_error), url(docsrs))]
     SerdeError(#[source] serde_json::Error, String),
 
+    /// Returned when writing with `WriteOpts::fail_on_conflict()` to a key
+    /// that already points to different content. Includes the cache, the
+    /// key, and the existing and new integrities.
+    #[error("Key {1:?} in cache {0:?} already points to different content.\n\tExisting: {2}\n\tNew: {3}")]
+    #[diagnostic(code(cacache::key_conflict), url(docsrs))]
+    KeyConflict(PathBuf, String, Integrity, Integrity),
+
+    /// Returned by `verify_link_sync()` when the file a linked entry points to
+    /// has changed or gone away since it was linked into the cache. Includes
+    /// the cache, the key, and the link's target.
+    #[error("Target {2:?} of key {1:?} in cache {0:?} has changed since it was linked")]
+    #[diagnostic(code(cacache::link_target_changed), url(docsrs))]
+    LinkTargetChanged(PathBuf, String, PathBuf),
+
+    /// Returned when data being written to the cache didn't match the
+    /// integrity it was expected to have. Includes the cache, the key (if
+    /// there was one), and the wanted and actual integrities.
+    #[error(
+        "Integrity check failed while writing {} to cache {0:?}.\n\tWanted: {2}\n\tActual: {3}",
+        describe_key(.1.as_deref())
+    )]
+    #[diagnostic(code(cacache::write_integrity_mismatch), url(docsrs))]
+    WriteIntegrityMismatch(PathBuf, Option<String>, Integrity, Integrity),
+
+    /// Returned when data read back from the cache didn't match the
+    /// integrity it was stored under, which usually means the content on
+    /// disk is corrupted. Includes the cache, the key (if there was one), and
+    /// the wanted and actual integrities, so the offending entry can be
+    /// located.
+    #[error(
+        "Integrity check failed while reading {} from cache {0:?}, at {}.\n\tWanted: {2}\n\tActual: {3}",
+        describe_key(.1.as_deref()),
+        content_path(.0.into(), .2).display()
+    )]
+    #[diagnostic(code(cacache::read_integrity_mismatch), url(docsrs))]
+    ReadIntegrityMismatch(PathBuf, Option<String>, Integrity, Integrity),
+
     /// Returned when an integrity check has failed.
     #[error(transparent)]
     #[diagnostic(code(cacache::integrity_error), url(docsrs))]
@@ -36,6 +102,49 @@ pub enum Error {
 /// The result type returned by calls to this library
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Attaches `key` to a read integrity mismatch that was detected without
+    /// knowing which key the content belonged to.
+    pub(crate) fn with_key(self, key: &str) -> Self {
+        match self {
+            Error::ReadIntegrityMismatch(cache, None, wanted, actual) => {
+                Error::ReadIntegrityMismatch(cache, Some(key.into()), wanted, actual)
+            }
+            err => err,
+        }
+    }
+}
+
+fn describe_key(key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("key {key:?}"),
+        None => "content".into(),
+    }
+}
+
+pub trait IntegrityErrorExt<T> {
+    /// Converts a failed integrity check on data read from `cache` into an
+    /// `Error::ReadIntegrityMismatch`.
+    fn with_location(self, cache: &Path, key: Option<&str>) -> Result<T>;
+}
+
+impl<T> IntegrityErrorExt<T> for std::result::Result<T, ssri::Error> {
+    fn with_location(self, cache: &Path, key: Option<&str>) -> Result<T> {
+        match self {
+            Ok(t) => Ok(t),
+            Err(ssri::Error::IntegrityCheckError(wanted, actual)) => {
+                Err(Error::ReadIntegrityMismatch(
+                    cache.to_path_buf(),
+                    key.map(String::from),
+                    wanted,
+                    actual,
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
 pub trait IoErrorExt<T> {
     fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T>;
 }
@@ -0,0 +1,805 @@
+//! Functions for cleaning up and checking the health of a cache.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use ssri::{Algorithm, Integrity};
+use walkdir::WalkDir;
+
+use crate::config::{self, CacheDir};
+use crate::content::{path, read};
+use crate::errors::{Error, IoErrorExt, Result};
+use crate::index;
+
+/// Statistics from a garbage collection run. See [`GcOpts::gc_sync`].
+#[derive(Debug, Default)]
+pub struct GcStats {
+    /// Number of content files no live index entry refers to that were
+    /// removed. Files that couldn't be are only counted in `failed`.
+    pub removed_content: usize,
+    /// Total size, in bytes, of that content.
+    pub reclaimed_size: u64,
+    /// Content files that couldn't be removed, along with why.
+    pub failed: Vec<(PathBuf, Error)>,
+    /// If true, nothing was actually removed, and the stats above only
+    /// describe what would have been.
+    pub dry_run: bool,
+}
+
+/// Statistics from a content verification run. See [`VerifyOpts::verify_sync`].
+#[derive(Debug, Default)]
+pub struct VerifyStats {
+    /// Number of content files whose data matched their hash.
+    pub verified_content: usize,
+    /// Number of content files whose data didn't match their hash.
+    pub bad_content: usize,
+    /// Total size, in bytes, of the bad content.
+    pub reclaimed_size: u64,
+    /// Content files that couldn't be checked or removed, along with why.
+    pub failed: Vec<(PathBuf, Error)>,
+    /// If true, nothing was actually removed, and the stats above only
+    /// describe what would have been.
+    pub dry_run: bool,
+}
+
+/// Statistics from an eviction run. See [`EvictOpts::evict_sync`].
+#[derive(Debug, Default)]
+pub struct EvictStats {
+    /// Number of index entries older than the maximum age that were removed.
+    /// Entries that couldn't be are only counted in `failed`.
+    pub evicted_entries: usize,
+    /// Keys whose entries couldn't be removed, along with why.
+    pub failed: Vec<(String, Error)>,
+    /// If true, nothing was actually removed, and the stats above only
+    /// describe what would have been.
+    pub dry_run: bool,
+}
+
+/// Options for garbage collecting content that's no longer referenced by the
+/// index.
+#[derive(Clone, Default)]
+pub struct GcOpts {
+    pub(crate) dry_run: bool,
+    pub(crate) grace_period: Option<Duration>,
+}
+
+/// How recently content must have been written for `GcOpts::gc_sync` to
+/// leave it alone, unless set otherwise.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+impl GcOpts {
+    /// Creates garbage collection options.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// If set to true, scans the cache and reports what would be removed
+    /// without removing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Leaves content written less than `grace_period` before the run
+    /// started alone, even if nothing references it. A writer stores its
+    /// content before indexing it, so without this, content that's about to
+    /// be indexed could be removed out from under it. Defaults to one minute.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    /// Removes all content that isn't referenced by a live index entry,
+    /// except for content written within the grace period, or while the run
+    /// is going. Files that can't be removed are reported in
+    /// [`GcStats::failed`] rather than stopping the run.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// fn main() -> cacache::Result<()> {
+    ///     let stats = cacache::GcOpts::new().dry_run(true).gc_sync("./my-cache")?;
+    ///     println!("would reclaim {} bytes", stats.reclaimed_size);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn gc_sync<P: AsRef<Path>>(self, cache: P) -> Result<GcStats> {
+        config::with_configured_dir(cache.as_ref(), |cache| gc_with(self, cache, remove_content))
+    }
+}
+
+/// Does the work of [`GcOpts::gc_sync`], using `remove` to get rid of each
+/// unreferenced content file so tests can make removal fail.
+fn gc_with(
+    me: GcOpts,
+    cache: CacheDir<'_>,
+    remove: impl Fn(&Path, bool) -> Result<u64>,
+) -> Result<GcStats> {
+    // Taken before the index is read, so anything written after this point
+    // is kept whether or not the index snapshot saw it.
+    let cutoff = SystemTime::now()
+        .checked_sub(me.grace_period.unwrap_or(DEFAULT_GRACE_PERIOD))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    // Content may be stored under any of the hashes an entry records.
+    let live = index::referenced_content(cache)?
+        .into_iter()
+        .flat_map(|sri| sri.hashes)
+        .map(|hash| path::content_path(cache, &Integrity { hashes: vec![hash] }))
+        .collect::<HashSet<_>>();
+    let mut stats = GcStats {
+        dry_run: me.dry_run,
+        ..Default::default()
+    };
+    for file in content_files(cache)? {
+        if live.contains(&file) {
+            continue;
+        }
+        let res = is_recent(&file, cutoff).and_then(|recent| {
+            if recent {
+                Ok(None)
+            } else {
+                remove(&file, me.dry_run).map(Some)
+            }
+        });
+        match res {
+            Ok(Some(size)) => {
+                stats.removed_content += 1;
+                stats.reclaimed_size += size;
+            }
+            Ok(None) => {}
+            Err(e) => stats.failed.push((file, e)),
+        }
+    }
+    Ok(stats)
+}
+
+/// Options for verifying the integrity of all content in the cache.
+#[derive(Clone, Default)]
+pub struct VerifyOpts {
+    pub(crate) dry_run: bool,
+}
+
+impl VerifyOpts {
+    /// Creates content verification options.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// If set to true, scans the cache and reports what would be removed
+    /// without removing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Reads every content file in the cache, and removes the ones whose data
+    /// doesn't match the hash they're stored under. Files that can't be read
+    /// or removed are reported in [`VerifyStats::failed`] rather than
+    /// stopping the run.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// fn main() -> cacache::Result<()> {
+    ///     let stats = cacache::VerifyOpts::new().verify_sync("./my-cache")?;
+    ///     println!("removed {} corrupted files", stats.bad_content);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn verify_sync<P: AsRef<Path>>(self, cache: P) -> Result<VerifyStats> {
+        fn inner(me: VerifyOpts, cache: CacheDir<'_>) -> Result<VerifyStats> {
+            let mut stats = VerifyStats {
+                dry_run: me.dry_run,
+                ..Default::default()
+            };
+            for file in content_files(cache)? {
+                match check_content(cache, &file) {
+                    Ok(true) => stats.verified_content += 1,
+                    Ok(false) => {
+                        stats.bad_content += 1;
+                        match remove_content(&file, me.dry_run) {
+                            Ok(size) => stats.reclaimed_size += size,
+                            Err(e) => stats.failed.push((file, e)),
+                        }
+                    }
+                    Err(e) => stats.failed.push((file, e)),
+                }
+            }
+            Ok(stats)
+        }
+        config::with_configured_dir(cache.as_ref(), |cache| inner(self, cache))
+    }
+}
+
+/// Options for evicting old entries from the index.
+#[derive(Clone, Default)]
+pub struct EvictOpts {
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) dry_run: bool,
+}
+
+impl EvictOpts {
+    /// Creates eviction options.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Evict index entries that were written longer than `max_age` ago. If
+    /// this isn't set, nothing is evicted.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// If set to true, scans the index and reports what would be evicted
+    /// without removing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Removes index entries older than the configured maximum age. Their
+    /// content is left in place, to be cleaned up by [`GcOpts::gc_sync`].
+    /// Entries that can't be removed are reported in [`EvictStats::failed`]
+    /// rather than stopping the run.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     cacache::EvictOpts::new()
+    ///         .max_age(Duration::from_secs(60 * 60 * 24 * 30))
+    ///         .evict_sync("./my-cache")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn evict_sync<P: AsRef<Path>>(self, cache: P) -> Result<EvictStats> {
+        config::with_configured_dir(cache.as_ref(), |cache| {
+            evict_with(self, cache, index::delete_in)
+        })
+    }
+}
+
+/// Does the work of [`EvictOpts::evict_sync`], using `delete` to remove each
+/// expired entry so tests can make deletion fail.
+fn evict_with(
+    me: EvictOpts,
+    cache: CacheDir<'_>,
+    delete: impl Fn(CacheDir<'_>, &str) -> Result<()>,
+) -> Result<EvictStats> {
+    let mut stats = EvictStats {
+        dry_run: me.dry_run,
+        ..Default::default()
+    };
+    let max_age = match me.max_age {
+        Some(max_age) => max_age.as_millis(),
+        None => return Ok(stats),
+    };
+    let now = index::now();
+    for entry in index::ls_in(cache) {
+        let entry = entry?;
+        if now.saturating_sub(entry.time) > max_age {
+            let res = if me.dry_run {
+                Ok(())
+            } else {
+                delete(cache, &entry.key)
+            };
+            match res {
+                Ok(()) => stats.evicted_entries += 1,
+                Err(e) => stats.failed.push((entry.key, e)),
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Counts the content files stored under each algorithm, along with their
+/// total size in bytes. Only the content directory is walked, without reading
+/// the index, so this also covers content no entry refers to anymore.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     for (algo, (files, size)) in cacache::content_stats_sync("./my-cache")? {
+///         println!("{algo}: {files} files, {size} bytes");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn content_stats_sync<P: AsRef<Path>>(cache: P) -> Result<HashMap<Algorithm, (usize, u64)>> {
+    fn inner(cache: CacheDir<'_>) -> Result<HashMap<Algorithm, (usize, u64)>> {
+        let mut stats = HashMap::new();
+        for file in content_files(cache)? {
+            // Content lives at `{algo}/xx/yy/rest` under the content directory.
+            let algo = file
+                .ancestors()
+                .nth(3)
+                .and_then(|dir| dir.file_name()?.to_str()?.parse::<Algorithm>().ok());
+            let algo = match algo {
+                Some(algo) => algo,
+                None => continue,
+            };
+            let size = fs::metadata(&file)
+                .with_context(|| format!("Failed to get metadata for {}", file.display()))?
+                .len();
+            let (count, total) = stats.entry(algo).or_insert((0, 0));
+            *count += 1;
+            *total += size;
+        }
+        Ok(stats)
+    }
+    config::with_configured_dir(cache.as_ref(), inner)
+}
+
+/// Overview of a cache's index, as reported by [`stats_sync`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of live index entries.
+    pub entries: usize,
+    /// Number of distinct pieces of content live entries point to.
+    pub unique_content: usize,
+    /// Number of keys whose latest record is a tombstone left by a delete.
+    pub tombstones: usize,
+    /// Sum of the sizes recorded by live entries. Content shared by several
+    /// entries is counted once for each of them.
+    pub total_logical_size: u64,
+    /// Time, in unix milliseconds, the oldest live entry was written at.
+    pub oldest_time: Option<u128>,
+    /// Time, in unix milliseconds, the newest live entry was written at.
+    pub newest_time: Option<u128>,
+}
+
+/// Gathers [`CacheStats`] for `cache`, in a single walk of its index. The
+/// content directory isn't touched.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::stats("./my-cache").await?;
+///     println!("{} entries, {} bytes", stats.entries, stats.total_logical_size);
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn stats<P: AsRef<Path>>(cache: P) -> Result<CacheStats> {
+    let cache = cache.as_ref().to_path_buf();
+    crate::async_lib::run_blocking(move || stats_sync(cache)).await
+}
+
+/// Gathers [`CacheStats`] for `cache` synchronously, in a single walk of its
+/// index. The content directory isn't touched.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::stats_sync("./my-cache")?;
+///     println!("{} entries, {} bytes", stats.entries, stats.total_logical_size);
+///     Ok(())
+/// }
+/// ```
+pub fn stats_sync<P: AsRef<Path>>(cache: P) -> Result<CacheStats> {
+    fn inner(cache: CacheDir<'_>) -> Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        if !cache.index_dir().exists() {
+            return Ok(stats);
+        }
+        let mut content = HashSet::new();
+        for record in index::ls_with_tombstones_in(cache) {
+            let record = record?;
+            let sri = match record.integrity {
+                Some(sri) => sri,
+                None => {
+                    stats.tombstones += 1;
+                    continue;
+                }
+            };
+            stats.entries += 1;
+            stats.total_logical_size += record.size;
+            stats.oldest_time = Some(
+                stats
+                    .oldest_time
+                    .map_or(record.time, |t| t.min(record.time)),
+            );
+            stats.newest_time = Some(
+                stats
+                    .newest_time
+                    .map_or(record.time, |t| t.max(record.time)),
+            );
+            content.insert(sri);
+        }
+        stats.unique_content = content.len();
+        Ok(stats)
+    }
+    config::with_configured_dir(cache.as_ref(), inner)
+}
+
+/// Replaces content files in `secondary` that `primary` also has with
+/// hardlinks to `primary`'s copies, returning how many bytes that saved.
+///
+/// Both copies are hashed before anything is replaced, and content that
+/// doesn't match its hash in either cache is left alone. Pairs that live on
+/// different filesystems can't be linked and are skipped.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let saved = cacache::dedup_across_sync("./my-cache", "./other-cache")?;
+///     println!("saved {saved} bytes");
+///     Ok(())
+/// }
+/// ```
+pub fn dedup_across_sync<P, Q>(primary: P, secondary: Q) -> Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fn inner(primary: CacheDir<'_>, secondary: CacheDir<'_>) -> Result<u64> {
+        let mut saved = 0;
+        for file in content_files(secondary)? {
+            let sri = match path::path_integrity(&file) {
+                Some(sri) => sri,
+                None => continue,
+            };
+            if read::has_content(primary, &sri).is_none() {
+                continue;
+            }
+            let original = path::content_path(primary, &path::stored_integrity(primary, &sri));
+            if is_same_file(&original, &file)? {
+                continue;
+            }
+            if !check_content(secondary, &file)? || !check_content(primary, &original)? {
+                continue;
+            }
+            let tmp_path = secondary.join("tmp");
+            fs::create_dir_all(&tmp_path).with_context(|| {
+                format!("Failed to create tmp directory at {}", tmp_path.display())
+            })?;
+            // The staging directory and anything left in it is removed when
+            // dropped.
+            let staging = tempfile::tempdir_in(&tmp_path).with_context(|| {
+                format!("Failed to create tmp directory at {}", tmp_path.display())
+            })?;
+            let link = staging.path().join("link");
+            match read::hard_link_unchecked(primary, &sri, &link) {
+                Ok(()) => {}
+                Err(Error::IoError(e, _)) if is_cross_device(&e) => continue,
+                Err(e) => return Err(e),
+            }
+            let size = fs::metadata(&file)
+                .with_context(|| format!("Failed to get metadata for {}", file.display()))?
+                .len();
+            fs::rename(&link, &file).with_context(|| {
+                format!("Failed to replace cache contents at {}", file.display())
+            })?;
+            saved += size;
+        }
+        Ok(saved)
+    }
+    config::with_configured_dir(primary.as_ref(), |primary| {
+        config::with_configured_dir(secondary.as_ref(), |secondary| inner(primary, secondary))
+    })
+}
+
+fn content_files(cache: CacheDir<'_>) -> Result<Vec<PathBuf>> {
+    let content = path::content_dir(cache);
+    if !content.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&content) {
+        let entry = entry
+            .map_err(|e| match e.io_error() {
+                Some(io_err) => std::io::Error::new(io_err.kind(), io_err.kind().to_string()),
+                None => crate::errors::io_error("Unexpected error"),
+            })
+            .with_context(|| {
+                format!(
+                    "Error while walking cache content directory at {}",
+                    content.display()
+                )
+            })?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+/// Returns true if the data in a content file matches the hash it's stored
+/// under.
+fn check_content(cache: CacheDir<'_>, file: &Path) -> Result<bool> {
+    let sri = match path::path_integrity(file) {
+        Some(sri) => sri,
+        None => return Ok(false),
+    };
+    read::is_intact(cache, &sri)
+}
+
+/// Returns true if both paths are already links to the same file.
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let a =
+        fs::metadata(a).with_context(|| format!("Failed to get metadata for {}", a.display()))?;
+    let b =
+        fs::metadata(b).with_context(|| format!("Failed to get metadata for {}", b.display()))?;
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Path, _b: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// Returns true if a link failed because its ends are on different
+/// filesystems.
+fn is_cross_device(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    const CROSS_DEVICE: i32 = libc::EXDEV;
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(windows)]
+    const CROSS_DEVICE: i32 = 17;
+    #[cfg(not(any(unix, windows)))]
+    const CROSS_DEVICE: i32 = -1;
+    e.raw_os_error() == Some(CROSS_DEVICE)
+}
+
+/// Returns true if `file` was last modified after `cutoff`.
+fn is_recent(file: &Path, cutoff: SystemTime) -> Result<bool> {
+    let modified = fs::metadata(file)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("Failed to get metadata for {}", file.display()))?;
+    Ok(modified > cutoff)
+}
+
+/// Removes a content file unless this is a dry run, returning its size.
+fn remove_content(file: &Path, dry_run: bool) -> Result<u64> {
+    let size = fs::metadata(file)
+        .with_context(|| format!("Failed to get metadata for {}", file.display()))?
+        .len();
+    if !dry_run {
+        fs::remove_file(file)
+            .with_context(|| format!("Failed to remove cache contents at {}", file.display()))?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
+    use async_attributes::test as async_test;
+    #[cfg(feature = "tokio")]
+    use tokio::test as async_test;
+
+    #[test]
+    fn content_stats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert!(content_stats_sync(&dir).unwrap().is_empty());
+        crate::write_hash_sync(&dir, b"hello").unwrap();
+        crate::write_hash_sync(&dir, b"world!").unwrap();
+        crate::write_hash_sync_with_algo(Algorithm::Xxh3, &dir, b"hello").unwrap();
+
+        let stats = content_stats_sync(&dir).unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[&Algorithm::Sha256], (2, 11));
+        assert_eq!(stats[&Algorithm::Xxh3], (1, 5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedup_across() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let primary = tmp.path().join("primary");
+        let secondary = tmp.path().join("secondary");
+        let shared = crate::write_sync(&primary, "a", b"shared data").unwrap();
+        crate::write_sync(&secondary, "b", b"shared data").unwrap();
+        crate::write_sync(&secondary, "c", b"only here").unwrap();
+
+        assert_eq!(dedup_across_sync(&primary, &secondary).unwrap(), 11);
+        let original = fs::metadata(crate::content_path(&primary, &shared)).unwrap();
+        let linked = fs::metadata(crate::content_path(&secondary, &shared)).unwrap();
+        assert_eq!(original.ino(), linked.ino());
+        assert_eq!(crate::read_sync(&secondary, "b").unwrap(), b"shared data");
+        assert_eq!(crate::read_sync(&secondary, "c").unwrap(), b"only here");
+
+        // Already-linked content isn't counted again.
+        assert_eq!(dedup_across_sync(&primary, &secondary).unwrap(), 0);
+    }
+
+    #[test]
+    fn cache_stats() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert_eq!(stats_sync(&dir).unwrap(), CacheStats::default());
+        for (key, data, time) in [
+            ("a", &b"hello"[..], 30),
+            ("b", b"hello", 10),
+            ("c", b"world!", 20),
+        ] {
+            let mut writer = crate::WriteOpts::new()
+                .time(time)
+                .open_sync(&dir, key)
+                .unwrap();
+            writer.write_all(data).unwrap();
+            writer.commit().unwrap();
+        }
+        crate::write_sync(&dir, "gone", b"bye").unwrap();
+        crate::remove_sync(&dir, "gone").unwrap();
+
+        let expected = CacheStats {
+            entries: 3,
+            unique_content: 2,
+            tombstones: 1,
+            total_logical_size: 16,
+            oldest_time: Some(10),
+            newest_time: Some(30),
+        };
+        assert_eq!(stats_sync(&dir).unwrap(), expected);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn cache_stats_async() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "a", b"hello").await.unwrap();
+        crate::write(&dir, "b", b"hello").await.unwrap();
+        let stats = stats(&dir).await.unwrap();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.unique_content, 1);
+        assert_eq!(stats.total_logical_size, 10);
+    }
+
+    #[test]
+    fn gc_dry_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "live", b"live").unwrap();
+        let dead = crate::write_hash_sync(&dir, b"dead").unwrap();
+        let dead_path = crate::content_path(&dir, &dead);
+
+        let stats = GcOpts::new()
+            .grace_period(Duration::ZERO)
+            .dry_run(true)
+            .gc_sync(&dir)
+            .unwrap();
+        assert!(stats.dry_run);
+        assert_eq!(stats.removed_content, 1);
+        assert_eq!(stats.reclaimed_size, 4);
+        assert!(dead_path.exists());
+
+        let stats = GcOpts::new()
+            .grace_period(Duration::ZERO)
+            .gc_sync(&dir)
+            .unwrap();
+        assert!(!stats.dry_run);
+        assert_eq!(stats.removed_content, 1);
+        assert!(!dead_path.exists());
+        assert_eq!(crate::read_sync(&dir, "live").unwrap(), b"live");
+    }
+
+    #[test]
+    fn gc_grace_period() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        // Stands in for content a writer has stored but not indexed yet.
+        let pending = crate::write_hash_sync(&dir, b"pending").unwrap();
+        let pending_path = crate::content_path(&dir, &pending);
+
+        let stats = GcOpts::new().gc_sync(&dir).unwrap();
+        assert_eq!(stats.removed_content, 0);
+        assert!(pending_path.exists());
+
+        let stats = GcOpts::new()
+            .grace_period(Duration::from_secs(3600))
+            .gc_sync(&dir)
+            .unwrap();
+        assert_eq!(stats.removed_content, 0);
+
+        let stats = GcOpts::new()
+            .grace_period(Duration::ZERO)
+            .gc_sync(&dir)
+            .unwrap();
+        assert_eq!(stats.removed_content, 1);
+        assert!(!pending_path.exists());
+    }
+
+    #[test]
+    fn verify_dry_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "good", b"good").unwrap();
+        let bad = crate::write_sync(&dir, "bad", b"bad").unwrap();
+        let bad_path = crate::content_path(&dir, &bad);
+        fs::write(&bad_path, b"corrupted").unwrap();
+
+        let stats = VerifyOpts::new().dry_run(true).verify_sync(&dir).unwrap();
+        assert!(stats.dry_run);
+        assert_eq!(stats.verified_content, 1);
+        assert_eq!(stats.bad_content, 1);
+        assert!(bad_path.exists());
+    }
+
+    #[test]
+    fn evict_dry_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "old", b"old").unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+
+        let stats = EvictOpts::new()
+            .max_age(Duration::from_millis(1))
+            .dry_run(true)
+            .evict_sync(&dir)
+            .unwrap();
+        assert!(stats.dry_run);
+        assert_eq!(stats.evicted_entries, 1);
+        assert!(crate::metadata_sync(&dir, "old").unwrap().is_some());
+    }
+
+    #[test]
+    fn gc_failed_removal() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "live", b"live").unwrap();
+        let dead = crate::write_hash_sync(&dir, b"dead").unwrap();
+        let dead_path = crate::content_path(&dir, &dead);
+
+        let stats = gc_with(
+            GcOpts::new().grace_period(Duration::ZERO),
+            dir.as_path().into(),
+            |file, _| {
+                Err(Error::IoError(
+                    crate::errors::io_error("nope"),
+                    format!("Failed to remove {}", file.display()),
+                ))
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.removed_content, 0);
+        assert_eq!(stats.reclaimed_size, 0);
+        assert_eq!(stats.failed.len(), 1);
+        assert_eq!(stats.failed[0].0, dead_path);
+        assert!(dead_path.exists());
+    }
+
+    #[test]
+    fn evict_failed_delete() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "old", b"old").unwrap();
+        crate::write_sync(&dir, "older", b"older").unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+
+        let opts = EvictOpts::new().max_age(Duration::from_millis(1));
+        let stats = evict_with(opts, dir.as_path().into(), |cache, key| {
+            if key == "old" {
+                Err(Error::IoError(
+                    crate::errors::io_error("nope"),
+                    "Failed to delete entry".into(),
+                ))
+            } else {
+                index::delete_in(cache, key)
+            }
+        })
+        .unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(stats.failed.len(), 1);
+        assert_eq!(stats.failed[0].0, "old");
+        assert!(crate::metadata_sync(&dir, "old").unwrap().is_some());
+        assert!(crate::metadata_sync(&dir, "older").unwrap().is_none());
+    }
+}
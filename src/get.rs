@@ -1,16 +1,17 @@
 //! Functions for reading from cache.
-use std::path::Path;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::pin::Pin;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::task::{Context as TaskContext, Poll};
 
 use ssri::{Algorithm, Integrity};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-use crate::async_lib::AsyncRead;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use crate::async_lib::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use crate::content::read;
-use crate::errors::{Error, Result};
+use crate::errors::{Error, IoErrorExt, Result};
 use crate::index::{self, Metadata};
 
 // ---------
@@ -21,14 +22,16 @@ use crate::index::{self, Metadata};
 ///
 /// Make sure to call `.check()` when done reading to verify that the
 /// extracted data passes integrity verification.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub struct Reader {
     reader: read::AsyncReader,
+    key: Option<String>,
+    expected_size: Option<u64>,
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncRead for Reader {
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut TaskContext<'_>,
@@ -47,7 +50,7 @@ impl AsyncRead for Reader {
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl Reader {
     /// Checks that data read from disk passes integrity checks. Returns the
     /// algorithm that was used verified the data. Should be called only after
@@ -73,7 +76,25 @@ impl Reader {
     /// }
     /// ```
     pub fn check(self) -> Result<Algorithm> {
-        self.reader.check()
+        match &self.key {
+            Some(key) => self.reader.check().map_err(|e| e.with_key(key)),
+            None => self.reader.check(),
+        }
+    }
+
+    /// Returns the algorithm `check()` will verify the data with: the
+    /// strongest one in the entry's integrity. This is known as soon as the
+    /// reader is opened, before any data is read.
+    pub fn algorithm(&self) -> Algorithm {
+        self.reader.algorithm()
+    }
+
+    /// Returns the size of the data as recorded in the index, if this reader
+    /// was opened by key. Handy for progress displays or for pre-sizing
+    /// buffers. Readers opened by hash have no index entry to go by, so this
+    /// is `None` for them.
+    pub fn expected_size(&self) -> Option<u64> {
+        self.expected_size
     }
 
     /// Opens a new file handle into the cache, looking it up in the index using
@@ -101,7 +122,10 @@ impl Reader {
     {
         async fn inner(cache: &Path, key: &str) -> Result<Reader> {
             if let Some(entry) = index::find_async(cache, key).await? {
-                Reader::open_hash(cache, entry.integrity).await
+                let mut reader = Reader::open_hash(cache, entry.integrity).await?;
+                reader.key = Some(key.into());
+                reader.expected_size = Some(entry.size);
+                Ok(reader)
             } else {
                 Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
             }
@@ -132,7 +156,9 @@ impl Reader {
         P: AsRef<Path>,
     {
         Ok(Reader {
-            reader: read::open_async(cache.as_ref(), sri).await?,
+            reader: read::open_async(cache.as_ref().into(), sri).await?,
+            key: None,
+            expected_size: None,
         })
     }
 }
@@ -140,6 +166,10 @@ impl Reader {
 /// Reads the entire contents of a cache file into a bytes vector, looking the
 /// data up by key.
 ///
+/// Data is verified the same way as with [`read_hash`], except that content
+/// shorter than the entry's recorded size fails with `Error::SizeMismatch`
+/// instead, since it was only partially written rather than corrupted.
+///
 /// ## Example
 /// ```no_run
 /// use async_std::prelude::*;
@@ -151,7 +181,7 @@ impl Reader {
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn read<P, K>(cache: P, key: K) -> Result<Vec<u8>>
 where
     P: AsRef<Path>,
@@ -159,7 +189,9 @@ where
 {
     async fn inner(cache: &Path, key: &str) -> Result<Vec<u8>> {
         if let Some(entry) = index::find_async(cache, key).await? {
-            read_hash(cache, &entry.integrity).await
+            read::read_sized_async(cache.into(), &entry.integrity, entry.size)
+                .await
+                .map_err(|e| e.with_key(key))
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -167,9 +199,51 @@ where
     inner(cache.as_ref(), key.as_ref()).await
 }
 
+/// Reads compressed content out of the cache by key, and returns it
+/// decompressed. The content is decoded according to the encoding recorded
+/// with `WriteOpts::content_encoding`, and returned as-is if none was
+/// recorded. The content's integrity is checked against the data as it was
+/// stored, before decompressing it. Use [`read`] to get the compressed bytes
+/// themselves.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let tarball: Vec<u8> = cacache::read_decompressed("./my-cache", "my-package.tgz").await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(all(
+    feature = "gzip",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
+pub async fn read_decompressed<P, K>(cache: P, key: K) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    async fn inner(cache: &Path, key: &str) -> Result<Vec<u8>> {
+        let entry = index::find_async(cache, key)
+            .await?
+            .ok_or_else(|| Error::EntryNotFound(cache.to_path_buf(), key.into()))?;
+        let data = read::read_sized_async(cache.into(), &entry.integrity, entry.size)
+            .await
+            .map_err(|e| e.with_key(key))?;
+        decode(cache, key, entry.content_encoding, data)
+    }
+    inner(cache.as_ref(), key.as_ref()).await
+}
+
 /// Reads the entire contents of a cache file into a bytes vector, looking the
 /// data up by its content address.
 ///
+/// The whole file is checked against `sri` before anything is returned, so
+/// corrupted or truncated content fails with `Error::ReadIntegrityMismatch`
+/// rather than handing back partial or bad data.
+///
 /// ## Example
 /// ```no_run
 /// use async_std::prelude::*;
@@ -182,16 +256,215 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn read_hash<P>(cache: P, sri: &Integrity) -> Result<Vec<u8>>
 where
     P: AsRef<Path>,
 {
-    read::read_async(cache.as_ref(), sri).await
+    read::read_async(cache.as_ref().into(), sri).await
+}
+
+/// Streams the contents of a cache entry into `writer`, looking the data up
+/// by key, without holding all of it in memory. Returns the number of bytes
+/// written.
+///
+/// The data is only verified once it's all been written, so if this fails
+/// with `Error::ReadIntegrityMismatch` or `Error::SizeMismatch`, whatever
+/// `writer` already received must be thrown away.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut out = async_std::io::stdout();
+///     cacache::read_to_writer("./my-cache", "my-key", &mut out).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_to_writer<P, K, W>(cache: P, key: K, mut writer: W) -> Result<u64>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    W: AsyncWrite + Unpin,
+{
+    let (cache, key) = (cache.as_ref(), key.as_ref());
+    let entry = index::find_async(cache, key)
+        .await?
+        .ok_or_else(|| Error::EntryNotFound(cache.to_path_buf(), key.into()))?;
+    let mut reader = Reader::open_hash(cache, entry.integrity).await?;
+    reader.key = Some(key.into());
+    let written = pump_async(&mut reader, &mut writer).await?;
+    if written < entry.size {
+        return Err(Error::SizeMismatch(entry.size, written));
+    }
+    reader.check()?;
+    Ok(written)
+}
+
+/// Streams the contents of a cache file into `writer`, looking the data up
+/// by its content address, without holding all of it in memory. Returns the
+/// number of bytes written.
+///
+/// The data is only verified once it's all been written, so if this fails
+/// with `Error::ReadIntegrityMismatch`, whatever `writer` already received
+/// must be thrown away.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///     let mut out = async_std::io::stdout();
+///     cacache::read_hash_to_writer("./my-cache", &sri, &mut out).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_hash_to_writer<P, W>(cache: P, sri: &Integrity, mut writer: W) -> Result<u64>
+where
+    P: AsRef<Path>,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = Reader::open_hash(cache, sri.clone()).await?;
+    let written = pump_async(&mut reader, &mut writer).await?;
+    reader.check()?;
+    Ok(written)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn pump_async<W: AsyncWrite + Unpin>(reader: &mut Reader, writer: &mut W) -> Result<u64> {
+    let mut buf = [0u8; 1024 * 8];
+    let mut written = 0u64;
+    loop {
+        let read = AsyncReadExt::read(reader, &mut buf)
+            .await
+            .with_context(|| "Failed to read cache contents".into())?;
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buf[..read])
+            .await
+            .with_context(|| "Failed to write cache contents to writer".into())?;
+        written += read as u64;
+    }
+    writer
+        .flush()
+        .await
+        .with_context(|| "Failed to flush writer".into())?;
+    Ok(written)
+}
+
+/// Size of the chunks yielded by [`read_stream`].
+#[cfg(all(
+    feature = "stream",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
+const READ_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Opens a cache entry by key as a stream of byte chunks, such as for use as
+/// an HTTP response body.
+///
+/// The data is verified once the stream reaches its end. If it doesn't
+/// match its integrity, the last item of the stream is an
+/// `Error::ReadIntegrityMismatch`, so consumers must not treat the data as
+/// good until the stream has ended without errors.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut stream = cacache::read_stream("./my-cache", "my-key").await?;
+///     while let Some(chunk) = stream.next().await {
+///         let chunk: bytes::Bytes = chunk?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(all(
+    feature = "stream",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
+pub async fn read_stream<P, K>(cache: P, key: K) -> Result<ReadStream>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    let reader = Reader::open(cache, key).await?;
+    let stream = futures::stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buf = vec![0; READ_STREAM_CHUNK_SIZE];
+        match reader.read(&mut buf).await {
+            Ok(0) => reader.check().err().map(|e| (Err(e), None)),
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(bytes::Bytes::from(buf)), Some(reader)))
+            }
+            Err(e) => {
+                let res = Err(e).with_context(|| "Failed to read cache contents".into());
+                Some((res, None))
+            }
+        }
+    });
+    Ok(ReadStream(Box::pin(stream)))
+}
+
+/// Stream of byte chunks returned by [`read_stream`].
+#[cfg(all(
+    feature = "stream",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
+pub struct ReadStream(Pin<Box<dyn futures::Stream<Item = Result<bytes::Bytes>> + Send>>);
+
+#[cfg(all(
+    feature = "stream",
+    any(feature = "async-std", feature = "tokio", feature = "async-fs")
+))]
+impl futures::Stream for ReadStream {
+    type Item = Result<bytes::Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+/// Reads the entire contents of a cache file into a bytes vector, looking the
+/// data up by its content address, unless it's larger than `max_bytes`. The
+/// size of the content is checked before anything is read, so unexpectedly
+/// large entries fail with `Error::ContentTooLarge` without being loaded
+/// into memory.
+///
+/// ## Example
+/// ```no_run
+/// use async_std::prelude::*;
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///     let data: Vec<u8> = cacache::read_hash_limited("./my-cache", &sri, 1024).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_hash_limited<P>(cache: P, sri: &Integrity, max_bytes: u64) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+{
+    read::read_limited_async(cache.as_ref().into(), sri, max_bytes).await
 }
 
 /// Copies cache data to a specified location. Returns the number of bytes
-/// copied.
+/// copied. Content shorter than the entry's recorded size fails with
+/// `Error::SizeMismatch`.
 ///
 /// ## Example
 /// ```no_run
@@ -204,7 +477,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn copy<P, K, Q>(cache: P, key: K, to: Q) -> Result<u64>
 where
     P: AsRef<Path>,
@@ -213,7 +486,9 @@ where
 {
     async fn inner(cache: &Path, key: &str, to: &Path) -> Result<u64> {
         if let Some(entry) = index::find_async(cache, key).await? {
-            copy_hash(cache, &entry.integrity, to).await
+            read::copy_sized_async(cache.into(), &entry.integrity, to, entry.size)
+                .await
+                .map_err(|e| e.with_key(key))
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -235,7 +510,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn copy_unchecked<P, K, Q>(cache: P, key: K, to: Q) -> Result<u64>
 where
     P: AsRef<Path>,
@@ -267,13 +542,13 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn copy_hash<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<u64>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::copy_async(cache.as_ref(), sri, to.as_ref()).await
+    read::copy_async(cache.as_ref().into(), sri, to.as_ref()).await
 }
 
 /// Copies a cache data by hash to a specified location. Copied data will not
@@ -291,13 +566,13 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn copy_hash_unchecked<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<u64>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::copy_unchecked_async(cache.as_ref(), sri, to.as_ref()).await
+    read::copy_unchecked_async(cache.as_ref().into(), sri, to.as_ref()).await
 }
 
 /// Creates a reflink/clonefile from a cache entry to a destination path.
@@ -319,7 +594,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn reflink<P, K, Q>(cache: P, key: K, to: Q) -> Result<()>
 where
     P: AsRef<Path>,
@@ -328,7 +603,9 @@ where
 {
     async fn inner(cache: &Path, key: &str, to: &Path) -> Result<()> {
         if let Some(entry) = index::find_async(cache, key).await? {
-            reflink_hash(cache, &entry.integrity, to).await
+            read::reflink_sized_async(cache.into(), &entry.integrity, to, entry.size)
+                .await
+                .map_err(|e| e.with_key(key))
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -356,7 +633,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn reflink_unchecked<P, K, Q>(cache: P, key: K, to: Q) -> Result<()>
 where
     P: AsRef<Path>,
@@ -393,27 +670,27 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn reflink_hash<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<()>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::reflink_async(cache.as_ref(), sri, to.as_ref()).await
+    read::reflink_async(cache.as_ref().into(), sri, to.as_ref()).await
 }
 
 /// Hard links a cache entry by hash to a specified location.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn hard_link_hash<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<()>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::hard_link_async(cache.as_ref(), sri, to.as_ref()).await
+    read::hard_link_async(cache.as_ref().into(), sri, to.as_ref()).await
 }
 
 /// Hard links a cache entry by key to a specified location.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn hard_link<P, K, Q>(cache: P, key: K, to: Q) -> Result<()>
 where
     P: AsRef<Path>,
@@ -434,8 +711,9 @@ where
 ///
 /// Note that the existence of a metadata entry is not a guarantee that the
 /// underlying data exists, since they are stored and managed independently.
-/// To verify that the underlying associated data exists, use `exists()`.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+/// To verify that the underlying associated data exists, use `exists()`, or
+/// `metadata_checked()` to do both at once.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn metadata<P, K>(cache: P, key: K) -> Result<Option<Metadata>>
 where
     P: AsRef<Path>,
@@ -444,10 +722,190 @@ where
     index::find_async(cache.as_ref(), key.as_ref()).await
 }
 
-/// Returns true if the given hash exists in the cache.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+/// Gets the metadata entry for a certain key, but only if its content is
+/// also in the cache. An index entry whose content has gone missing is
+/// treated the same as no entry at all, so this answers whether `key` can
+/// actually be read.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     if let Some(entry) = cacache::metadata_checked("./my-cache", "my-key").await? {
+///         println!("{} bytes cached", entry.size);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn metadata_checked<P, K>(cache: P, key: K) -> Result<Option<Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    async fn inner(cache: &Path, key: &str) -> Result<Option<Metadata>> {
+        match index::find_async(cache, key).await? {
+            Some(entry)
+                if read::has_content_async(cache.into(), &entry.integrity)
+                    .await
+                    .is_some() =>
+            {
+                Ok(Some(entry))
+            }
+            _ => Ok(None),
+        }
+    }
+    inner(cache.as_ref(), key.as_ref()).await
+}
+
+/// Gets the metadata entries for many keys at once, in the same order as
+/// `keys`. Each index bucket is only read once, and distinct buckets are read
+/// concurrently, which is much cheaper than calling `metadata()` for each
+/// key. Keys without an entry come back as `None`, so the results can be
+/// zipped with `keys` to build a map.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let entries = cacache::metadata_many("./my-cache", ["key1", "key2"]).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn metadata_many<P, I, K>(cache: P, keys: I) -> Result<Vec<Option<Metadata>>>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = K>,
+    K: AsRef<str>,
+{
+    let keys = keys.into_iter().collect::<Vec<_>>();
+    let keys = keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+    index::find_many_async(cache.as_ref(), &keys).await
+}
+
+/// Reads the data for many keys, with at most `concurrency` reads in flight
+/// at once. Results are returned in the same order as `keys`, and a failure to
+/// read one key doesn't affect the others. Use this instead of joining
+/// unbounded `read()` calls to avoid running out of file descriptors on large
+/// sets of keys.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let keys = (0..1000).map(|i| format!("key-{i}"));
+///     for data in cacache::read_many("./my-cache", keys, 32).await {
+///         let data: Vec<u8> = data?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn read_many<P, I, K>(cache: P, keys: I, concurrency: usize) -> Vec<Result<Vec<u8>>>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = K>,
+    K: AsRef<str>,
+{
+    read_many_with(cache.as_ref(), keys, concurrency, read).await
+}
+
+/// `read_many()`, with each key read by `read`.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn read_many_with<'a, I, K, F, Fut>(
+    cache: &'a Path,
+    keys: I,
+    concurrency: usize,
+    read: F,
+) -> Vec<Result<Vec<u8>>>
+where
+    I: IntoIterator<Item = K>,
+    F: Fn(&'a Path, K) -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<u8>>>,
+{
+    use futures::stream::StreamExt;
+    let read = &read;
+    let mut results = futures::stream::iter(keys.into_iter().enumerate())
+        .map(|(i, key)| async move { (i, read(cache, key).await) })
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    results.sort_unstable_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, data)| data).collect()
+}
+
+/// Returns true if the given hash exists in the cache. Only the content
+/// file's existence is checked; use `check_hash()` to also verify its data.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn exists<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
-    read::has_content_async(cache.as_ref(), sri).await.is_some()
+    read::has_content_async(cache.as_ref().into(), sri)
+        .await
+        .is_some()
+}
+
+/// Reports whether content for `sri` exists, how big it is, and, if `verify`
+/// is set, whether it still matches its hash. Corrupted content is reported
+/// as `verified: Some(false)` rather than as an error.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///     let health = cacache::stat_hash("./my-cache", &sri, true).await?;
+///     assert_eq!(health.verified, Some(true));
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn stat_hash<P: AsRef<Path>>(
+    cache: P,
+    sri: &Integrity,
+    verify: bool,
+) -> Result<ContentHealth> {
+    let cache = cache.as_ref();
+    Ok(match read::content_size_async(cache.into(), sri).await? {
+        Some(size) => ContentHealth {
+            exists: true,
+            verified: if verify {
+                Some(read::is_intact_async(cache.into(), sri).await?)
+            } else {
+                None
+            },
+            size,
+        },
+        None => ContentHealth::missing(),
+    })
+}
+
+/// Reads through the content for `sri` and checks it against its hash,
+/// without keeping any of the data around. Returns the algorithm it was
+/// verified with, or `Error::ReadIntegrityMismatch` if it's corrupted.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///     let algo = cacache::check_hash("./my-cache", &sri).await?;
+///     assert_eq!(algo, cacache::Algorithm::Sha256);
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn check_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<Algorithm> {
+    read::check_async(cache.as_ref().into(), sri).await
 }
 
 // ---------------
@@ -461,6 +919,8 @@ pub async fn exists<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
 /// verification.
 pub struct SyncReader {
     reader: read::Reader,
+    key: Option<String>,
+    expected_size: Option<u64>,
 }
 
 impl std::io::Read for SyncReader {
@@ -488,7 +948,25 @@ impl SyncReader {
     /// }
     /// ```
     pub fn check(self) -> Result<Algorithm> {
-        self.reader.check()
+        match &self.key {
+            Some(key) => self.reader.check().map_err(|e| e.with_key(key)),
+            None => self.reader.check(),
+        }
+    }
+
+    /// Returns the algorithm `check()` will verify the data with: the
+    /// strongest one in the entry's integrity. This is known as soon as the
+    /// reader is opened, before any data is read.
+    pub fn algorithm(&self) -> Algorithm {
+        self.reader.integrity().pick_algorithm()
+    }
+
+    /// Returns the size of the data as recorded in the index, if this reader
+    /// was opened by key. Handy for progress displays or for pre-sizing
+    /// buffers. Readers opened by hash have no index entry to go by, so this
+    /// is `None` for them.
+    pub fn expected_size(&self) -> Option<u64> {
+        self.expected_size
     }
 
     /// Opens a new synchronous file handle into the cache, looking it up in the
@@ -514,7 +992,10 @@ impl SyncReader {
     {
         fn inner(cache: &Path, key: &str) -> Result<SyncReader> {
             if let Some(entry) = index::find(cache, key)? {
-                SyncReader::open_hash(cache, entry.integrity)
+                let mut reader = SyncReader::open_hash(cache, entry.integrity)?;
+                reader.key = Some(key.into());
+                reader.expected_size = Some(entry.size);
+                Ok(reader)
             } else {
                 Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
             }
@@ -543,14 +1024,95 @@ impl SyncReader {
         P: AsRef<Path>,
     {
         Ok(SyncReader {
-            reader: read::open(cache.as_ref(), sri)?,
+            reader: read::open(cache.as_ref().into(), sri)?,
+            key: None,
+            expected_size: None,
+        })
+    }
+
+    /// Returns how far into the cached data this reader is.
+    pub fn stream_position(&mut self) -> Result<u64> {
+        self.reader
+            .stream_position()
+            .with_context(|| "Failed to get position of cache reader".into())
+    }
+
+    /// Saves how far this reader has got, including the hash of the data
+    /// read so far, so reading can be picked back up later with
+    /// `SyncReader::resume()` and still be checked as a whole.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::io::Read;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::SyncReader::open("./my-cache", "my-key")?;
+    ///     let mut buf = [0; 1024];
+    ///     fd.read_exact(&mut buf).expect("Failed to read");
+    ///     let checkpoint = fd.checkpoint()?;
+    ///     drop(fd);
+    ///
+    ///     let mut fd = cacache::SyncReader::resume("./my-cache", checkpoint)?;
+    ///     let mut rest = Vec::new();
+    ///     fd.read_to_end(&mut rest).expect("Failed to read");
+    ///     fd.check()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn checkpoint(&mut self) -> Result<ReadCheckpoint> {
+        Ok(ReadCheckpoint {
+            position: self.stream_position()?,
+            sri: self.reader.integrity().clone(),
+            hasher: self.reader.hasher().clone(),
+            key: self.key.clone(),
+            expected_size: self.expected_size,
+        })
+    }
+
+    /// Reopens the data a `checkpoint` was taken from, picking up where it
+    /// left off. `check()` verifies all of the data, including what was read
+    /// before the checkpoint was taken.
+    pub fn resume<P>(cache: P, checkpoint: ReadCheckpoint) -> Result<SyncReader>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(SyncReader {
+            reader: read::Reader::resume(
+                cache.as_ref().into(),
+                checkpoint.sri,
+                checkpoint.position,
+                checkpoint.hasher,
+            )?,
+            key: checkpoint.key,
+            expected_size: checkpoint.expected_size,
         })
     }
 }
 
+/// How far a [`SyncReader`] had got, as saved by `SyncReader::checkpoint()`.
+#[derive(Clone, Debug)]
+pub struct ReadCheckpoint {
+    sri: Integrity,
+    key: Option<String>,
+    expected_size: Option<u64>,
+    position: u64,
+    hasher: ssri::IntegrityOpts,
+}
+
+impl ReadCheckpoint {
+    /// Number of bytes that had been read when the checkpoint was taken.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
 /// Reads the entire contents of a cache file synchronously into a bytes
 /// vector, looking the data up by key.
 ///
+/// Data is verified the same way as with [`read_hash_sync`], except that content
+/// shorter than the entry's recorded size fails with `Error::SizeMismatch`
+/// instead, since it was only partially written rather than corrupted.
+///
 /// ## Example
 /// ```no_run
 /// use std::io::Read;
@@ -567,7 +1129,8 @@ where
 {
     fn inner(cache: &Path, key: &str) -> Result<Vec<u8>> {
         if let Some(entry) = index::find(cache, key)? {
-            read_hash_sync(cache, &entry.integrity)
+            read::read_sized(cache.into(), &entry.integrity, entry.size)
+                .map_err(|e| e.with_key(key))
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -575,9 +1138,73 @@ where
     inner(cache.as_ref(), key.as_ref())
 }
 
+/// Reads compressed content out of the cache synchronously by key, and
+/// returns it decompressed. The content is decoded according to the encoding
+/// recorded with `WriteOpts::content_encoding`, and returned as-is if none
+/// was recorded. The content's integrity is checked against the data as
+/// it was stored, before decompressing it. Use [`read_sync`] to get the
+/// compressed bytes themselves.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Write;
+///
+/// fn main() -> cacache::Result<()> {
+///     let tarball = std::fs::read("my-package.tgz").expect("Failed to read tarball");
+///     let mut writer = cacache::WriteOpts::new()
+///         .content_encoding(cacache::ContentEncoding::Gzip)
+///         .open_sync("./my-cache", "my-package.tgz")?;
+///     writer.write_all(&tarball).expect("Failed to write to cache");
+///     writer.commit()?;
+///     let tar = cacache::read_decompressed_sync("./my-cache", "my-package.tgz")?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(feature = "gzip")]
+pub fn read_decompressed_sync<P, K>(cache: P, key: K) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> Result<Vec<u8>> {
+        let entry = index::find(cache, key)?
+            .ok_or_else(|| Error::EntryNotFound(cache.to_path_buf(), key.into()))?;
+        let data = read::read_sized(cache.into(), &entry.integrity, entry.size)
+            .map_err(|e| e.with_key(key))?;
+        decode(cache, key, entry.content_encoding, data)
+    }
+    inner(cache.as_ref(), key.as_ref())
+}
+
+/// Decompresses `data` stored under `key` according to its recorded
+/// `encoding`. Content with no recorded encoding is returned unchanged.
+#[cfg(feature = "gzip")]
+fn decode(
+    cache: &Path,
+    key: &str,
+    encoding: Option<crate::ContentEncoding>,
+    data: Vec<u8>,
+) -> Result<Vec<u8>> {
+    use std::io::Read;
+    let mut decompressed = Vec::new();
+    match encoding {
+        None => return Ok(data),
+        Some(crate::ContentEncoding::Gzip) => flate2::read::GzDecoder::new(&data[..])
+            .read_to_end(&mut decompressed)
+            .with_context(|| {
+                format!("Failed to decompress data for key {key} in cache at {cache:?}")
+            })?,
+    };
+    Ok(decompressed)
+}
+
 /// Reads the entire contents of a cache file synchronously into a bytes
 /// vector, looking the data up by its content address.
 ///
+/// The whole file is checked against `sri` before anything is returned, so
+/// corrupted or truncated content fails with `Error::ReadIntegrityMismatch`
+/// rather than handing back partial or bad data.
+///
 /// ## Example
 /// ```no_run
 /// use std::io::Read;
@@ -592,33 +1219,171 @@ pub fn read_hash_sync<P>(cache: P, sri: &Integrity) -> Result<Vec<u8>>
 where
     P: AsRef<Path>,
 {
-    read::read(cache.as_ref(), sri)
+    read::read(cache.as_ref().into(), sri)
 }
 
-/// Copies a cache entry by key to a specified location. Returns the number of
-/// bytes copied.
+/// Streams the contents of a cache entry into `writer` synchronously,
+/// looking the data up by key, without holding all of it in memory. Returns
+/// the number of bytes written.
 ///
-/// On platforms that support it, this will create a copy-on-write "reflink"
-/// with a full-copy fallback.
+/// The data is only verified once it's all been written, so if this fails
+/// with `Error::ReadIntegrityMismatch` or `Error::SizeMismatch`, whatever
+/// `writer` already received must be thrown away.
 ///
 /// ## Example
 /// ```no_run
-/// use std::io::Read;
-///
 /// fn main() -> cacache::Result<()> {
-///     cacache::copy_sync("./my-cache", "my-key", "./my-hello.txt")?;
+///     let mut out = std::io::stdout();
+///     cacache::read_to_writer_sync("./my-cache", "my-key", &mut out)?;
 ///     Ok(())
 /// }
 /// ```
-pub fn copy_sync<P, K, Q>(cache: P, key: K, to: Q) -> Result<u64>
+pub fn read_to_writer_sync<P, K, W>(cache: P, key: K, mut writer: W) -> Result<u64>
 where
     P: AsRef<Path>,
     K: AsRef<str>,
-    Q: AsRef<Path>,
+    W: Write,
 {
-    fn inner(cache: &Path, key: &str, to: &Path) -> Result<u64> {
-        if let Some(entry) = index::find(cache, key)? {
-            copy_hash_sync(cache, &entry.integrity, to)
+    fn inner(cache: &Path, key: &str, writer: &mut dyn Write) -> Result<u64> {
+        let entry = index::find(cache, key)?
+            .ok_or_else(|| Error::EntryNotFound(cache.to_path_buf(), key.into()))?;
+        let mut reader = SyncReader::open_hash(cache, entry.integrity)?;
+        reader.key = Some(key.into());
+        let written = pump(&mut reader, writer)?;
+        if written < entry.size {
+            return Err(Error::SizeMismatch(entry.size, written));
+        }
+        reader.check()?;
+        Ok(written)
+    }
+    inner(cache.as_ref(), key.as_ref(), &mut writer)
+}
+
+/// Streams the contents of a cache file into `writer` synchronously, looking
+/// the data up by its content address, without holding all of it in memory.
+/// Returns the number of bytes written.
+///
+/// The data is only verified once it's all been written, so if this fails
+/// with `Error::ReadIntegrityMismatch`, whatever `writer` already received
+/// must be thrown away.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     let mut out = std::io::stdout();
+///     cacache::read_hash_to_writer_sync("./my-cache", &sri, &mut out)?;
+///     Ok(())
+/// }
+/// ```
+pub fn read_hash_to_writer_sync<P, W>(cache: P, sri: &Integrity, mut writer: W) -> Result<u64>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut reader = SyncReader::open_hash(cache, sri.clone())?;
+    let written = pump(&mut reader, &mut writer)?;
+    reader.check()?;
+    Ok(written)
+}
+
+fn pump(reader: &mut SyncReader, writer: &mut dyn Write) -> Result<u64> {
+    let written = std::io::copy(reader, writer)
+        .with_context(|| "Failed to stream cache contents to writer".into())?;
+    writer
+        .flush()
+        .with_context(|| "Failed to flush writer".into())?;
+    Ok(written)
+}
+
+/// Reads the entire contents of a cache file synchronously into a bytes
+/// vector, looking `key` up in each of `caches` in order and reading from the
+/// first one that has both an entry and content for it. Useful for layering a
+/// small local cache over a larger, read-only shared one.
+///
+/// ## Example
+/// ```no_run
+/// use std::path::PathBuf;
+///
+/// fn main() -> cacache::Result<()> {
+///     let caches = [PathBuf::from("./local-cache"), PathBuf::from("/mnt/shared-cache")];
+///     let data = cacache::read_chain_sync(&caches, "my-key")?;
+///     Ok(())
+/// }
+/// ```
+pub fn read_chain_sync<P, K>(caches: &[P], key: K) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(caches: &[&Path], key: &str) -> Result<Vec<u8>> {
+        for cache in caches {
+            if let Some(entry) = index::find(cache, key)? {
+                match read::read_sized((*cache).into(), &entry.integrity, entry.size) {
+                    // The entry outlived its content, so try the next cache.
+                    Err(Error::IoError(e, _)) if e.kind() == std::io::ErrorKind::NotFound => {
+                        continue
+                    }
+                    res => return res.map_err(|e| e.with_key(key)),
+                }
+            }
+        }
+        let cache = caches.first().map(|c| c.to_path_buf()).unwrap_or_default();
+        Err(Error::EntryNotFound(cache, key.into()))
+    }
+    let caches: Vec<&Path> = caches.iter().map(|c| c.as_ref()).collect();
+    inner(&caches, key.as_ref())
+}
+
+/// Reads the entire contents of a cache file synchronously into a bytes
+/// vector, looking the data up by its content address, unless it's larger
+/// than `max_bytes`. The size of the content is checked before anything is
+/// read, so unexpectedly large entries fail with `Error::ContentTooLarge`
+/// without being loaded into memory.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Read;
+///
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     let data = cacache::read_hash_limited_sync("./my-cache", &sri, 1024)?;
+///     Ok(())
+/// }
+/// ```
+pub fn read_hash_limited_sync<P>(cache: P, sri: &Integrity, max_bytes: u64) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+{
+    read::read_limited(cache.as_ref().into(), sri, max_bytes)
+}
+
+/// Copies a cache entry by key to a specified location. Returns the number of
+/// bytes copied. Content shorter than the entry's recorded size fails with
+/// `Error::SizeMismatch`.
+///
+/// On platforms that support it, this will create a copy-on-write "reflink"
+/// with a full-copy fallback.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Read;
+///
+/// fn main() -> cacache::Result<()> {
+///     cacache::copy_sync("./my-cache", "my-key", "./my-hello.txt")?;
+///     Ok(())
+/// }
+/// ```
+pub fn copy_sync<P, K, Q>(cache: P, key: K, to: Q) -> Result<u64>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    Q: AsRef<Path>,
+{
+    fn inner(cache: &Path, key: &str, to: &Path) -> Result<u64> {
+        if let Some(entry) = index::find(cache, key)? {
+            read::copy_sized(cache.into(), &entry.integrity, to, entry.size)
+                .map_err(|e| e.with_key(key))
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -678,7 +1443,27 @@ where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::copy(cache.as_ref(), sri, to.as_ref())
+    read::copy(cache.as_ref().into(), sri, to.as_ref())
+}
+
+/// Copies a cache entry by integrity address to a specified location, and
+/// returns its data too. The content is only read from the cache once, and
+/// is verified before anything gets written to `to`.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     let data = cacache::extract_hash_sync("./my-cache", &sri, "./my-hello.txt")?;
+///     Ok(())
+/// }
+/// ```
+pub fn extract_hash_sync<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    read::extract(cache.as_ref().into(), sri, to.as_ref())
 }
 
 /// Copies a cache entry by integrity address to a specified location. Does
@@ -702,7 +1487,7 @@ where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::copy_unchecked(cache.as_ref(), sri, to.as_ref())
+    read::copy_unchecked(cache.as_ref().into(), sri, to.as_ref())
 }
 
 /// Creates a reflink/clonefile from a cache entry to a destination path.
@@ -715,11 +1500,7 @@ where
 ///
 /// ## Example
 /// ```no_run
-/// use async_std::prelude::*;
-/// use async_attributes;
-///
-/// #[async_attributes::main]
-/// async fn main() -> cacache::Result<()> {
+/// fn main() -> cacache::Result<()> {
 ///     cacache::reflink_sync("./my-cache", "my-key", "./data.txt")?;
 ///     Ok(())
 /// }
@@ -732,7 +1513,8 @@ where
 {
     fn inner(cache: &Path, key: &str, to: &Path) -> Result<()> {
         if let Some(entry) = index::find(cache, key)? {
-            reflink_hash_sync(cache, &entry.integrity, to)
+            read::reflink_sized(cache.into(), &entry.integrity, to, entry.size)
+                .map_err(|e| e.with_key(key))
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -750,11 +1532,7 @@ where
 ///
 /// ## Example
 /// ```no_run
-/// use async_std::prelude::*;
-/// use async_attributes;
-///
-/// #[async_attributes::main]
-/// async fn main() -> cacache::Result<()> {
+/// fn main() -> cacache::Result<()> {
 ///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello world")?;
 ///     cacache::reflink_hash_sync("./my-cache", &sri, "./data.txt")?;
 ///     Ok(())
@@ -765,7 +1543,7 @@ where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::reflink(cache.as_ref(), sri, to.as_ref())
+    read::reflink(cache.as_ref().into(), sri, to.as_ref())
 }
 
 /// Reflinks/clonefiles cache data by hash to a specified location. Cache data
@@ -779,11 +1557,7 @@ where
 ///
 /// ## Example
 /// ```no_run
-/// use async_std::prelude::*;
-/// use async_attributes;
-///
-/// #[async_attributes::main]
-/// async fn main() -> cacache::Result<()> {
+/// fn main() -> cacache::Result<()> {
 ///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello world")?;
 ///     cacache::reflink_hash_unchecked_sync("./my-cache", &sri, "./data.txt")?;
 ///     Ok(())
@@ -794,7 +1568,7 @@ where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::reflink_unchecked(cache.as_ref(), sri, to.as_ref())
+    read::reflink_unchecked(cache.as_ref().into(), sri, to.as_ref())
 }
 
 /// Reflinks/clonefiles cache data to a specified location. Cache data will
@@ -808,11 +1582,7 @@ where
 ///
 /// ## Example
 /// ```no_run
-/// use async_std::prelude::*;
-/// use async_attributes;
-///
-/// #[async_attributes::main]
-/// async fn main() -> cacache::Result<()> {
+/// fn main() -> cacache::Result<()> {
 ///     cacache::reflink_unchecked_sync("./my-cache", "my-key", "./data.txt")?;
 ///     Ok(())
 /// }
@@ -833,6 +1603,27 @@ where
     inner(cache.as_ref(), key.as_ref(), to.as_ref())
 }
 
+/// Checks whether the filesystem the cache lives on supports reflinks, by
+/// attempting to reflink a small temporary file inside `{cache}/tmp`. The
+/// result is remembered for the lifetime of the process, so this is cheap to
+/// call repeatedly. Use it to decide up front between reflinking and copying
+/// cache data.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     if cacache::supports_reflink("./my-cache") {
+///         cacache::reflink_sync("./my-cache", "my-key", "./data.txt")?;
+///     } else {
+///         cacache::copy_sync("./my-cache", "my-key", "./data.txt")?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn supports_reflink<P: AsRef<Path>>(cache: P) -> bool {
+    read::supports_reflink(cache.as_ref())
+}
+
 /// Hard links a cache entry by key to a specified location. The cache entry
 /// contents will not be checked, and all the usual caveats of hard links
 /// apply: The potentially-shared cache might be corrupted if the hard link is
@@ -862,7 +1653,7 @@ where
 {
     fn inner(cache: &Path, key: &str, to: &Path) -> Result<()> {
         if let Some(entry) = index::find(cache, key)? {
-            read::hard_link(cache, &entry.integrity, to)
+            read::hard_link(cache.into(), &entry.integrity, to)
         } else {
             Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
         }
@@ -877,7 +1668,7 @@ where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::hard_link(cache.as_ref(), sri, to.as_ref())
+    read::hard_link(cache.as_ref().into(), sri, to.as_ref())
 }
 
 /// Hard links a cache entry by integrity address to a specified location. The
@@ -889,14 +1680,15 @@ where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    read::hard_link_unchecked(cache.as_ref(), sri, to.as_ref())
+    read::hard_link_unchecked(cache.as_ref().into(), sri, to.as_ref())
 }
 
 /// Gets metadata for a certain key.
 ///
 /// Note that the existence of a metadata entry is not a guarantee that the
 /// underlying data exists, since they are stored and managed independently.
-/// To verify that the underlying associated data exists, use `exists_sync()`.
+/// To verify that the underlying associated data exists, use `exists_sync()`,
+/// or `metadata_checked_sync()` to do both at once.
 pub fn metadata_sync<P, K>(cache: P, key: K) -> Result<Option<Metadata>>
 where
     P: AsRef<Path>,
@@ -905,79 +1697,649 @@ where
     index::find(cache.as_ref(), key.as_ref())
 }
 
-/// Returns true if the given hash exists in the cache.
-pub fn exists_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
-    read::has_content(cache.as_ref(), sri).is_some()
-}
-
-#[cfg(test)]
-mod tests {
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
-    use crate::async_lib::AsyncReadExt;
-    use std::fs;
-
-    #[cfg(feature = "async-std")]
-    use async_attributes::test as async_test;
-    #[cfg(feature = "tokio")]
-    use tokio::test as async_test;
-
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
-    #[async_test]
-    async fn test_open() {
-        let tmp = tempfile::tempdir().unwrap();
-        let dir = tmp.path().to_owned();
-        crate::write(&dir, "my-key", b"hello world").await.unwrap();
-
-        let mut handle = crate::Reader::open(&dir, "my-key").await.unwrap();
-        let mut str = String::new();
-        handle.read_to_string(&mut str).await.unwrap();
-        handle.check().unwrap();
-        assert_eq!(str, String::from("hello world"));
+/// Gets metadata for a certain key synchronously, but only if its content
+/// is also in the cache. An index entry whose content has gone missing is
+/// treated the same as no entry at all, so this answers whether `key` can
+/// actually be read.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     if let Some(entry) = cacache::metadata_checked_sync("./my-cache", "my-key")? {
+///         println!("{} bytes cached", entry.size);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn metadata_checked_sync<P, K>(cache: P, key: K) -> Result<Option<Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> Result<Option<Metadata>> {
+        match index::find(cache, key)? {
+            Some(entry) if read::has_content(cache.into(), &entry.integrity).is_some() => {
+                Ok(Some(entry))
+            }
+            _ => Ok(None),
+        }
     }
+    inner(cache.as_ref(), key.as_ref())
+}
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
-    #[async_test]
-    async fn test_open_hash() {
-        let tmp = tempfile::tempdir().unwrap();
-        let dir = tmp.path().to_owned();
-        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+/// Gets the metadata entries for many keys at once, synchronously, in the
+/// same order as `keys`. Each index bucket is only read once, which is much
+/// cheaper than calling `metadata_sync()` for each key. Keys without an entry
+/// come back as `None`, so the results can be zipped with `keys` to build a
+/// map.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let entries = cacache::metadata_many_sync("./my-cache", ["key1", "key2"])?;
+///     Ok(())
+/// }
+/// ```
+pub fn metadata_many_sync<P, I, K>(cache: P, keys: I) -> Result<Vec<Option<Metadata>>>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = K>,
+    K: AsRef<str>,
+{
+    let keys = keys.into_iter().collect::<Vec<_>>();
+    let keys = keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+    index::find_many(cache.as_ref(), &keys)
+}
 
-        let mut handle = crate::Reader::open_hash(&dir, sri).await.unwrap();
-        let mut str = String::new();
-        handle.read_to_string(&mut str).await.unwrap();
-        handle.check().unwrap();
-        assert_eq!(str, String::from("hello world"));
+/// Re-reads the data for a key and checks it against the SHA-256 hash
+/// recorded in its index entry, regardless of which algorithm the content is
+/// stored and normally checked under. Use this with entries written using
+/// `WriteOpts::extra_algorithm(Algorithm::Sha256)` to cryptographically audit
+/// content stored under a fast, non-cryptographic hash like xxh3.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::WriteOpts::new()
+///         .algorithm(cacache::Algorithm::Xxh3)
+///         .extra_algorithm(cacache::Algorithm::Sha256)
+///         .open_sync("./my-cache", "my-key")?
+///         .commit()?;
+///     cacache::verify_crypto_sync("./my-cache", "my-key")?;
+///     Ok(())
+/// }
+/// ```
+pub fn verify_crypto_sync<P, K>(cache: P, key: K) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> Result<()> {
+        let entry = index::find(cache, key)?
+            .ok_or_else(|| Error::EntryNotFound(cache.to_path_buf(), key.into()))?;
+        let hashes = entry
+            .integrity
+            .hashes
+            .iter()
+            .filter(|hash| hash.algorithm == Algorithm::Sha256)
+            .cloned()
+            .collect::<Vec<_>>();
+        if hashes.is_empty() {
+            return Err(Error::MissingHash(
+                cache.to_path_buf(),
+                key.into(),
+                Algorithm::Sha256,
+            ));
+        }
+        read::check_with(cache.into(), &entry.integrity, Integrity { hashes })
+            .map_err(|e| e.with_key(key))?;
+        Ok(())
     }
+    inner(cache.as_ref(), key.as_ref())
+}
 
-    #[test]
-    fn test_open_sync() {
-        use std::io::prelude::*;
-        let tmp = tempfile::tempdir().unwrap();
-        let dir = tmp.path().to_owned();
-        crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+/// Returns true if the given hash exists in the cache. Only the content
+/// file's existence is checked; use `exists_valid_sync()` or
+/// `check_hash_sync()` to also look at its data.
+pub fn exists_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
+    read::has_content(cache.as_ref().into(), sri).is_some()
+}
 
-        let mut handle = crate::SyncReader::open(&dir, "my-key").unwrap();
-        let mut str = String::new();
-        handle.read_to_string(&mut str).unwrap();
-        handle.check().unwrap();
-        assert_eq!(str, String::from("hello world"));
-    }
+/// Checks which of the given hashes exist in the cache, returning one result
+/// per hash, in the same order. When many of them share content directories,
+/// this makes far fewer syscalls than calling `exists_sync()` for each.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let hello = cacache::write_hash_sync("./my-cache", b"hello")?;
+///     let world = cacache::write_hash_sync("./my-cache", b"world")?;
+///     let found = cacache::exists_many_sync("./my-cache", &[hello, world]);
+///     assert_eq!(found, [true, true]);
+///     Ok(())
+/// }
+/// ```
+pub fn exists_many_sync<P: AsRef<Path>>(cache: P, sris: &[Integrity]) -> Vec<bool> {
+    read::has_content_many(cache.as_ref().into(), sris)
+}
 
-    #[test]
-    fn test_open_hash_sync() {
-        use std::io::prelude::*;
-        let tmp = tempfile::tempdir().unwrap();
-        let dir = tmp.path().to_owned();
-        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+/// Returns true if the given hash exists in the cache and its content looks
+/// usable. Unlike `exists_sync()`, this rejects empty content files left
+/// behind by a crash, and re-hashes content up to 1MiB in size to make sure
+/// it hasn't been corrupted. Larger content is only checked for existence.
+///
+/// To verify content of any size, use `check_hash_sync()`, which reads all
+/// of it and reports why it failed, or `stat_hash_sync()`, which also reports
+/// its size.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     assert!(cacache::exists_valid_sync("./my-cache", &sri));
+///     Ok(())
+/// }
+/// ```
+pub fn exists_valid_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
+    read::has_valid_content(cache.as_ref().into(), sri)
+}
 
-        let mut handle = crate::SyncReader::open_hash(&dir, sri).unwrap();
-        let mut str = String::new();
-        handle.read_to_string(&mut str).unwrap();
-        handle.check().unwrap();
-        assert_eq!(str, String::from("hello world"));
+/// Returns true if there's an entry for `key` and its content looks usable,
+/// as with `exists_valid_sync()`. The content's length is also checked
+/// against the size recorded in the entry, so truncated content is caught
+/// no matter how large it is.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     assert!(cacache::exists_valid_entry_sync("./my-cache", "my-key"));
+///     Ok(())
+/// }
+/// ```
+pub fn exists_valid_entry_sync<P, K>(cache: P, key: K) -> bool
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> bool {
+        match index::find(cache, key) {
+            Ok(Some(entry)) => {
+                read::has_valid_sized_content(cache.into(), &entry.integrity, entry.size)
+            }
+            _ => false,
+        }
     }
+    inner(cache.as_ref(), key.as_ref())
+}
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+/// Returns the path content for `sri` is stored at, without touching the
+/// filesystem. If `sri` carries more than one hash, the path is for the
+/// first of them. Like the rest of the free functions, this assumes the
+/// default layout, so it doesn't apply to caches with a `CacheConfig`
+/// that moves their content directory.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     println!("stored at {}", cacache::content_path("./my-cache", &sri).display());
+///     Ok(())
+/// }
+/// ```
+pub fn content_path<P: AsRef<Path>>(cache: P, sri: &Integrity) -> PathBuf {
+    crate::content::path::content_path(cache.as_ref().into(), sri)
+}
+
+/// Reports whether content for `sri` exists, how big it is, and, if `verify`
+/// is set, whether it still matches its hash. Corrupted content is reported
+/// as `verified: Some(false)` rather than as an error.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     let health = cacache::stat_hash_sync("./my-cache", &sri, true)?;
+///     assert_eq!(health.verified, Some(true));
+///     Ok(())
+/// }
+/// ```
+pub fn stat_hash_sync<P: AsRef<Path>>(
+    cache: P,
+    sri: &Integrity,
+    verify: bool,
+) -> Result<ContentHealth> {
+    let cache = cache.as_ref();
+    Ok(match read::content_size(cache.into(), sri)? {
+        Some(size) => ContentHealth {
+            exists: true,
+            verified: if verify {
+                Some(read::is_intact(cache.into(), sri)?)
+            } else {
+                None
+            },
+            size,
+        },
+        None => ContentHealth::missing(),
+    })
+}
+
+/// Reads through the content for `sri` and checks it against its hash,
+/// without keeping any of the data around. Returns the algorithm it was
+/// verified with, or `Error::ReadIntegrityMismatch` if it's corrupted.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     let algo = cacache::check_hash_sync("./my-cache", &sri)?;
+///     assert_eq!(algo, cacache::Algorithm::Sha256);
+///     Ok(())
+/// }
+/// ```
+pub fn check_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<Algorithm> {
+    read::check(cache.as_ref().into(), sri)
+}
+
+/// State of a single piece of content, as reported by `stat_hash()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentHealth {
+    /// Whether there's any content for the hash in the cache.
+    pub exists: bool,
+    /// Whether the content matched its hash, or `None` if it wasn't checked,
+    /// either because verifying wasn't asked for or there was no content.
+    pub verified: Option<bool>,
+    /// Size of the content in bytes, or 0 if there isn't any.
+    pub size: u64,
+}
+
+impl ContentHealth {
+    fn missing() -> Self {
+        ContentHealth {
+            exists: false,
+            verified: None,
+            size: 0,
+        }
+    }
+}
+
+/// Builder for options and flags for copying or reflinking cache data out
+/// to another location.
+#[derive(Clone, Default)]
+pub struct CopyOpts {
+    pub(crate) atomic: bool,
+    pub(crate) mode: Option<u32>,
+}
+
+impl CopyOpts {
+    /// Creates a blank set of copy options.
+    pub fn new() -> CopyOpts {
+        Default::default()
+    }
+
+    /// If `atomic` is true, data is first written to a temporary file next to
+    /// the destination, and only renamed into place once it's been fully
+    /// written and its integrity verified. Other processes watching the
+    /// destination will never observe a partially-written file.
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Sets the permission bits of the destination file, such as `0o755` for
+    /// a cached binary that needs to be executable. By default, it gets
+    /// whatever permissions copying or reflinking gives it. With `atomic`,
+    /// the mode is set before the file is moved into place. Ignored on
+    /// platforms other than unix.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Copies cache data to a specified location, looking it up by key.
+    /// Returns the number of bytes copied.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_std::prelude::*;
+    /// use async_attributes;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     cacache::CopyOpts::new()
+    ///         .atomic(true)
+    ///         .copy("./my-cache", "my-key", "./data.txt")
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn copy<P, K, Q>(self, cache: P, key: K, to: Q) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        K: AsRef<str>,
+        Q: AsRef<Path>,
+    {
+        async fn inner(me: CopyOpts, cache: &Path, key: &str, to: &Path) -> Result<u64> {
+            if let Some(entry) = index::find_async(cache, key).await? {
+                me.copy_sized(cache, &entry.integrity, to, entry.size).await
+            } else {
+                Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
+            }
+        }
+        inner(self, cache.as_ref(), key.as_ref(), to.as_ref()).await
+    }
+
+    /// Copies cache data to a specified location, looking it up by its
+    /// content address. Returns the number of bytes copied.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn copy_hash<P, Q>(self, cache: P, sri: &Integrity, to: Q) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.copy_sized(cache.as_ref(), sri, to.as_ref(), 0).await
+    }
+
+    /// Copies the content for `sri`, checking it against `size` if that's
+    /// known (non-zero).
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    async fn copy_sized(self, cache: &Path, sri: &Integrity, to: &Path, size: u64) -> Result<u64> {
+        if self.atomic {
+            read::copy_atomic_async(cache.into(), sri, to, size, self.mode).await
+        } else {
+            let size = read::copy_sized_async(cache.into(), sri, to, size).await?;
+            read::set_mode(to, self.mode)?;
+            Ok(size)
+        }
+    }
+
+    /// Reflinks/clonefiles cache data to a specified location, looking it up
+    /// by key.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn reflink<P, K, Q>(self, cache: P, key: K, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        K: AsRef<str>,
+        Q: AsRef<Path>,
+    {
+        async fn inner(me: CopyOpts, cache: &Path, key: &str, to: &Path) -> Result<()> {
+            if let Some(entry) = index::find_async(cache, key).await? {
+                me.reflink_sized(cache, &entry.integrity, to, entry.size)
+                    .await
+            } else {
+                Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
+            }
+        }
+        inner(self, cache.as_ref(), key.as_ref(), to.as_ref()).await
+    }
+
+    /// Reflinks/clonefiles cache data to a specified location, looking it up
+    /// by its content address.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn reflink_hash<P, Q>(self, cache: P, sri: &Integrity, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.reflink_sized(cache.as_ref(), sri, to.as_ref(), 0)
+            .await
+    }
+
+    /// Reflinks the content for `sri`, checking it against `size` if that's
+    /// known (non-zero).
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    async fn reflink_sized(
+        self,
+        cache: &Path,
+        sri: &Integrity,
+        to: &Path,
+        size: u64,
+    ) -> Result<()> {
+        if self.atomic {
+            read::reflink_atomic_async(cache.into(), sri, to, size, self.mode).await
+        } else {
+            read::reflink_sized_async(cache.into(), sri, to, size).await?;
+            read::set_mode(to, self.mode)
+        }
+    }
+
+    /// Copies cache data to a specified location synchronously, looking it
+    /// up by key. Returns the number of bytes copied.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// fn main() -> cacache::Result<()> {
+    ///     cacache::CopyOpts::new()
+    ///         .atomic(true)
+    ///         .copy_sync("./my-cache", "my-key", "./data.txt")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn copy_sync<P, K, Q>(self, cache: P, key: K, to: Q) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        K: AsRef<str>,
+        Q: AsRef<Path>,
+    {
+        fn inner(me: CopyOpts, cache: &Path, key: &str, to: &Path) -> Result<u64> {
+            if let Some(entry) = index::find(cache, key)? {
+                me.copy_sized_sync(cache, &entry.integrity, to, entry.size)
+            } else {
+                Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
+            }
+        }
+        inner(self, cache.as_ref(), key.as_ref(), to.as_ref())
+    }
+
+    /// Copies cache data to a specified location synchronously, looking it
+    /// up by its content address. Returns the number of bytes copied.
+    pub fn copy_hash_sync<P, Q>(self, cache: P, sri: &Integrity, to: Q) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.copy_sized_sync(cache.as_ref(), sri, to.as_ref(), 0)
+    }
+
+    /// Copies the content for `sri`, checking it against `size` if that's
+    /// known (non-zero).
+    fn copy_sized_sync(self, cache: &Path, sri: &Integrity, to: &Path, size: u64) -> Result<u64> {
+        if self.atomic {
+            read::copy_atomic(cache.into(), sri, to, size, self.mode)
+        } else {
+            let size = read::copy_sized(cache.into(), sri, to, size)?;
+            read::set_mode(to, self.mode)?;
+            Ok(size)
+        }
+    }
+
+    /// Reflinks/clonefiles cache data to a specified location synchronously,
+    /// looking it up by key.
+    pub fn reflink_sync<P, K, Q>(self, cache: P, key: K, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        K: AsRef<str>,
+        Q: AsRef<Path>,
+    {
+        fn inner(me: CopyOpts, cache: &Path, key: &str, to: &Path) -> Result<()> {
+            if let Some(entry) = index::find(cache, key)? {
+                me.reflink_sized_sync(cache, &entry.integrity, to, entry.size)
+            } else {
+                Err(Error::EntryNotFound(cache.to_path_buf(), key.into()))
+            }
+        }
+        inner(self, cache.as_ref(), key.as_ref(), to.as_ref())
+    }
+
+    /// Reflinks/clonefiles cache data to a specified location synchronously,
+    /// looking it up by its content address.
+    pub fn reflink_hash_sync<P, Q>(self, cache: P, sri: &Integrity, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.reflink_sized_sync(cache.as_ref(), sri, to.as_ref(), 0)
+    }
+
+    /// Reflinks the content for `sri`, checking it against `size` if that's
+    /// known (non-zero).
+    fn reflink_sized_sync(self, cache: &Path, sri: &Integrity, to: &Path, size: u64) -> Result<()> {
+        if self.atomic {
+            read::reflink_atomic(cache.into(), sri, to, size, self.mode)
+        } else {
+            read::reflink_sized(cache.into(), sri, to, size)?;
+            read::set_mode(to, self.mode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    use crate::async_lib::AsyncReadExt;
+    use std::fs;
+
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
+    use async_attributes::test as async_test;
+    #[cfg(feature = "tokio")]
+    use tokio::test as async_test;
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "my-key", b"hello world").await.unwrap();
+
+        let mut handle = crate::Reader::open(&dir, "my-key").await.unwrap();
+        assert_eq!(handle.expected_size(), Some(11));
+        let mut str = String::new();
+        handle.read_to_string(&mut str).await.unwrap();
+        handle.check().unwrap();
+        assert_eq!(str, String::from("hello world"));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_open_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+
+        let mut handle = crate::Reader::open_hash(&dir, sri).await.unwrap();
+        assert_eq!(handle.expected_size(), None);
+        let mut str = String::new();
+        handle.read_to_string(&mut str).await.unwrap();
+        handle.check().unwrap();
+        assert_eq!(str, String::from("hello world"));
+    }
+
+    #[test]
+    fn test_open_sync() {
+        use std::io::prelude::*;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+
+        let mut handle = crate::SyncReader::open(&dir, "my-key").unwrap();
+        assert_eq!(handle.expected_size(), Some(11));
+        let mut str = String::new();
+        handle.read_to_string(&mut str).unwrap();
+        handle.check().unwrap();
+        assert_eq!(str, String::from("hello world"));
+    }
+
+    #[test]
+    fn test_open_hash_sync() {
+        use std::io::prelude::*;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+
+        let mut handle = crate::SyncReader::open_hash(&dir, sri).unwrap();
+        assert_eq!(handle.expected_size(), None);
+        let mut str = String::new();
+        handle.read_to_string(&mut str).unwrap();
+        handle.check().unwrap();
+        assert_eq!(str, String::from("hello world"));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_reader_algorithm() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_with_algo(crate::Algorithm::Xxh3, &dir, "my-key", b"hello world")
+            .await
+            .unwrap();
+
+        let handle = crate::Reader::open(&dir, "my-key").await.unwrap();
+        assert_eq!(handle.algorithm(), crate::Algorithm::Xxh3);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_metadata_checked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert!(crate::metadata_checked(&dir, "my-key")
+            .await
+            .unwrap()
+            .is_none());
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+        let entry = crate::metadata_checked(&dir, "my-key")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.integrity, sri);
+
+        crate::remove_hash(&dir, &sri).await.unwrap();
+        assert!(crate::metadata(&dir, "my-key").await.unwrap().is_some());
+        assert!(crate::metadata_checked(&dir, "my-key")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_metadata_checked_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert!(crate::metadata_checked_sync(&dir, "my-key")
+            .unwrap()
+            .is_none());
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+        let entry = crate::metadata_checked_sync(&dir, "my-key")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.integrity, sri);
+
+        crate::remove_hash_sync(&dir, &sri).unwrap();
+        assert!(crate::metadata_sync(&dir, "my-key").unwrap().is_some());
+        assert!(crate::metadata_checked_sync(&dir, "my-key")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_reader_algorithm_sync() {
+        use std::io::prelude::*;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync_with_algo(crate::Algorithm::Xxh3, &dir, "my-key", b"hello world")
+            .unwrap();
+
+        let mut handle = crate::SyncReader::open(&dir, "my-key").unwrap();
+        assert_eq!(handle.algorithm(), crate::Algorithm::Xxh3);
+        let mut str = String::new();
+        handle.read_to_string(&mut str).unwrap();
+        assert_eq!(handle.check().unwrap(), crate::Algorithm::Xxh3);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_read() {
         let tmp = tempfile::tempdir().unwrap();
@@ -988,7 +2350,7 @@ mod tests {
         assert_eq!(data, b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_read_hash() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1019,7 +2381,7 @@ mod tests {
         assert_eq!(data, b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_copy() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1032,7 +2394,7 @@ mod tests {
         assert_eq!(data, b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_copy_hash() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1045,6 +2407,725 @@ mod tests {
         assert_eq!(data, b"hello world");
     }
 
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_copy_opts_sized() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("truncated");
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+        fs::write(crate::content_path(dir, &sri), b"hello").unwrap();
+
+        for atomic in [false, true] {
+            let opts = || crate::CopyOpts::new().atomic(atomic);
+            assert!(matches!(
+                opts().copy(dir, "my-key", &dest).await,
+                Err(crate::Error::SizeMismatch(11, 5))
+            ));
+            assert!(matches!(
+                opts().reflink(dir, "my-key", &dest).await,
+                Err(crate::Error::SizeMismatch(11, 5))
+            ));
+        }
+        assert!(!dest.exists());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_read_hash_limited() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+
+        let data = crate::read_hash_limited(&dir, &sri, 11).await.unwrap();
+        assert_eq!(data, b"hello world");
+        assert!(matches!(
+            crate::read_hash_limited(&dir, &sri, 10).await,
+            Err(crate::Error::ContentTooLarge(10, 11))
+        ));
+    }
+
+    #[test]
+    fn test_read_hash_limited_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+
+        let data = crate::read_hash_limited_sync(&dir, &sri, 11).unwrap();
+        assert_eq!(data, b"hello world");
+        assert!(matches!(
+            crate::read_hash_limited_sync(&dir, &sri, 10),
+            Err(crate::Error::ContentTooLarge(10, 11))
+        ));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_read_many() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let keys = (0..300).map(|i| format!("key-{i}")).collect::<Vec<_>>();
+        for key in &keys {
+            crate::write_sync(&dir, key, key.as_bytes()).unwrap();
+        }
+        let mut wanted = keys.clone();
+        wanted.push("missing".into());
+        let results = crate::read_many(&dir, &wanted, 4).await;
+        assert_eq!(results.len(), wanted.len());
+        for (key, data) in keys.iter().zip(&results) {
+            assert_eq!(data.as_ref().unwrap(), key.as_bytes());
+        }
+        assert!(matches!(
+            results.last().unwrap(),
+            Err(crate::Error::EntryNotFound(_, _))
+        ));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_read_many_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::task::Poll;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let keys = (0..50).map(|i| format!("key-{i}")).collect::<Vec<_>>();
+        for key in &keys {
+            crate::write_sync(&dir, key, key.as_bytes()).unwrap();
+        }
+        let in_flight = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let results = super::read_many_with(&dir, &keys, 4, |cache, key| {
+            let (in_flight, most) = (&in_flight, &most);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                // Give the other reads a chance to start before this one ends.
+                let mut yielded = false;
+                futures::future::poll_fn(|cx| {
+                    if yielded {
+                        Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                })
+                .await;
+                let data = crate::read(cache, key).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                data
+            }
+        })
+        .await;
+        assert_eq!(most.load(Ordering::SeqCst), 4);
+        for (key, data) in keys.iter().zip(&results) {
+            assert_eq!(data.as_ref().unwrap(), key.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_exists_many_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut sris = (0..300)
+            .map(|i| crate::write_hash_sync(&dir, format!("hello {i}")).unwrap())
+            .collect::<Vec<_>>();
+        let missing = crate::Integrity::from(b"missing");
+        sris.insert(150, missing.clone());
+        // Only the sha256 hash of this one is stored.
+        let dual = ssri::IntegrityOpts::new()
+            .algorithm(crate::Algorithm::Xxh3)
+            .algorithm(crate::Algorithm::Sha256)
+            .chain(b"dual")
+            .result();
+        crate::write_hash_sync(&dir, b"dual").unwrap();
+        sris.push(dual);
+        // Repeats share a directory, so they're looked up in its listing.
+        for _ in 0..8 {
+            sris.push(sris[0].clone());
+            sris.push(missing.clone());
+        }
+        let found = crate::exists_many_sync(&dir, &sris);
+        assert_eq!(found.len(), sris.len());
+        for (sri, found) in sris.iter().zip(found) {
+            assert_eq!(found, *sri != missing, "{sri}");
+            assert_eq!(found, crate::exists_sync(&dir, sri));
+        }
+    }
+
+    #[test]
+    fn test_exists_valid_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let empty = crate::write_sync(&dir, "empty", b"").unwrap();
+        assert!(crate::exists_valid_sync(&dir, &sri));
+        assert!(crate::exists_valid_sync(&dir, &empty));
+
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, b"").unwrap();
+        assert!(crate::exists_sync(&dir, &sri));
+        assert!(!crate::exists_valid_sync(&dir, &sri));
+        fs::write(&cpath, b"jello").unwrap();
+        assert!(!crate::exists_valid_sync(&dir, &sri));
+        fs::remove_file(&cpath).unwrap();
+        assert!(!crate::exists_valid_sync(&dir, &sri));
+    }
+
+    #[test]
+    fn test_exists_valid_entry_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let data = vec![b'x'; 2 * 1024 * 1024];
+        let sri = crate::write_sync(&dir, "big", &data).unwrap();
+        assert!(crate::exists_valid_entry_sync(&dir, "big"));
+        assert!(!crate::exists_valid_entry_sync(&dir, "missing"));
+
+        // Too large to be re-hashed, so only the recorded size catches this.
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, &data[..1024 * 1024 + 1]).unwrap();
+        assert!(crate::exists_valid_sync(&dir, &sri));
+        assert!(!crate::exists_valid_entry_sync(&dir, "big"));
+    }
+
+    #[test]
+    fn test_read_to_writer_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let mut out = Vec::new();
+        let written = crate::read_to_writer_sync(&dir, "hello", &mut out).unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(out, b"hello");
+        assert!(sri.check(&out).is_ok());
+
+        let mut out = Vec::new();
+        crate::read_hash_to_writer_sync(&dir, &sri, &mut out).unwrap();
+        assert_eq!(out, b"hello");
+
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, b"jello").unwrap();
+        let err = crate::read_to_writer_sync(&dir, "hello", Vec::new()).unwrap_err();
+        assert!(matches!(err, crate::Error::ReadIntegrityMismatch(..)));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_read_to_writer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "hello", b"hello").await.unwrap();
+        let mut out = Vec::new();
+        let written = crate::read_to_writer(&dir, "hello", &mut out)
+            .await
+            .unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(out, b"hello");
+        assert!(sri.check(&out).is_ok());
+
+        let mut out = Vec::new();
+        crate::read_hash_to_writer(&dir, &sri, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, b"hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_only_cache() {
+        use std::io::Read;
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let list = || {
+            walkdir::WalkDir::new(&dir)
+                .into_iter()
+                .map(|entry| entry.unwrap().into_path())
+                .collect::<Vec<_>>()
+        };
+        let before = list();
+        let set_mode = |mode| {
+            for entry in walkdir::WalkDir::new(&dir) {
+                let entry = entry.unwrap();
+                if entry.file_type().is_dir() {
+                    let perms = fs::Permissions::from_mode(mode);
+                    fs::set_permissions(entry.path(), perms).unwrap();
+                }
+            }
+        };
+        set_mode(0o555);
+
+        let read = || -> crate::Result<()> {
+            assert!(crate::metadata_sync(&dir, "hello")?.is_some());
+            assert_eq!(crate::read_sync(&dir, "hello")?, b"hello");
+            assert_eq!(crate::read_hash_sync(&dir, &sri)?, b"hello");
+            let mut reader = crate::SyncReader::open(&dir, "hello")?;
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            reader.check()?;
+            assert!(crate::exists_sync(&dir, &sri));
+            Ok(())
+        };
+        let result = read();
+        set_mode(0o755);
+        result.unwrap();
+        assert_eq!(list(), before);
+    }
+
+    #[test]
+    fn test_stat_hash_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let health = crate::stat_hash_sync(&dir, &sri, false).unwrap();
+        assert!(health.exists);
+        assert_eq!(health.verified, None);
+        assert_eq!(health.size, 5);
+        let health = crate::stat_hash_sync(&dir, &sri, true).unwrap();
+        assert_eq!(health.verified, Some(true));
+
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, b"jello").unwrap();
+        let health = crate::stat_hash_sync(&dir, &sri, true).unwrap();
+        assert!(health.exists);
+        assert_eq!(health.verified, Some(false));
+        fs::remove_file(&cpath).unwrap();
+        let health = crate::stat_hash_sync(&dir, &sri, true).unwrap();
+        assert!(!health.exists);
+        assert_eq!(health.verified, None);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_stat_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "hello", b"hello").await.unwrap();
+        let health = crate::stat_hash(&dir, &sri, true).await.unwrap();
+        assert!(health.exists);
+        assert_eq!(health.verified, Some(true));
+        assert_eq!(health.size, 5);
+
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, b"jello").unwrap();
+        let health = crate::stat_hash(&dir, &sri, true).await.unwrap();
+        assert_eq!(health.verified, Some(false));
+    }
+
+    #[test]
+    fn test_content_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let path = crate::content_path(&dir, &sri);
+        assert!(path.starts_with(dir.join("content-v2").join("sha256")));
+        assert_eq!(fs::read(path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_check_hash_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        assert_eq!(
+            crate::check_hash_sync(&dir, &sri).unwrap(),
+            ssri::Algorithm::Sha256
+        );
+
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, b"jello").unwrap();
+        assert!(matches!(
+            crate::check_hash_sync(&dir, &sri),
+            Err(crate::Error::ReadIntegrityMismatch(..))
+        ));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_check_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "hello", b"hello").await.unwrap();
+        assert_eq!(
+            crate::check_hash(&dir, &sri).await.unwrap(),
+            ssri::Algorithm::Sha256
+        );
+
+        let cpath = crate::content_path(&dir, &sri);
+        fs::write(&cpath, b"jello").unwrap();
+        assert!(matches!(
+            crate::check_hash(&dir, &sri).await,
+            Err(crate::Error::ReadIntegrityMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn test_verify_crypto_sync() {
+        use crate::Error;
+        use ssri::{Algorithm, Integrity};
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .algorithm(Algorithm::Xxh3)
+            .extra_algorithm(Algorithm::Sha256)
+            .open_sync(&dir, "dual")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        let sri = writer.commit().unwrap();
+        assert_eq!(sri.hashes.len(), 2);
+        let xxh3 = Integrity {
+            hashes: vec![sri.hashes[1].clone()],
+        };
+        assert_eq!(xxh3.pick_algorithm(), Algorithm::Xxh3);
+        let stored = crate::content_path(&dir, &xxh3);
+        assert!(stored.exists());
+
+        assert_eq!(crate::read_sync(&dir, "dual").unwrap(), b"hello");
+        crate::verify_crypto_sync(&dir, "dual").unwrap();
+
+        crate::write_sync_with_algo(Algorithm::Xxh3, &dir, "fast", b"hello").unwrap();
+        assert!(matches!(
+            crate::verify_crypto_sync(&dir, "fast"),
+            Err(Error::MissingHash(_, _, Algorithm::Sha256))
+        ));
+
+        fs::write(&stored, b"goodbye").unwrap();
+        assert!(matches!(
+            crate::verify_crypto_sync(&dir, "dual"),
+            Err(Error::ReadIntegrityMismatch(_, Some(_), _, _))
+        ));
+    }
+
+    #[test]
+    fn test_read_sync_corrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+        fs::write(crate::content_path(&dir, &sri), b"HELLO WORLD").unwrap();
+
+        match crate::read_sync(&dir, "my-key") {
+            Err(crate::Error::ReadIntegrityMismatch(cache, key, wanted, _)) => {
+                assert_eq!(cache, dir);
+                assert_eq!(key.as_deref(), Some("my-key"));
+                assert_eq!(wanted, sri);
+            }
+            res => panic!("unexpected result: {res:?}"),
+        }
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_read_hash_corrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+        fs::write(crate::content_path(&dir, &sri), b"hello_world").unwrap();
+
+        assert!(matches!(
+            crate::read_hash(&dir, &sri).await,
+            Err(crate::Error::ReadIntegrityMismatch(_, None, wanted, _)) if wanted == sri
+        ));
+    }
+
+    #[cfg(feature = "gzip")]
+    const HELLO_GZ: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x01, 0x00, 0x85, 0x11, 0x4a, 0x0d, 0x0b, 0x00, 0x00,
+        0x00,
+    ];
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_read_decompressed_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello.gz", HELLO_GZ).unwrap();
+        assert_eq!(sri, crate::Integrity::from(HELLO_GZ));
+
+        assert_eq!(crate::read_sync(&dir, "hello.gz").unwrap(), HELLO_GZ);
+        assert_eq!(
+            crate::read_decompressed_sync(&dir, "hello.gz").unwrap(),
+            HELLO_GZ
+        );
+
+        crate::write_sync(&dir, "plain", b"hello world").unwrap();
+        assert_eq!(
+            crate::read_decompressed_sync(&dir, "plain").unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_read_decompressed_sync_encoding() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .content_encoding(crate::ContentEncoding::Gzip)
+            .open_sync(&dir, "hello.gz")
+            .unwrap();
+        writer.write_all(HELLO_GZ).unwrap();
+        writer.commit().unwrap();
+
+        let entry = crate::metadata_sync(&dir, "hello.gz").unwrap().unwrap();
+        assert_eq!(entry.content_encoding, Some(crate::ContentEncoding::Gzip));
+        assert_eq!(crate::read_sync(&dir, "hello.gz").unwrap(), HELLO_GZ);
+        assert_eq!(
+            crate::read_decompressed_sync(&dir, "hello.gz").unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[cfg(all(
+        feature = "gzip",
+        any(feature = "async-std", feature = "tokio", feature = "async-fs")
+    ))]
+    #[async_test]
+    async fn test_read_decompressed() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .content_encoding(crate::ContentEncoding::Gzip)
+            .open(&dir, "hello.gz")
+            .await
+            .unwrap();
+        writer.write_all(HELLO_GZ).await.unwrap();
+        writer.commit().await.unwrap();
+
+        assert_eq!(crate::read(&dir, "hello.gz").await.unwrap(), HELLO_GZ);
+        assert_eq!(
+            crate::read_decompressed(&dir, "hello.gz").await.unwrap(),
+            b"hello world"
+        );
+
+        crate::write(&dir, "plain", b"hello world").await.unwrap();
+        assert_eq!(
+            crate::read_decompressed(&dir, "plain").await.unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[cfg(all(
+        feature = "stream",
+        any(feature = "async-std", feature = "tokio", feature = "async-fs")
+    ))]
+    #[async_test]
+    async fn test_read_stream() {
+        use futures::stream::StreamExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let sri = crate::write(&dir, "my-key", &data).await.unwrap();
+
+        let chunks: Vec<_> = crate::read_stream(&dir, "my-key")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(chunks.len() > 1);
+        let read: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap().to_vec())
+            .collect();
+        assert_eq!(read, data);
+
+        let mut corrupted = data.clone();
+        corrupted[0] ^= 1;
+        fs::write(crate::content_path(&dir, &sri), corrupted).unwrap();
+        let chunks: Vec<_> = crate::read_stream(&dir, "my-key")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(
+            chunks.last(),
+            Some(Err(crate::Error::ReadIntegrityMismatch(..)))
+        ));
+    }
+
+    #[test]
+    fn test_read_chain_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let local = tmp.path().join("local");
+        let shared = tmp.path().join("shared");
+        crate::write_sync(&local, "both", b"local").unwrap();
+        crate::write_sync(&shared, "both", b"shared").unwrap();
+        crate::write_sync(&shared, "shared-only", b"shared").unwrap();
+        let caches = [&local, &shared];
+
+        assert_eq!(crate::read_chain_sync(&caches, "both").unwrap(), b"local");
+        assert_eq!(
+            crate::read_chain_sync(&caches, "shared-only").unwrap(),
+            b"shared"
+        );
+        assert!(matches!(
+            crate::read_chain_sync(&caches, "missing"),
+            Err(crate::Error::EntryNotFound(cache, _)) if cache == local
+        ));
+    }
+
+    #[test]
+    fn test_read_chain_sync_missing_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let local = tmp.path().join("local");
+        let shared = tmp.path().join("shared");
+        let sri = crate::write_sync(&local, "both", b"local").unwrap();
+        crate::write_sync(&shared, "both", b"shared").unwrap();
+        let orphan = crate::write_sync(&local, "orphan", b"orphan").unwrap();
+        fs::remove_file(crate::content_path(&local, &sri)).unwrap();
+        fs::remove_file(crate::content_path(&local, &orphan)).unwrap();
+        let caches = [&local, &shared];
+
+        assert_eq!(crate::read_chain_sync(&caches, "both").unwrap(), b"shared");
+        assert!(matches!(
+            crate::read_chain_sync(&caches, "orphan"),
+            Err(crate::Error::EntryNotFound(cache, _)) if cache == local
+        ));
+    }
+
+    #[test]
+    fn test_read_hash_sync_truncated() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+        fs::write(crate::content_path(&dir, &sri), b"hello").unwrap();
+
+        assert!(matches!(
+            crate::read_hash_sync(&dir, &sri),
+            Err(crate::Error::ReadIntegrityMismatch(_, None, wanted, _)) if wanted == sri
+        ));
+    }
+
+    #[test]
+    fn test_read_sync_truncated() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+        fs::write(crate::content_path(&dir, &sri), b"hello").unwrap();
+        assert!(matches!(
+            crate::read_sync(&dir, "my-key"),
+            Err(crate::Error::SizeMismatch(11, 5))
+        ));
+    }
+
+    #[test]
+    fn test_sync_reader_resume() {
+        use std::io::Read;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+
+        let mut handle = crate::SyncReader::open(&dir, "my-key").unwrap();
+        let mut buf = [0; 6];
+        handle.read_exact(&mut buf).unwrap();
+        assert_eq!(handle.stream_position().unwrap(), 6);
+        let checkpoint = handle.checkpoint().unwrap();
+        assert_eq!(checkpoint.position(), 6);
+        drop(handle);
+
+        let mut handle = crate::SyncReader::resume(&dir, checkpoint.clone()).unwrap();
+        let mut rest = String::new();
+        handle.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world");
+        assert_eq!(handle.check().unwrap(), crate::Algorithm::Sha256);
+
+        // Data changed after the checkpoint is still caught.
+        fs::write(crate::content_path(&dir, &sri), b"hello WORLD").unwrap();
+        let mut handle = crate::SyncReader::resume(&dir, checkpoint).unwrap();
+        handle.read_to_end(&mut Vec::new()).unwrap();
+        assert!(matches!(
+            handle.check(),
+            Err(crate::Error::ReadIntegrityMismatch(_, Some(key), _, _)) if key == "my-key"
+        ));
+    }
+
+    static_assertions::assert_impl_all!(crate::SyncReader: Send, Sync);
+    static_assertions::assert_impl_all!(crate::ReadCheckpoint: Send, Sync);
+    static_assertions::assert_impl_all!(crate::SyncWriter: Send, Sync);
+
+    #[test]
+    fn test_sync_reader_check_corrupted() {
+        use std::io::Read;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+        fs::write(crate::content_path(&dir, &sri), b"goodbye").unwrap();
+
+        let mut handle = crate::SyncReader::open(&dir, "my-key").unwrap();
+        let mut buf = Vec::new();
+        handle.read_to_end(&mut buf).unwrap();
+        assert!(matches!(
+            handle.check(),
+            Err(crate::Error::ReadIntegrityMismatch(_, Some(key), _, _)) if key == "my-key"
+        ));
+    }
+
+    #[test]
+    fn test_copy_atomic_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let dest = out.join("data");
+        crate::write_sync(&dir, "my-key", b"hello world").unwrap();
+
+        let size = crate::CopyOpts::new()
+            .atomic(true)
+            .copy_sync(&dir, "my-key", &dest)
+            .unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(fs::read(&dest).unwrap(), b"hello world");
+        assert_eq!(fs::read_dir(&out).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_mode_sync() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        crate::write_sync(dir, "my-key", b"#!/bin/sh\n").unwrap();
+
+        for atomic in [false, true] {
+            let dest = dir.join(format!("bin-{atomic}"));
+            crate::CopyOpts::new()
+                .atomic(atomic)
+                .mode(0o755)
+                .copy_sync(dir, "my-key", &dest)
+                .unwrap();
+            let mode = fs::metadata(&dest).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+            assert_eq!(fs::read(&dest).unwrap(), b"#!/bin/sh\n");
+        }
+    }
+
+    #[test]
+    fn test_copy_atomic_interrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("data");
+
+        let staged = crate::content::read::Staged::new(&dest).unwrap();
+        fs::write(staged.path(), b"hello").unwrap();
+        // Dropping before `persist` simulates a copy that never finished.
+        drop(staged);
+        assert!(!dest.exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+
+        let sri = crate::write_sync(tmp.path(), "my-key", b"hello world").unwrap();
+        fs::write(crate::content_path(tmp.path(), &sri), b"goodbye").unwrap();
+        assert!(crate::CopyOpts::new()
+            .atomic(true)
+            .copy_hash_sync(tmp.path(), &sri, &dest)
+            .is_err());
+        assert!(!dest.exists());
+    }
+
     #[test]
     fn test_copy_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1057,6 +3138,39 @@ mod tests {
         assert_eq!(data, b"hello world");
     }
 
+    #[test]
+    fn test_copy_sync_sized() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        let sri = crate::write_sync(dir, "my-key", b"hello world").unwrap();
+
+        let entry = crate::metadata_sync(dir, "my-key").unwrap().unwrap();
+        assert_eq!(crate::copy_sync(dir, "my-key", &dest).unwrap(), entry.size);
+
+        fs::write(crate::content_path(dir, &sri), b"hello").unwrap();
+        assert!(matches!(
+            crate::copy_sync(dir, "my-key", dir.join("truncated")),
+            Err(crate::Error::SizeMismatch(11, 5))
+        ));
+        assert!(matches!(
+            crate::reflink_sync(dir, "my-key", dir.join("truncated")),
+            Err(crate::Error::SizeMismatch(11, 5))
+        ));
+        for atomic in [false, true] {
+            let opts = || crate::CopyOpts::new().atomic(atomic);
+            assert!(matches!(
+                opts().copy_sync(dir, "my-key", dir.join("truncated")),
+                Err(crate::Error::SizeMismatch(11, 5))
+            ));
+            assert!(matches!(
+                opts().reflink_sync(dir, "my-key", dir.join("truncated")),
+                Err(crate::Error::SizeMismatch(11, 5))
+            ));
+        }
+        assert!(!dir.join("truncated").exists());
+    }
+
     #[test]
     fn test_copy_hash_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1068,4 +3182,35 @@ mod tests {
         let data = fs::read(&dest).unwrap();
         assert_eq!(data, b"hello world");
     }
+
+    #[test]
+    fn test_extract_hash_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        let sri = crate::write_sync(dir, "my-key", b"hello world").unwrap();
+
+        let data = crate::extract_hash_sync(dir, &sri, &dest).unwrap();
+        assert_eq!(data, b"hello world");
+        assert_eq!(fs::read(&dest).unwrap(), b"hello world");
+
+        fs::write(crate::content_path(dir, &sri), b"goodbye").unwrap();
+        let dest = dir.join("corrupted");
+        assert!(crate::extract_hash_sync(dir, &sri, &dest).is_err());
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn test_supports_reflink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let sri = crate::write_sync(dir, "my-key", b"hello world").unwrap();
+
+        let supported = crate::supports_reflink(dir);
+        assert_eq!(crate::supports_reflink(dir), supported);
+        assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+
+        let res = crate::reflink_hash_sync(dir, &sri, dir.join("data"));
+        assert_eq!(res.is_ok(), supported);
+    }
 }
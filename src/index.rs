@@ -1,30 +1,45 @@
 //! Raw access to the cache index. Use with caution!
 
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::hash_map;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, OpenOptions};
 use std::hash::{Hash, Hasher};
-use std::io::{ErrorKind, Write};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use digest::Digest;
 use either::{Left, Right};
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-use futures::stream::StreamExt;
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
 use sha1::Sha1;
 use sha2::Sha256;
-use ssri::Integrity;
+use ssri::{Algorithm, Integrity};
 use walkdir::WalkDir;
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
-use crate::async_lib::{AsyncBufReadExt, AsyncWriteExt};
-use crate::content::path::content_path;
-use crate::errors::{IoErrorExt, Result};
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use crate::async_lib::{AsyncSeekExt, AsyncWriteExt};
+use crate::config::CacheDir;
+use crate::content::path::{content_path, stored_integrity};
+use crate::content::{read, write};
+use crate::errors::{Error, IoErrorExt, Result};
 use crate::put::WriteOpts;
 
-const INDEX_VERSION: &str = "5";
+pub(crate) const INDEX_VERSION: &str = "5";
+
+/// Longest key, in bytes, that can be written to the index. Keys are stored
+/// verbatim in every entry written under them, so anything much longer than a
+/// URL or a path bloats index buckets and every lookup that reads them.
+/// Writing under a longer key fails with `Error::KeyTooLong`.
+pub const MAX_KEY_LENGTH: usize = 64 * 1024;
+
+/// A function used to normalize keys before they're hashed into a bucket and
+/// stored in the index. Normalizers are set with `Cache::key_normalizer`,
+/// which applies them to every lookup, write, and removal made through that
+/// handle. Everything else uses keys exactly as given.
+pub type KeyNormalizer = fn(&str) -> String;
 
 /// Represents a cache index entry, which points to content.
 #[derive(PartialEq, Debug)]
@@ -36,11 +51,83 @@ pub struct Metadata {
     /// Timestamp in unix milliseconds when this entry was written.
     pub time: u128,
     /// Size of data associated with this entry.
-    pub size: usize,
+    pub size: u64,
     /// Arbitrary JSON  associated with this entry.
     pub metadata: Value,
     /// Raw metadata in binary form. Can be different from JSON metadata.
     pub raw_metadata: Option<Vec<u8>>,
+    /// How the data is encoded, if that was recorded when it was written.
+    pub content_encoding: Option<ContentEncoding>,
+    /// MIME type of the data, if that was recorded when it was written.
+    pub mime: Option<String>,
+    /// Original name of the file the data came from, if that was recorded
+    /// when it was written.
+    pub filename: Option<String>,
+}
+
+/// How an entry's data is encoded, as recorded with
+/// [`WriteOpts::content_encoding`]. The data is stored, hashed, and read
+/// back exactly as it was written either way. This only records what it is,
+/// so readers can tell how to decode it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ContentEncoding {
+    /// Gzip-compressed data.
+    Gzip,
+}
+
+impl ContentEncoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    /// Encodings this version doesn't know about are treated as unrecorded.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(ContentEncoding::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Alias for [`Metadata`], the type of a single index entry.
+pub type Entry = Metadata;
+
+impl Metadata {
+    /// Algorithm the entry's content was hashed with. If the integrity
+    /// carries more than one hash, this is the strongest of them, same as
+    /// `Integrity::pick_algorithm`.
+    pub fn algorithm(&self) -> Algorithm {
+        self.integrity.pick_algorithm()
+    }
+
+    /// Deserializes the entry's JSON `metadata` into a `T`, such as one
+    /// written with [`WriteOpts::metadata_typed`]. Fails with
+    /// `Error::SerdeError` if the stored JSON doesn't have the shape `T`
+    /// expects.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use serde_derive::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Origin {
+    ///     url: String,
+    /// }
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     if let Some(entry) = cacache::metadata_sync("./my-cache", "my-key")? {
+    ///         let origin: Origin = entry.metadata_as()?;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn metadata_as<T: DeserializeOwned>(&self) -> Result<T> {
+        T::deserialize(&self.metadata)
+            .with_context(|| format!("Failed to deserialize metadata for entry {:?}", self.key))
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -48,9 +135,19 @@ struct SerializableMetadata {
     key: String,
     integrity: Option<String>,
     time: u128,
-    size: usize,
+    size: u64,
     metadata: Value,
     raw_metadata: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata_integrity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compressed_metadata: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
 }
 
 impl PartialEq for SerializableMetadata {
@@ -69,6 +166,16 @@ impl Hash for SerializableMetadata {
 
 /// Raw insertion into the cache index.
 pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
+    insert_detailed(cache, key, opts).map(|(sri, _)| sri)
+}
+
+/// Same as `insert()`, but also returns the byte offset in the bucket at
+/// which the new entry was written, for use with `find_at()`.
+pub fn insert_detailed(cache: &Path, key: &str, opts: WriteOpts) -> Result<(Integrity, u64)> {
+    let layout = opts.layout.clone();
+    let cache = CacheDir::new(cache, &layout);
+    let key = &*normalize_key(key, opts.key_normalizer);
+    check_key_length(key)?;
     let bucket = bucket_path(cache, key);
     fs::create_dir_all(bucket.parent().unwrap()).with_context(|| {
         format!(
@@ -76,15 +183,46 @@ pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
             bucket.parent().unwrap()
         )
     })?;
-    let stringified = serde_json::to_string(&SerializableMetadata {
+    if opts.fail_on_conflict {
+        let entries = bucket_entries(&bucket)
+            .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+        check_conflict(cache, key, &entries, opts.sri.as_ref())?;
+    }
+    let metadata = opts.metadata.unwrap_or(serde_json::Value::Null);
+    let (metadata, metadata_integrity) =
+        match external_metadata(&metadata, opts.metadata_threshold)? {
+            Some(data) => {
+                let mut writer = write::Writer::new(cache, Algorithm::Sha256, Some(data.len()))?;
+                writer.write_all(&data).with_context(|| {
+                    format!(
+                        "Failed to write external metadata for key `{key}` to cache at {cache:?}"
+                    )
+                })?;
+                (Value::Null, Some(writer.close()?.to_string()))
+            }
+            None => (metadata, None),
+        };
+    let entry = SerializableMetadata {
         key: key.to_owned(),
         integrity: opts.sri.clone().map(|x| x.to_string()),
         time: opts.time.unwrap_or_else(now),
         size: opts.size.unwrap_or(0),
-        metadata: opts.metadata.unwrap_or(serde_json::Value::Null),
+        metadata,
         raw_metadata: opts.raw_metadata,
-    })
-    .with_context(|| format!("Failed to serialize entry with key `{key}`"))?;
+        metadata_integrity,
+        compressed_metadata: None,
+        content_encoding: opts.content_encoding.map(|enc| enc.as_str().into()),
+        mime: opts.mime,
+        filename: opts.filename,
+    };
+    #[cfg(feature = "gzip")]
+    let entry = if opts.compress_metadata {
+        compress_metadata(entry)?
+    } else {
+        entry
+    };
+    let stringified = serde_json::to_string(&entry)
+        .with_context(|| format!("Failed to serialize entry with key `{key}`"))?;
 
     let mut buck = OpenOptions::new()
         .create(true)
@@ -92,20 +230,89 @@ pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
         .open(&bucket)
         .with_context(|| format!("Failed to create or open index bucket at {bucket:?}"))?;
 
-    let out = format!("\n{}\t{}", hash_entry(&stringified), stringified);
+    let out = bucket_line(&stringified);
     buck.write_all(out.as_bytes())
         .with_context(|| format!("Failed to write to index bucket at {bucket:?}"))?;
     buck.flush()
         .with_context(|| format!("Failed to flush bucket at {bucket:?}"))?;
-    Ok(opts
-        .sri
-        .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
-        .unwrap())
+    // Appends always land at the end of the file, so this is where ours
+    // ended, even if others were appending to the bucket at the same time.
+    let end = buck
+        .stream_position()
+        .with_context(|| format!("Failed to get position in bucket at {bucket:?}"))?;
+    Ok((
+        opts.sri
+            .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
+            .unwrap(),
+        line_offset(end, &out),
+    ))
+}
+
+/// Indexes each of `entries`' keys under its integrity and size, with no
+/// metadata. Entries that share a bucket are appended to it together, so
+/// each bucket is only opened once, however many of them land in it.
+pub(crate) fn insert_many(cache: &Path, entries: &[(String, Integrity, u64)]) -> Result<()> {
+    let time = now();
+    let mut buckets: HashMap<PathBuf, String> = HashMap::new();
+    for (key, sri, size) in entries {
+        check_key_length(key)?;
+        let entry = SerializableMetadata {
+            key: key.clone(),
+            integrity: Some(sri.to_string()),
+            time,
+            size: *size,
+            metadata: Value::Null,
+            raw_metadata: None,
+            metadata_integrity: None,
+            compressed_metadata: None,
+            content_encoding: None,
+            mime: None,
+            filename: None,
+        };
+        let stringified = serde_json::to_string(&entry)
+            .with_context(|| format!("Failed to serialize entry with key `{key}`"))?;
+        buckets
+            .entry(bucket_path(cache.into(), key))
+            .or_default()
+            .push_str(&bucket_line(&stringified));
+    }
+    for (bucket, out) in buckets {
+        fs::create_dir_all(bucket.parent().unwrap()).with_context(|| {
+            format!(
+                "Failed to create index bucket directory: {:?}",
+                bucket.parent().unwrap()
+            )
+        })?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&bucket)
+            .and_then(|mut buck| buck.write_all(out.as_bytes()))
+            .with_context(|| format!("Failed to write to index bucket at {bucket:?}"))?;
+    }
+    Ok(())
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 /// Asynchronous raw insertion into the cache index.
 pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) -> Result<Integrity> {
+    insert_detailed_async(cache, key, opts)
+        .await
+        .map(|(sri, _)| sri)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Same as `insert_async()`, but also returns the byte offset in the bucket
+/// at which the new entry was written, for use with `find_at_async()`.
+pub async fn insert_detailed_async<'a>(
+    cache: &'a Path,
+    key: &'a str,
+    opts: WriteOpts,
+) -> Result<(Integrity, u64)> {
+    let layout = opts.layout.clone();
+    let cache = CacheDir::new(cache, &layout);
+    let key = &*normalize_key(key, opts.key_normalizer);
+    check_key_length(key)?;
     let bucket = bucket_path(cache, key);
     crate::async_lib::create_dir_all(bucket.parent().unwrap())
         .await
@@ -115,15 +322,48 @@ pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) ->
                 bucket.parent().unwrap()
             )
         })?;
-    let stringified = serde_json::to_string(&SerializableMetadata {
+    if opts.fail_on_conflict {
+        let entries = bucket_entries_async(&bucket)
+            .await
+            .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+        check_conflict(cache, key, &entries, opts.sri.as_ref())?;
+    }
+    let metadata = opts.metadata.unwrap_or(serde_json::Value::Null);
+    let (metadata, metadata_integrity) =
+        match external_metadata(&metadata, opts.metadata_threshold)? {
+            Some(data) => {
+                let mut writer =
+                    write::AsyncWriter::new(cache, Algorithm::Sha256, Some(data.len())).await?;
+                writer.write_all(&data).await.with_context(|| {
+                    format!(
+                        "Failed to write external metadata for key `{key}` to cache at {cache:?}"
+                    )
+                })?;
+                (Value::Null, Some(writer.close().await?.to_string()))
+            }
+            None => (metadata, None),
+        };
+    let entry = SerializableMetadata {
         key: key.to_owned(),
         integrity: opts.sri.clone().map(|x| x.to_string()),
         time: opts.time.unwrap_or_else(now),
         size: opts.size.unwrap_or(0),
-        metadata: opts.metadata.unwrap_or(serde_json::Value::Null),
+        metadata,
         raw_metadata: opts.raw_metadata,
-    })
-    .with_context(|| format!("Failed to serialize entry with key `{key}`"))?;
+        metadata_integrity,
+        compressed_metadata: None,
+        content_encoding: opts.content_encoding.map(|enc| enc.as_str().into()),
+        mime: opts.mime,
+        filename: opts.filename,
+    };
+    #[cfg(feature = "gzip")]
+    let entry = if opts.compress_metadata {
+        compress_metadata(entry)?
+    } else {
+        entry
+    };
+    let stringified = serde_json::to_string(&entry)
+        .with_context(|| format!("Failed to serialize entry with key `{key}`"))?;
 
     let mut buck = crate::async_lib::OpenOptions::new()
         .create(true)
@@ -132,120 +372,657 @@ pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) ->
         .await
         .with_context(|| format!("Failed to create or open index bucket at {bucket:?}"))?;
 
-    let out = format!("\n{}\t{}", hash_entry(&stringified), stringified);
+    let out = bucket_line(&stringified);
     buck.write_all(out.as_bytes())
         .await
         .with_context(|| format!("Failed to write to index bucket at {bucket:?}"))?;
     buck.flush()
         .await
         .with_context(|| format!("Failed to flush bucket at {bucket:?}"))?;
-    Ok(opts
-        .sri
-        .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
-        .unwrap())
+    let end = buck
+        .seek(SeekFrom::Current(0))
+        .await
+        .with_context(|| format!("Failed to get position in bucket at {bucket:?}"))?;
+    Ok((
+        opts.sri
+            .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
+            .unwrap(),
+        line_offset(end, &out),
+    ))
 }
 
 /// Raw index Metadata access.
 pub fn find(cache: &Path, key: &str) -> Result<Option<Metadata>> {
+    find_in(cache.into(), key)
+}
+
+/// Same as `find()`, for a cache that may not use the default layout.
+pub(crate) fn find_in(cache: CacheDir<'_>, key: &str) -> Result<Option<Metadata>> {
     let bucket = bucket_path(cache, key);
-    Ok(bucket_entries(&bucket)
-        .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?
-        .into_iter()
-        .fold(None, |acc, entry| {
-            if entry.key == key {
-                if let Some(integrity) = entry.integrity {
-                    let integrity: Integrity = match integrity.parse() {
-                        Ok(sri) => sri,
-                        _ => return acc,
-                    };
-                    Some(Metadata {
-                        key: entry.key,
-                        integrity,
-                        size: entry.size,
-                        time: entry.time,
-                        metadata: entry.metadata,
-                        raw_metadata: entry.raw_metadata,
-                    })
-                } else {
-                    None
-                }
-            } else {
-                acc
-            }
-        }))
+    let entries = bucket_entries(&bucket)
+        .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+    load_external(cache, latest_entry(&entries, key)?)
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 /// Asynchronous raw index Metadata access.
 pub async fn find_async(cache: &Path, key: &str) -> Result<Option<Metadata>> {
+    find_in_async(cache.into(), key).await
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronous version of `find_in()`.
+pub(crate) async fn find_in_async(cache: CacheDir<'_>, key: &str) -> Result<Option<Metadata>> {
     let bucket = bucket_path(cache, key);
-    Ok(bucket_entries_async(&bucket)
+    let entries = bucket_entries_async(&bucket)
         .await
-        .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?
-        .into_iter()
-        .fold(None, |acc, entry| {
-            if entry.key == key {
-                if let Some(integrity) = entry.integrity {
-                    let integrity: Integrity = match integrity.parse() {
-                        Ok(sri) => sri,
-                        _ => return acc,
-                    };
-                    Some(Metadata {
-                        key: entry.key,
-                        integrity,
-                        size: entry.size,
-                        time: entry.time,
-                        metadata: entry.metadata,
-                        raw_metadata: entry.raw_metadata,
-                    })
-                } else {
-                    None
+        .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+    load_external_async(cache, latest_entry(&entries, key)?).await
+}
+
+/// Like `find()`, but if the entry's metadata is stored outside the index
+/// and can't be read back, the entry is still returned, with `Value::Null`
+/// metadata. For callers that care whether there's a live entry at all, such
+/// as removal, which shouldn't be blocked by a missing metadata blob.
+pub(crate) fn find_lenient(cache: CacheDir<'_>, key: &str) -> Result<Option<Metadata>> {
+    let bucket = bucket_path(cache, key);
+    let entries = bucket_entries(&bucket)
+        .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+    Ok(
+        latest_entry_lenient(&entries, key).map(|(mut entry, external)| {
+            if let Some(sri) = external.and_then(|sri| sri.parse::<Integrity>().ok()) {
+                if let Ok(metadata) = read::read(cache, &sri)
+                    .and_then(|data| parse_external_metadata(&data, &entry.key))
+                {
+                    entry.metadata = metadata;
+                }
+            }
+            entry
+        }),
+    )
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronous version of `find_lenient()`.
+pub(crate) async fn find_lenient_async(cache: CacheDir<'_>, key: &str) -> Result<Option<Metadata>> {
+    let bucket = bucket_path(cache, key);
+    let entries = bucket_entries_async(&bucket)
+        .await
+        .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+    match latest_entry_lenient(&entries, key) {
+        Some((mut entry, Some(sri))) => {
+            if let Ok(sri) = sri.parse::<Integrity>() {
+                if let Ok(data) = read::read_async(cache, &sri).await {
+                    if let Ok(metadata) = parse_external_metadata(&data, &entry.key) {
+                        entry.metadata = metadata;
+                    }
                 }
+            }
+            Ok(Some(entry))
+        }
+        found => Ok(found.map(|(entry, _)| entry)),
+    }
+}
+
+/// Raw index Metadata access, starting from `offset` in the key's bucket, as
+/// returned by `insert_detailed()`. Only the part of the bucket from `offset`
+/// onwards is read, so entries written after that one are still picked up.
+/// If there's no entry for `key` at `offset`, because the bucket has since
+/// been rewritten, this falls back to reading the whole bucket.
+pub fn find_at(cache: &Path, key: &str, offset: u64) -> Result<Option<Metadata>> {
+    let bucket = bucket_path(cache.into(), key);
+    let mut tail = String::new();
+    let read = fs::File::open(&bucket).and_then(|mut file| {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_to_string(&mut tail)
+    });
+    match read {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        // The offset might land in the middle of a multibyte character if
+        // the bucket was rewritten, which is the same as a stale offset.
+        Err(err) if err.kind() == ErrorKind::InvalidData => return find(cache, key),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))
+        }
+    }
+    match entries_from_offset(&tail, key) {
+        Some(entries) => load_external(cache.into(), latest_entry(&entries, key)?),
+        None => find(cache, key),
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronous raw index Metadata access, starting from `offset` in the
+/// key's bucket. See `find_at()`.
+pub async fn find_at_async(cache: &Path, key: &str, offset: u64) -> Result<Option<Metadata>> {
+    use crate::async_lib::AsyncReadExt;
+    let bucket = bucket_path(cache.into(), key);
+    let mut tail = String::new();
+    let read = async {
+        let mut file = crate::async_lib::File::open(&bucket).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_to_string(&mut tail).await
+    }
+    .await;
+    match read {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) if err.kind() == ErrorKind::InvalidData => return find_async(cache, key).await,
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))
+        }
+    }
+    match entries_from_offset(&tail, key) {
+        Some(entries) => load_external_async(cache.into(), latest_entry(&entries, key)?).await,
+        None => find_async(cache, key).await,
+    }
+}
+
+/// Raw index Metadata access for many keys at once. Each bucket is read at
+/// most once, no matter how many of the requested keys live in it. Results
+/// are in the same order as `keys`.
+pub fn find_many(cache: &Path, keys: &[&str]) -> Result<Vec<Option<Metadata>>> {
+    let cache = CacheDir::from(cache);
+    let mut buckets = HashMap::new();
+    for key in keys {
+        if let hash_map::Entry::Vacant(slot) = buckets.entry(bucket_path(cache, key)) {
+            let bucket = slot.key();
+            let entries = bucket_entries(bucket)
+                .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+            slot.insert(entries);
+        }
+    }
+    keys.iter()
+        .map(|key| {
+            load_external(
+                cache,
+                latest_entry(&buckets[&bucket_path(cache, key)], key)?,
+            )
+        })
+        .collect()
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronous raw index Metadata access for many keys at once. Distinct
+/// buckets are read concurrently, each at most once. Results are in the same
+/// order as `keys`.
+pub async fn find_many_async(cache: &Path, keys: &[&str]) -> Result<Vec<Option<Metadata>>> {
+    let cache = CacheDir::from(cache);
+    let buckets = keys
+        .iter()
+        .map(|key| bucket_path(cache, key))
+        .collect::<HashSet<_>>();
+    let buckets = futures::future::try_join_all(buckets.into_iter().map(|bucket| async move {
+        let entries = bucket_entries_async(&bucket)
+            .await
+            .with_context(|| format!("Failed to read index bucket entries from {bucket:?}"))?;
+        Ok::<_, crate::Error>((bucket, entries))
+    }))
+    .await?
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+    let mut found = Vec::with_capacity(keys.len());
+    for key in keys {
+        let entry = latest_entry(&buckets[&bucket_path(cache, key)], key)?;
+        found.push(load_external_async(cache, entry).await?);
+    }
+    Ok(found)
+}
+
+/// Picks out the latest entry for `key` from a bucket's entries, along with
+/// its parsed integrity.
+fn latest<'a>(
+    entries: &'a [SerializableMetadata],
+    key: &str,
+) -> Option<(&'a SerializableMetadata, Integrity)> {
+    entries.iter().fold(None, |acc, entry| {
+        if entry.key == key {
+            if let Some(integrity) = &entry.integrity {
+                let integrity: Integrity = match integrity.parse() {
+                    Ok(sri) => sri,
+                    _ => return acc,
+                };
+                Some((entry, integrity))
             } else {
-                acc
+                None
             }
-        }))
+        } else {
+            acc
+        }
+    })
+}
+
+/// Picks out the latest entry for `key` from a bucket's entries, along with
+/// the integrity of its external metadata, if it has any. Fails if the
+/// entry's metadata was compressed and can't be decompressed.
+fn latest_entry(
+    entries: &[SerializableMetadata],
+    key: &str,
+) -> Result<Option<(Metadata, Option<String>)>> {
+    latest(entries, key)
+        .map(|(entry, integrity)| {
+            let inline = inline_metadata(entry)?;
+            Ok((
+                to_metadata(entry, integrity, inline),
+                entry.metadata_integrity.clone(),
+            ))
+        })
+        .transpose()
+}
+
+/// Like `latest_entry()`, but compressed metadata that can't be decompressed
+/// is left out instead of failing.
+fn latest_entry_lenient(
+    entries: &[SerializableMetadata],
+    key: &str,
+) -> Option<(Metadata, Option<String>)> {
+    latest(entries, key).map(|(entry, integrity)| {
+        let inline = inline_metadata(entry).unwrap_or((Value::Null, None));
+        (
+            to_metadata(entry, integrity, inline),
+            entry.metadata_integrity.clone(),
+        )
+    })
+}
+
+fn to_metadata(
+    entry: &SerializableMetadata,
+    integrity: Integrity,
+    (metadata, raw_metadata): (Value, Option<Vec<u8>>),
+) -> Metadata {
+    Metadata {
+        key: entry.key.clone(),
+        integrity,
+        size: entry.size,
+        time: entry.time,
+        metadata,
+        raw_metadata,
+        content_encoding: entry
+            .content_encoding
+            .as_deref()
+            .and_then(ContentEncoding::from_name),
+        mime: entry.mime.clone(),
+        filename: entry.filename.clone(),
+    }
+}
+
+/// Fails with `Error::KeyConflict` if the latest of `entries` for `key` points
+/// to content that doesn't share a hash with `sri`.
+fn check_conflict(
+    cache: CacheDir<'_>,
+    key: &str,
+    entries: &[SerializableMetadata],
+    sri: Option<&Integrity>,
+) -> Result<()> {
+    match (latest(entries, key), sri) {
+        (Some((_, existing)), Some(sri)) if existing.matches(sri).is_none() => Err(
+            Error::KeyConflict(cache.to_path_buf(), key.into(), existing, sri.clone()),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn load_external(
+    cache: CacheDir<'_>,
+    found: Option<(Metadata, Option<String>)>,
+) -> Result<Option<Metadata>> {
+    match found {
+        Some((mut entry, Some(sri))) => {
+            let sri: Integrity = sri.parse()?;
+            entry.metadata = parse_external_metadata(&read::read(cache, &sri)?, &entry.key)?;
+            Ok(Some(entry))
+        }
+        found => Ok(found.map(|(entry, _)| entry)),
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn load_external_async(
+    cache: CacheDir<'_>,
+    found: Option<(Metadata, Option<String>)>,
+) -> Result<Option<Metadata>> {
+    match found {
+        Some((mut entry, Some(sri))) => {
+            let data = read::read_async(cache, &sri.parse()?).await?;
+            entry.metadata = parse_external_metadata(&data, &entry.key)?;
+            Ok(Some(entry))
+        }
+        found => Ok(found.map(|(entry, _)| entry)),
+    }
+}
+
+/// Inserts an index entry for `key` pointing at content that is already
+/// present in the cache, such as content written with `write_hash`, without
+/// rewriting it. Errors if the content isn't present. If `opts` has a `size`
+/// set, it's checked against the size of the content on disk; otherwise the
+/// entry's size is taken from it. If `verify` is set, the content is also
+/// read through and checked against `sri` first, failing with
+/// `Error::ReadIntegrityMismatch` if it's corrupted.
+pub fn link_key(
+    cache: &Path,
+    key: &str,
+    sri: &Integrity,
+    opts: WriteOpts,
+    verify: bool,
+) -> Result<Integrity> {
+    let dir = CacheDir::new(cache, &opts.layout);
+    let cpath = content_path(dir, &stored_integrity(dir, sri));
+    let len = fs::metadata(&cpath)
+        .with_context(|| format!("Content for {sri} not found in cache at {cache:?}"))?
+        .len();
+    if verify {
+        read::check(dir, sri)?;
+    }
+    insert(cache, key, linked_opts(sri, len, opts)?)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronously inserts an index entry for `key` pointing at content that
+/// is already present in the cache, such as content written with
+/// `write_hash`, without rewriting it. Errors if the content isn't present.
+/// If `opts` has a `size` set, it's checked against the size of the content
+/// on disk; otherwise the entry's size is taken from it. If `verify` is set,
+/// the content is also read through and checked against `sri` first.
+pub async fn link_key_async(
+    cache: &Path,
+    key: &str,
+    sri: &Integrity,
+    opts: WriteOpts,
+    verify: bool,
+) -> Result<Integrity> {
+    let dir = CacheDir::new(cache, &opts.layout);
+    let cpath = content_path(dir, &stored_integrity(dir, sri));
+    let len = crate::async_lib::metadata(&cpath)
+        .await
+        .with_context(|| format!("Content for {sri} not found in cache at {cache:?}"))?
+        .len();
+    if verify {
+        read::check_async(dir, sri).await?;
+    }
+    insert_async(cache, key, linked_opts(sri, len, opts)?).await
+}
+
+fn linked_opts(sri: &Integrity, len: u64, opts: WriteOpts) -> Result<WriteOpts> {
+    match opts.size {
+        Some(size) if size != len => Err(crate::Error::SizeMismatch(size, len)),
+        _ => Ok(opts.integrity(sri.clone()).size(len)),
+    }
 }
 
 /// Deletes an index entry, without deleting the actual cache data entry.
 pub fn delete(cache: &Path, key: &str) -> Result<()> {
-    insert(
-        cache,
-        key,
-        WriteOpts {
-            algorithm: None,
-            size: None,
-            sri: None,
-            time: None,
-            metadata: None,
-            raw_metadata: None,
-        },
-    )
-    .map(|_| ())
+    delete_in(cache.into(), key)
+}
+
+/// Same as `delete()`, for a cache that may not use the default layout.
+pub(crate) fn delete_in(cache: CacheDir<'_>, key: &str) -> Result<()> {
+    let opts = WriteOpts::new().layout(cache.layout().clone());
+    insert(&cache, key, opts).map(|_| ())
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 /// Asynchronously deletes an index entry, without deleting the actual cache
 /// data entry.
 pub async fn delete_async(cache: &Path, key: &str) -> Result<()> {
-    insert(
-        cache,
-        key,
-        WriteOpts {
-            algorithm: None,
-            size: None,
-            sri: None,
-            time: None,
-            metadata: None,
-            raw_metadata: None,
-        },
-    )
-    .map(|_| ())
+    delete_in_async(cache.into(), key).await
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronous version of `delete_in()`.
+pub(crate) async fn delete_in_async(cache: CacheDir<'_>, key: &str) -> Result<()> {
+    let opts = WriteOpts::new().layout(cache.layout().clone());
+    insert(&cache, key, opts).map(|_| ())
 }
 
 /// Lists raw index Metadata entries.
 pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
-    let cache_path = cache.join(format!("index-v{INDEX_VERSION}"));
+    ls_in(cache.into())
+}
+
+/// Same as `ls()`, for a cache that may not use the default layout.
+pub(crate) fn ls_in(cache: CacheDir<'_>) -> impl Iterator<Item = Result<Metadata>> + Send {
+    ls_index_dir(cache, cache.index_dir())
+}
+
+/// Migrates live entries from an older index version into the current one,
+/// returning the number of entries migrated. Entries keep their original
+/// time, size, and metadata. Keys that already have an entry in the current
+/// index are left alone, so running this again doesn't duplicate anything.
+/// If `remove_old` is true, the old index directory is removed once
+/// everything has been migrated.
+///
+/// `from_version` must be made up only of ASCII digits. Every old entry is
+/// read and checked before any are written, so an unreadable entry or an
+/// overlong key fails the migration without migrating anything.
+pub fn migrate(cache: &Path, from_version: &str, remove_old: bool) -> Result<usize> {
+    if from_version.is_empty() || !from_version.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::IoError(
+            std::io::Error::new(ErrorKind::InvalidInput, "invalid index version"),
+            format!("Invalid index version {from_version:?}"),
+        ));
+    }
+    let old_index = cache.join(format!("index-v{from_version}"));
+    if old_index == index_dir(cache) || !old_index.exists() {
+        return Ok(0);
+    }
+    let entries = ls_index_dir(cache.into(), old_index.clone()).collect::<Result<Vec<_>>>()?;
+    for entry in &entries {
+        check_key_length(&entry.key)?;
+    }
+    let mut migrated = 0;
+    for entry in entries {
+        if find(cache, &entry.key)?.is_some() {
+            continue;
+        }
+        let mut opts = WriteOpts::new()
+            .integrity(entry.integrity)
+            .time(entry.time)
+            .size(entry.size)
+            .metadata(entry.metadata);
+        opts.raw_metadata = entry.raw_metadata;
+        opts.content_encoding = entry.content_encoding;
+        opts.mime = entry.mime;
+        opts.filename = entry.filename;
+        insert(cache, &entry.key, opts)?;
+        migrated += 1;
+    }
+    if remove_old {
+        fs::remove_dir_all(&old_index)
+            .with_context(|| format!("Failed to remove old index directory at {old_index:?}"))?;
+    }
+    Ok(migrated)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronously lists a page of up to `limit` live index entries, skipping
+/// the first `offset`. Entries are ordered by bucket path, then by the order
+/// in which their keys first appear in their bucket, so pages are stable
+/// across calls as long as the cache isn't written to in between. Only as
+/// many buckets as needed to fill the page are read.
+pub async fn ls_page_async(cache: &Path, offset: usize, limit: usize) -> Result<Vec<Metadata>> {
+    let index = index_dir(cache);
+    let mut page = Vec::new();
+    if limit == 0 {
+        return Ok(page);
+    }
+    let mut skipped = 0;
+    let mut buckets = AsyncBucketWalk::new(&index);
+    while let Some(bucket) = buckets.next().await.with_context(|| {
+        format!(
+            "Error while walking cache index directory at {}",
+            index.display()
+        )
+    })? {
+        let entries = bucket_entries_async(&bucket)
+            .await
+            .with_context(|| format!("Error getting bucket entries from {}", bucket.display()))?;
+        for entry in live_entries(entries) {
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+            page.push(listed_entry_async(cache.into(), entry).await?);
+            if page.len() == limit {
+                return Ok(page);
+            }
+        }
+    }
+    Ok(page)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Asynchronously lists all live index entries as a stream, in the same
+/// order as `ls_page_async()`. Buckets are only read as the stream is
+/// polled. Like `ls()`, the listing keeps going past entries and buckets
+/// that can't be read, yielding an error for each of them.
+pub fn ls_async(cache: &Path) -> impl futures::Stream<Item = Result<Metadata>> + Send {
+    ls_in_async(cache.into())
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+/// Same as `ls_async()`, for a cache that may not use the default layout.
+pub(crate) fn ls_in_async(
+    cache: CacheDir<'_>,
+) -> impl futures::Stream<Item = Result<Metadata>> + Send {
+    let index = cache.index_dir();
+    let listing = AsyncListing {
+        cache: cache.to_path_buf(),
+        layout: cache.layout().clone(),
+        buckets: AsyncBucketWalk::new(&index),
+        index,
+        entries: Vec::new().into_iter(),
+    };
+    futures::stream::unfold(listing, |mut listing| async move {
+        let next = listing.next().await.transpose()?;
+        Some((next, listing))
+    })
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+struct AsyncListing {
+    cache: PathBuf,
+    layout: crate::config::Layout,
+    index: PathBuf,
+    buckets: AsyncBucketWalk,
+    entries: std::vec::IntoIter<SerializableMetadata>,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+impl AsyncListing {
+    async fn next(&mut self) -> Result<Option<Metadata>> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return listed_entry_async(CacheDir::new(&self.cache, &self.layout), entry)
+                    .await
+                    .map(Some);
+            }
+            let bucket = match self.buckets.next().await.with_context(|| {
+                format!(
+                    "Error while walking cache index directory at {}",
+                    self.index.display()
+                )
+            })? {
+                Some(bucket) => bucket,
+                None => return Ok(None),
+            };
+            let entries = bucket_entries_async(&bucket).await.with_context(|| {
+                format!("Error getting bucket entries from {}", bucket.display())
+            })?;
+            self.entries = live_entries(entries).into_iter();
+        }
+    }
+}
+
+/// Turns a live bucket entry into the `Metadata` listed for it, loading its
+/// metadata if it's stored elsewhere.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn listed_entry_async(cache: CacheDir<'_>, entry: SerializableMetadata) -> Result<Metadata> {
+    let integrity = entry.integrity.as_deref().unwrap_or_default().parse()?;
+    let (metadata, raw_metadata) = inline_metadata(&entry)?;
+    let metadata = match entry.metadata_integrity {
+        Some(sri) => {
+            let data = read::read_async(cache, &sri.parse()?).await?;
+            parse_external_metadata(&data, &entry.key)?
+        }
+        None => metadata,
+    };
+    Ok(Metadata {
+        key: entry.key,
+        integrity,
+        time: entry.time,
+        size: entry.size,
+        metadata,
+        raw_metadata,
+        content_encoding: entry
+            .content_encoding
+            .as_deref()
+            .and_then(ContentEncoding::from_name),
+        mime: entry.mime,
+        filename: entry.filename,
+    })
+}
+
+/// Walks the bucket files under an index directory without blocking, in the
+/// same order as `WalkDir::sort_by_file_name()`. Directories are only read
+/// once the walk reaches them, and ones that are gone by then, including the
+/// index directory itself, are skipped.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+struct AsyncBucketWalk {
+    pending: Vec<(PathBuf, bool)>,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+impl AsyncBucketWalk {
+    fn new(index: &Path) -> Self {
+        AsyncBucketWalk {
+            pending: vec![(index.to_path_buf(), true)],
+        }
+    }
+
+    async fn next(&mut self) -> std::io::Result<Option<PathBuf>> {
+        while let Some((path, is_dir)) = self.pending.pop() {
+            if !is_dir {
+                return Ok(Some(path));
+            }
+            let entries = match crate::async_lib::read_dir_sorted(&path).await {
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                res => res?,
+            };
+            self.pending.extend(entries.into_iter().rev());
+        }
+        Ok(None)
+    }
+}
+
+/// Resolves the latest entry for each key in a bucket, dropping deleted
+/// ones. Keys keep the order in which they first appear in the bucket.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+fn live_entries(entries: Vec<SerializableMetadata>) -> Vec<SerializableMetadata> {
+    let mut positions = HashMap::new();
+    let mut latest: Vec<SerializableMetadata> = Vec::new();
+    for entry in entries {
+        match positions.get(&entry.key) {
+            Some(&i) => latest[i] = entry,
+            None => {
+                positions.insert(entry.key.clone(), latest.len());
+                latest.push(entry);
+            }
+        }
+    }
+    latest
+        .into_iter()
+        .filter(|entry| entry.integrity.is_some())
+        .collect()
+}
+
+fn ls_index_dir(
+    cache: CacheDir<'_>,
+    cache_path: PathBuf,
+) -> impl Iterator<Item = Result<Metadata>> + Send {
+    let (cache, layout) = (cache.to_path_buf(), cache.layout().clone());
     let cloned = cache_path.clone();
     WalkDir::new(&cache_path)
         .into_iter()
@@ -267,7 +1044,7 @@ pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
             }
 
             let owned_path = bucket.path().to_owned();
-            Ok(bucket_entries(bucket.path())
+            bucket_entries(bucket.path())
                 .with_context(|| {
                     format!("Error getting bucket entries from {}", owned_path.display())
                 })?
@@ -276,20 +1053,36 @@ pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
                 .collect::<HashSet<SerializableMetadata>>()
                 .into_iter()
                 .filter_map(|se| {
-                    if let Some(i) = se.integrity {
-                        Some(Metadata {
+                    let i = se.integrity.as_ref()?;
+                    let metadata = inline_metadata(&se).and_then(|(metadata, raw_metadata)| {
+                        let metadata = match &se.metadata_integrity {
+                            Some(sri) => sri
+                                .parse()
+                                .map_err(crate::Error::from)
+                                .and_then(|sri| read::read(CacheDir::new(&cache, &layout), &sri))
+                                .and_then(|data| parse_external_metadata(&data, &se.key))?,
+                            None => metadata,
+                        };
+                        Ok((metadata, raw_metadata))
+                    });
+                    Some(metadata.map(|(metadata, raw_metadata)| {
+                        Metadata {
                             key: se.key,
                             integrity: i.parse().unwrap(),
                             time: se.time,
                             size: se.size,
-                            metadata: se.metadata,
-                            raw_metadata: se.raw_metadata,
-                        })
-                    } else {
-                        None
-                    }
+                            metadata,
+                            raw_metadata,
+                            content_encoding: se
+                                .content_encoding
+                                .as_deref()
+                                .and_then(ContentEncoding::from_name),
+                            mime: se.mime,
+                            filename: se.filename,
+                        }
+                    }))
                 })
-                .collect())
+                .collect::<Result<Vec<_>>>()
         })
         .flat_map(|res| match res {
             Ok(it) => Left(it.into_iter().map(Ok)),
@@ -297,28 +1090,307 @@ pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
         })
 }
 
-fn bucket_path(cache: &Path, key: &str) -> PathBuf {
+/// Latest index record for a key, as returned by
+/// [`ls_with_tombstones`]. Unlike [`Metadata`], this may describe a key that
+/// has since been deleted.
+#[derive(PartialEq, Eq, Debug)]
+pub struct IndexRecord {
+    /// Key this record is stored under.
+    pub key: String,
+    /// Integrity hash for the stored data, or `None` if the key was deleted.
+    pub integrity: Option<Integrity>,
+    /// Timestamp in unix milliseconds when this record was written. For a
+    /// deleted key, this is when it was deleted.
+    pub time: u128,
+    /// Size of data associated with this record.
+    pub size: u64,
+}
+
+impl IndexRecord {
+    /// Returns true if this record is a tombstone left behind by a delete.
+    pub fn is_deleted(&self) -> bool {
+        self.integrity.is_none()
+    }
+}
+
+/// Lists the latest record for every key in the index, including keys whose
+/// latest record is a tombstone. Entry metadata isn't read.
+pub fn ls_with_tombstones(cache: &Path) -> impl Iterator<Item = Result<IndexRecord>> {
+    ls_with_tombstones_in(cache.into())
+}
+
+/// Same as `ls_with_tombstones()`, for a cache that may not use the default
+/// layout.
+pub(crate) fn ls_with_tombstones_in(
+    cache: CacheDir<'_>,
+) -> impl Iterator<Item = Result<IndexRecord>> {
+    let index = cache.index_dir();
+    WalkDir::new(&index)
+        .into_iter()
+        .map(move |bucket| {
+            let bucket = bucket
+                .map_err(|e| match e.io_error() {
+                    Some(io_err) => std::io::Error::new(io_err.kind(), io_err.kind().to_string()),
+                    None => crate::errors::io_error("Unexpected error"),
+                })
+                .with_context(|| {
+                    format!(
+                        "Error while walking cache index directory at {}",
+                        index.display()
+                    )
+                })?;
+
+            if bucket.file_type().is_dir() {
+                return Ok(Vec::new());
+            }
+
+            bucket_entries(bucket.path())
+                .with_context(|| {
+                    format!(
+                        "Error getting bucket entries from {}",
+                        bucket.path().display()
+                    )
+                })?
+                .into_iter()
+                .rev()
+                .collect::<HashSet<SerializableMetadata>>()
+                .into_iter()
+                .map(|se| {
+                    Ok(IndexRecord {
+                        integrity: se.integrity.map(|i| i.parse()).transpose()?,
+                        key: se.key,
+                        time: se.time,
+                        size: se.size,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .flat_map(|res| match res {
+            Ok(it) => Left(it.into_iter().map(Ok)),
+            Err(err) => Right(std::iter::once(Err(err))),
+        })
+}
+
+/// Lists the integrity of every piece of content that a live index entry
+/// depends on, including externally stored metadata.
+pub(crate) fn referenced_content(cache: CacheDir<'_>) -> Result<Vec<Integrity>> {
+    let index = cache.index_dir();
+    if !index.exists() {
+        return Ok(Vec::new());
+    }
+    let mut referenced = Vec::new();
+    for bucket in WalkDir::new(&index) {
+        let bucket = bucket
+            .map_err(|e| match e.io_error() {
+                Some(io_err) => std::io::Error::new(io_err.kind(), io_err.kind().to_string()),
+                None => crate::errors::io_error("Unexpected error"),
+            })
+            .with_context(|| {
+                format!(
+                    "Error while walking cache index directory at {}",
+                    index.display()
+                )
+            })?;
+        if bucket.file_type().is_dir() {
+            continue;
+        }
+        let entries = bucket_entries(bucket.path()).with_context(|| {
+            format!(
+                "Error getting bucket entries from {}",
+                bucket.path().display()
+            )
+        })?;
+        for se in entries
+            .into_iter()
+            .rev()
+            .collect::<HashSet<SerializableMetadata>>()
+        {
+            if let Some(sri) = se.integrity {
+                referenced.push(sri.parse()?);
+                if let Some(sri) = se.metadata_integrity {
+                    referenced.push(sri.parse()?);
+                }
+            }
+        }
+    }
+    Ok(referenced)
+}
+
+/// Returns the serialized form of `metadata` if it's large enough that it
+/// should be stored outside of its index bucket.
+fn external_metadata(metadata: &Value, threshold: Option<usize>) -> Result<Option<Vec<u8>>> {
+    match threshold {
+        Some(threshold) => {
+            let data = serde_json::to_vec(metadata)
+                .with_context(|| "Failed to serialize entry metadata".into())?;
+            Ok(if data.len() > threshold {
+                Some(data)
+            } else {
+                None
+            })
+        }
+        None => Ok(None),
+    }
+}
+
+/// Replaces `entry`'s metadata with a gzipped, base64-encoded copy of it, unless
+/// that would end up taking more room than the metadata itself.
+#[cfg(feature = "gzip")]
+fn compress_metadata(mut entry: SerializableMetadata) -> Result<SerializableMetadata> {
+    use base64::Engine;
+    use flate2::{write::GzEncoder, Compression};
+
+    let data = serde_json::to_vec(&(&entry.metadata, &entry.raw_metadata))
+        .with_context(|| "Failed to serialize entry metadata".into())?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&data)
+        .and_then(|_| encoder.finish())
+        .map(|compressed| base64::engine::general_purpose::STANDARD.encode(compressed))
+        .map(|compressed| {
+            if compressed.len() < data.len() {
+                entry.metadata = Value::Null;
+                entry.raw_metadata = None;
+                entry.compressed_metadata = Some(compressed);
+            }
+            entry
+        })
+        .with_context(|| "Failed to compress entry metadata".into())
+}
+
+/// Returns `entry`'s inline `metadata` and `raw_metadata`, decompressing
+/// them if they were stored with `compress_metadata()`.
+fn inline_metadata(entry: &SerializableMetadata) -> Result<(Value, Option<Vec<u8>>)> {
+    match &entry.compressed_metadata {
+        Some(compressed) => decompress_metadata(compressed, &entry.key),
+        None => Ok((entry.metadata.clone(), entry.raw_metadata.clone())),
+    }
+}
+
+/// Restores metadata that was stored with `compress_metadata()`.
+#[cfg(feature = "gzip")]
+fn decompress_metadata(compressed: &str, key: &str) -> Result<(Value, Option<Vec<u8>>)> {
+    use base64::Engine;
+
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(compressed)
+        .map_err(crate::errors::io_error)
+        .with_context(|| format!("Failed to decode compressed metadata for key `{key}`"))?;
+    serde_json::from_reader(flate2::read::GzDecoder::new(&data[..]))
+        .with_context(|| format!("Failed to decompress metadata for key `{key}`"))
+}
+
+/// Metadata stored with `compress_metadata()` can only be read back by builds
+/// with the `gzip` feature.
+#[cfg(not(feature = "gzip"))]
+fn decompress_metadata(_compressed: &str, key: &str) -> Result<(Value, Option<Vec<u8>>)> {
+    Err(crate::errors::io_error(
+        "reading compressed metadata requires the `gzip` feature",
+    ))
+    .with_context(|| format!("Failed to decompress metadata for key `{key}`"))
+}
+
+fn parse_external_metadata(data: &[u8], key: &str) -> Result<Value> {
+    serde_json::from_slice(data)
+        .with_context(|| format!("Failed to parse external metadata for key `{key}`"))
+}
+
+/// Directory the index of `cache` is stored under, in the default layout.
+fn index_dir(cache: &Path) -> PathBuf {
+    CacheDir::from(cache).index_dir()
+}
+
+/// Returns the path of the index bucket that entries for `key` are stored
+/// in, without touching the filesystem. The key is used exactly as given, so
+/// for a `Cache` with a [`KeyNormalizer`], pass the normalized key. The path
+/// is in the default layout, so it doesn't apply to caches with a
+/// `CacheConfig` that moves their index directory.
+///
+/// ## Example
+/// ```no_run
+/// let bucket = cacache::bucket_path_for_key("./my-cache", "my-key");
+/// println!("entries for my-key live in {}", bucket.display());
+/// ```
+pub fn bucket_path_for_key<P, K>(cache: P, key: K) -> PathBuf
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    bucket_path(cache.as_ref().into(), key.as_ref())
+}
+
+fn bucket_path(cache: CacheDir<'_>, key: &str) -> PathBuf {
     let hashed = hash_key(key);
     cache
-        .join(format!("index-v{INDEX_VERSION}"))
+        .index_dir()
         .join(&hashed[0..2])
         .join(&hashed[2..4])
         .join(&hashed[4..])
 }
 
+/// Fails with `Error::KeyTooLong` if `key`, once normalized, can't be
+/// written to the index.
+pub(crate) fn validate_key(key: &str, normalizer: Option<KeyNormalizer>) -> Result<()> {
+    check_key_length(&normalize_key(key, normalizer))
+}
+
+fn check_key_length(key: &str) -> Result<()> {
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(Error::KeyTooLong(MAX_KEY_LENGTH, key.len()));
+    }
+    Ok(())
+}
+
+fn normalize_key(key: &str, normalizer: Option<KeyNormalizer>) -> Cow<'_, str> {
+    match normalizer {
+        Some(normalize) => Cow::Owned(normalize(key)),
+        None => Cow::Borrowed(key),
+    }
+}
+
 fn hash_key(key: &str) -> String {
     let mut hasher = Sha1::new();
     hasher.update(key);
     hex::encode(hasher.finalize())
 }
 
+/// Formats a serialized entry as a line to append to a bucket. Buckets are
+/// parsed line by line, and each line is split into its hash and entry on a
+/// tab, so the entry must not contain either. Compact JSON output never does:
+/// serde_json escapes every control character inside strings (so keys and
+/// metadata containing `\n` or `\t` are stored as `"\\n"` and `"\\t"`),
+/// and it emits no whitespace between tokens.
+fn bucket_line(stringified: &str) -> String {
+    debug_assert!(!stringified.contains(['\n', '\r', '\t']));
+    format!("\n{}\t{}", hash_entry(stringified), stringified)
+}
+
+/// Offset of the entry in bucket line `out`, which was appended to a bucket
+/// and ended at `end`. This skips the newline the line starts with.
+fn line_offset(end: u64, out: &str) -> u64 {
+    end - out.len() as u64 + 1
+}
+
+/// Parses the entries in `tail`, the part of a bucket starting at an offset
+/// returned by `insert_detailed()`. Returns `None` if `tail` doesn't start
+/// with a valid entry for `key`, meaning the offset is stale.
+fn entries_from_offset(tail: &str, key: &str) -> Option<Vec<SerializableMetadata>> {
+    let mut lines = tail.lines();
+    let first = parse_entry(lines.next()?).filter(|entry| entry.key == key)?;
+    Some(
+        std::iter::once(first)
+            .chain(lines.filter_map(parse_entry))
+            .collect(),
+    )
+}
+
 fn hash_entry(key: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(key);
     hex::encode(hasher.finalize())
 }
 
-fn now() -> u128 {
+pub(crate) fn now() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -326,64 +1398,133 @@ fn now() -> u128 {
 }
 
 fn bucket_entries(bucket: &Path) -> std::io::Result<Vec<SerializableMetadata>> {
-    use std::io::{BufRead, BufReader};
-    fs::File::open(bucket)
-        .map(|file| {
-            BufReader::new(file)
-                .lines()
-                .map_while(std::result::Result::ok)
-                .filter_map(|entry| {
-                    let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
-                        [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
-                        // Something's wrong with the entry. Abort.
-                        _ => return None,
-                    };
-                    serde_json::from_str::<SerializableMetadata>(entry_str).ok()
-                })
-                .collect()
-        })
-        .or_else(|err| {
-            if err.kind() == ErrorKind::NotFound {
-                Ok(Vec::new())
-            } else {
-                Err(err)?
-            }
-        })
+    match fs::read(bucket) {
+        Ok(contents) => Ok(parse_bucket(&contents)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 async fn bucket_entries_async(bucket: &Path) -> std::io::Result<Vec<SerializableMetadata>> {
-    let file_result = crate::async_lib::File::open(bucket).await;
-    let file = if let Err(err) = file_result {
-        if err.kind() == ErrorKind::NotFound {
-            return Ok(Vec::new());
-        }
-        return Err(err)?;
-    } else {
-        file_result.unwrap()
+    match crate::async_lib::read(bucket).await {
+        Ok(contents) => Ok(parse_bucket(&contents)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Parses the valid entries in a bucket's `contents`. Every line is parsed on
+/// its own, so a line torn by an interrupted write, even one cut off in the
+/// middle of a multibyte character, is skipped without affecting any other
+/// line. Since lines are written with a leading newline, the next append
+/// after a torn line starts a fresh line of its own.
+fn parse_bucket(contents: &[u8]) -> Vec<SerializableMetadata> {
+    contents
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .filter_map(parse_entry)
+        .collect()
+}
+
+fn parse_entry(line: &str) -> Option<SerializableMetadata> {
+    let entry_str = match line.split('\t').collect::<Vec<&str>>()[..] {
+        [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
+        // Something's wrong with the entry. Abort.
+        _ => return None,
     };
-    let mut vec = Vec::new();
-    let mut lines =
-        crate::async_lib::lines_to_stream(crate::async_lib::BufReader::new(file).lines());
-    while let Some(line) = lines.next().await {
-        if let Ok(entry) = line {
-            let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
-                [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
-                // Something's wrong with the entry. Abort.
-                _ => continue,
-            };
-            if let Ok(serialized) = serde_json::from_str::<SerializableMetadata>(entry_str) {
-                vec.push(serialized);
+    serde_json::from_str::<SerializableMetadata>(entry_str).ok()
+}
+
+/// Returns the lines of a bucket's `contents`, minus the ones for `key`.
+/// Lines that can't be parsed are kept as they are.
+fn without_key(contents: &[u8], key: &str) -> String {
+    String::from_utf8_lossy(contents)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .filter(|line| parse_entry(line).map_or(true, |entry| entry.key != key))
+        .map(|line| format!("\n{line}"))
+        .collect()
+}
+
+/// Rewrites the bucket for `key` without any of its entries, leaving the
+/// entries of other keys that share the bucket in place. The new bucket is
+/// written to a temp file and renamed over the old one, so readers never see
+/// a partial bucket. Just before the rename, the bucket is read again and
+/// any entries appended to it in the meantime are carried over, so
+/// concurrent writes aren't lost.
+fn compact(cache: &Path, key: &str) -> Result<()> {
+    compact_with(cache, key, || ())
+}
+
+/// `compact()`, calling `before_replace` right before the bucket is read
+/// again for entries appended since it was first read.
+fn compact_with(cache: &Path, key: &str, before_replace: impl Fn()) -> Result<()> {
+    let bucket = bucket_path(cache.into(), key);
+    let tmp_path = cache.join("tmp");
+    loop {
+        let contents = match fs::read(&bucket) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            contents => {
+                contents.with_context(|| format!("Failed to read index bucket at {bucket:?}"))?
+            }
+        };
+        let kept = without_key(&contents, key);
+        fs::create_dir_all(&tmp_path)
+            .with_context(|| format!("Failed to create temp directory at {tmp_path:?}"))?;
+        let mut tmp = tempfile::NamedTempFile::new_in(&tmp_path)
+            .with_context(|| format!("Failed to create temp file in {tmp_path:?}"))?;
+        tmp.write_all(kept.as_bytes())
+            .with_context(|| format!("Failed to write compacted bucket to {:?}", tmp.path()))?;
+        before_replace();
+        let current = match fs::read(&bucket) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            current => {
+                current.with_context(|| format!("Failed to read index bucket at {bucket:?}"))?
             }
+        };
+        // Writers only ever append, so anything else means the bucket was
+        // rewritten by someone else, and this has to start over.
+        let appended = match current.strip_prefix(&contents[..]) {
+            Some(appended) => appended,
+            None => continue,
+        };
+        if kept.is_empty() && appended.is_empty() {
+            return fs::remove_file(&bucket)
+                .with_context(|| format!("Failed to remove bucket at {bucket:?}"));
         }
+        tmp.write_all(appended)
+            .with_context(|| format!("Failed to write compacted bucket to {:?}", tmp.path()))?;
+        tmp.persist(&bucket)
+            .map_err(|e| e.error)
+            .with_context(|| format!("Failed to replace index bucket at {bucket:?}"))?;
+        return Ok(());
     }
-    Ok(vec)
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn compact_async(cache: &Path, key: &str) -> Result<()> {
+    let (cache, key) = (cache.to_path_buf(), key.to_owned());
+    crate::async_lib::run_blocking(move || compact(&cache, &key)).await
 }
 
 /// Builder for options and flags for remove cache entry.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     // Removes the index entry and its content, even if other keys share it.
+///     cacache::RemoveOpts::new()
+///         .remove_fully(true)
+///         .remove_sync("./my-cache", "my-key")?;
+///     Ok(())
+/// }
+/// ```
 #[derive(Clone, Default)]
 pub struct RemoveOpts {
     pub(crate) remove_fully: bool,
+    pub(crate) compact_bucket: bool,
 }
 
 impl RemoveOpts {
@@ -399,6 +1540,15 @@ impl RemoveOpts {
         self
     }
 
+    /// Set the compact bucket option
+    /// If compact_bucket is set to true then the key's lines are physically removed from its index bucket, rather than appending a null, while other keys in the bucket and the content itself are left alone.
+    /// Ignored if remove_fully is also set.
+    /// This makes removal cost a rewrite of the bucket, but keeps buckets from growing with tombstones, which suits caches that churn through a small set of keys.
+    pub fn compact_bucket(mut self, compact_bucket: bool) -> Self {
+        self.compact_bucket = compact_bucket;
+        self
+    }
+
     /// Removes an individual index metadata entry.
     /// If remove_fully is set to false (default), the associated content will be left in the cache.
     /// If remove_fully is true, both the index entry and the contents will be physically removed from the disk
@@ -408,14 +1558,19 @@ impl RemoveOpts {
         K: AsRef<str>,
     {
         if !self.remove_fully {
-            delete(cache.as_ref(), key.as_ref())
+            if self.compact_bucket {
+                compact(cache.as_ref(), key.as_ref())
+            } else {
+                delete(cache.as_ref(), key.as_ref())
+            }
         } else {
             if let Some(meta) = crate::metadata_sync(cache.as_ref(), key.as_ref())? {
-                let content = content_path(cache.as_ref(), &meta.integrity);
+                let dir = CacheDir::from(cache.as_ref());
+                let content = content_path(dir, &stored_integrity(dir, &meta.integrity));
                 fs::remove_file(&content)
                     .with_context(|| format!("Failed to remove content at {content:?}"))?;
             }
-            let bucket = bucket_path(cache.as_ref(), key.as_ref());
+            let bucket = bucket_path(cache.as_ref().into(), key.as_ref());
             fs::remove_file(&bucket)
                 .with_context(|| format!("Failed to remove bucket at {bucket:?}"))
         }
@@ -424,22 +1579,27 @@ impl RemoveOpts {
     /// Removes an individual index metadata entry.
     /// If remove_fully is set to false (default), the associated content will be left in the cache.
     /// If remove_fully is true, both the index entry and the contents will be physically removed from the disk
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     pub async fn remove<P, K>(self, cache: P, key: K) -> Result<()>
     where
         P: AsRef<Path>,
         K: AsRef<str>,
     {
         if !self.remove_fully {
-            delete_async(cache.as_ref(), key.as_ref()).await
+            if self.compact_bucket {
+                compact_async(cache.as_ref(), key.as_ref()).await
+            } else {
+                delete_async(cache.as_ref(), key.as_ref()).await
+            }
         } else {
             if let Some(meta) = crate::metadata(cache.as_ref(), key.as_ref()).await? {
-                let content = content_path(cache.as_ref(), &meta.integrity);
+                let dir = CacheDir::from(cache.as_ref());
+                let content = content_path(dir, &stored_integrity(dir, &meta.integrity));
                 crate::async_lib::remove_file(&content)
                     .await
                     .with_context(|| format!("Failed to remove content at {content:?}"))?;
             }
-            let bucket = bucket_path(cache.as_ref(), key.as_ref());
+            let bucket = bucket_path(cache.as_ref().into(), key.as_ref());
             crate::async_lib::remove_file(&bucket)
                 .await
                 .with_context(|| format!("Failed to remove bucket at {bucket:?}"))
@@ -452,7 +1612,9 @@ mod tests {
     use super::*;
     use serde_json::json;
 
-    #[cfg(feature = "async-std")]
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use async_attributes::test as async_test;
     #[cfg(feature = "tokio")]
     use tokio::test as async_test;
@@ -476,11 +1638,185 @@ mod tests {
         let time = 1_234_567;
         let opts = WriteOpts::new().integrity(sri).time(time);
         insert(&dir, "hello", opts).unwrap();
-        let entry = std::fs::read_to_string(bucket_path(&dir, "hello")).unwrap();
+        let entry = std::fs::read_to_string(bucket_path(dir.as_path().into(), "hello")).unwrap();
         assert_eq!(entry, MOCK_ENTRY);
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[test]
+    fn link_key_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_hash_sync(&dir, b"hello").unwrap();
+        link_key(&dir, "hello", &sri, WriteOpts::new(), true).unwrap();
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"hello");
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().size, 5);
+
+        let missing = crate::write_hash_sync(tmp.path().join("other"), b"nope").unwrap();
+        assert!(link_key(&dir, "nope", &missing, WriteOpts::new(), false).is_err());
+        assert!(matches!(
+            link_key(&dir, "hello", &sri, WriteOpts::new().size(3), false),
+            Err(crate::Error::SizeMismatch(3, 5))
+        ));
+
+        // Corrupted content is only caught when asked to verify it.
+        fs::write(crate::content_path(&dir, &sri), b"jello").unwrap();
+        assert!(matches!(
+            link_key(&dir, "corrupt", &sri, WriteOpts::new(), true),
+            Err(crate::Error::ReadIntegrityMismatch(..))
+        ));
+        assert_eq!(find(&dir, "corrupt").unwrap(), None);
+        link_key(&dir, "corrupt", &sri, WriteOpts::new(), false).unwrap();
+        assert!(find(&dir, "corrupt").unwrap().is_some());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn link_key_async_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_hash(&dir, b"hello").await.unwrap();
+        link_key_async(&dir, "hello", &sri, WriteOpts::new(), true)
+            .await
+            .unwrap();
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+
+        fs::write(crate::content_path(&dir, &sri), b"jello").unwrap();
+        assert!(
+            link_key_async(&dir, "corrupt", &sri, WriteOpts::new(), true)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn find_many_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "world", WriteOpts::new().integrity(sri.clone())).unwrap();
+        // Plant a colliding entry in "hello"'s bucket. Looking it up must not
+        // leak it into the results for "hello".
+        let bucket = bucket_path(dir.as_path().into(), "hello");
+        let line = MOCK_ENTRY.replace("\"hello\"", "\"collides\"");
+        let (_, json) = line.trim_start().split_once('\t').unwrap();
+        let mut contents = fs::read_to_string(&bucket).unwrap();
+        contents.push_str(&format!("\n{}\t{}", hash_entry(json), json));
+        fs::write(&bucket, contents).unwrap();
+
+        let found = find_many(&dir, &["world", "hello", "missing", "hello"]).unwrap();
+        let keys = found
+            .iter()
+            .map(|entry| entry.as_ref().map(|e| e.key.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [Some("world"), Some("hello"), None, Some("hello")]);
+        assert_eq!(found[1], find(&dir, "hello").unwrap());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn find_many_async_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "world", WriteOpts::new().integrity(sri)).unwrap();
+        delete(&dir, "world").unwrap();
+
+        let found = find_many_async(&dir, &["hello", "world", "hello"])
+            .await
+            .unwrap();
+        assert_eq!(found[0], find(&dir, "hello").unwrap());
+        assert_eq!(found[1], None);
+        assert_eq!(found[2], found[0]);
+    }
+
+    #[test]
+    fn insert_content_headers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let opts = WriteOpts::new()
+            .integrity(sri)
+            .mime("application/json")
+            .filename("package.json");
+        insert(&dir, "hello", opts).unwrap();
+        let entry = find(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.mime.as_deref(), Some("application/json"));
+        assert_eq!(entry.filename.as_deref(), Some("package.json"));
+        let listed = ls(&dir).next().unwrap().unwrap();
+        assert_eq!(listed, entry);
+    }
+
+    #[test]
+    fn insert_external_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let metadata = serde_json::json!({ "blob": "x".repeat(8 * 1024) });
+        let opts = WriteOpts::new()
+            .integrity(sri.clone())
+            .metadata(metadata.clone())
+            .external_metadata(1024);
+        insert(&dir, "hello", opts).unwrap();
+        let bucket = std::fs::read_to_string(bucket_path(dir.as_path().into(), "hello")).unwrap();
+        assert!(bucket.len() < 1024);
+        let entry = find(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.integrity, sri);
+        assert_eq!(entry.metadata, metadata);
+        let listed = ls(&dir).next().unwrap().unwrap();
+        assert_eq!(listed.metadata, metadata);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn insert_compressed_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let metadata = serde_json::json!({ "blob": "x".repeat(8 * 1024) });
+        let opts = WriteOpts::new()
+            .integrity(sri.clone())
+            .metadata(metadata.clone())
+            .raw_metadata(b"raw".to_vec())
+            .compress_metadata(true);
+        insert(&dir, "hello", opts).unwrap();
+        let bucket = std::fs::read_to_string(bucket_path(dir.as_path().into(), "hello")).unwrap();
+        assert!(bucket.len() < 1024);
+        let entry = find(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.integrity, sri);
+        assert_eq!(entry.metadata, metadata);
+        assert_eq!(entry.raw_metadata, Some(b"raw".to_vec()));
+        let listed = ls(&dir).next().unwrap().unwrap();
+        assert_eq!(listed.metadata, metadata);
+    }
+
+    #[test]
+    fn corrupt_compressed_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "other", WriteOpts::new().integrity(sri)).unwrap();
+        let bucket = bucket_path(dir.as_path().into(), "hello");
+        fs::create_dir_all(bucket.parent().unwrap()).unwrap();
+        let json = MOCK_ENTRY
+            .trim_start()
+            .split_once('\t')
+            .unwrap()
+            .1
+            .replace("}", ",\"compressed_metadata\":\"not gzip\"}");
+        fs::write(&bucket, format!("\n{}\t{}", hash_entry(&json), json)).unwrap();
+
+        assert!(find(&dir, "hello").is_err());
+        assert!(ls(&dir).any(|entry| entry.is_err()));
+        // Other lookups don't need to decompress it, and removal isn't
+        // blocked by it.
+        assert!(find(&dir, "other").unwrap().is_some());
+        delete(&dir, "hello").unwrap();
+        assert_eq!(find(&dir, "hello").unwrap(), None);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn insert_async_basic() {
         let tmp = tempfile::tempdir().unwrap();
@@ -491,7 +1827,7 @@ mod tests {
         futures::executor::block_on(async {
             insert_async(&dir, "hello", opts).await.unwrap();
         });
-        let entry = std::fs::read_to_string(bucket_path(&dir, "hello")).unwrap();
+        let entry = std::fs::read_to_string(bucket_path(dir.as_path().into(), "hello")).unwrap();
         assert_eq!(entry, MOCK_ENTRY);
     }
 
@@ -501,7 +1837,7 @@ mod tests {
         let dir = tmp.path().to_owned();
         let sri: Integrity = "sha1-deadbeef".parse().unwrap();
         let time = 1_234_567;
-        let bucket = bucket_path(&dir, "hello");
+        let bucket = bucket_path(dir.as_path().into(), "hello");
         fs::create_dir_all(bucket.parent().unwrap()).unwrap();
         fs::write(bucket, MOCK_ENTRY).unwrap();
         let entry = find(&dir, "hello").unwrap().unwrap();
@@ -514,10 +1850,113 @@ mod tests {
                 size: 0,
                 metadata: json!(null),
                 raw_metadata: None,
+                content_encoding: None,
+                mime: None,
+                filename: None,
             }
         );
     }
 
+    /// Appends the start of an entry line to `key`'s bucket, cut off in the
+    /// middle of a multibyte character, as an interrupted write would.
+    fn append_torn_line(dir: &Path, key: &str) {
+        use std::io::Write;
+        let line = bucket_line(r#"{"key":"héllo","integrity":"sha1-deadbeef"}"#);
+        let torn = &line.as_bytes()[..line.find('é').unwrap() + 1];
+        fs::OpenOptions::new()
+            .append(true)
+            .open(bucket_path(dir.into(), key))
+            .unwrap()
+            .write_all(torn)
+            .unwrap();
+    }
+
+    #[test]
+    fn torn_line_recovery() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let first: Integrity = "sha1-deadbeef".parse().unwrap();
+        let second: Integrity = "sha1-badc0ffee".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(first.clone())).unwrap();
+        append_torn_line(&dir, "hello");
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().integrity, first);
+
+        insert(&dir, "hello", WriteOpts::new().integrity(second.clone())).unwrap();
+        let entries = bucket_entries(&bucket_path(dir.as_path().into(), "hello")).unwrap();
+        let integrities = entries
+            .iter()
+            .map(|entry| entry.integrity.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(integrities, ["sha1-deadbeef", "sha1-badc0ffee"]);
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().integrity, second);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn torn_line_recovery_async() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let first: Integrity = "sha1-deadbeef".parse().unwrap();
+        let second: Integrity = "sha1-badc0ffee".parse().unwrap();
+        insert_async(&dir, "hello", WriteOpts::new().integrity(first))
+            .await
+            .unwrap();
+        append_torn_line(&dir, "hello");
+        insert_async(&dir, "hello", WriteOpts::new().integrity(second.clone()))
+            .await
+            .unwrap();
+        let entries = bucket_entries_async(&bucket_path(dir.as_path().into(), "hello"))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        let entry = find_async(&dir, "hello").await.unwrap().unwrap();
+        assert_eq!(entry.integrity, second);
+    }
+
+    #[test]
+    fn find_at_offset() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let first: Integrity = "sha1-deadbeef".parse().unwrap();
+        let second: Integrity = "sha1-badc0ffee".parse().unwrap();
+        let (_, offset) =
+            insert_detailed(&dir, "hello", WriteOpts::new().integrity(first.clone())).unwrap();
+        let entry = find_at(&dir, "hello", offset).unwrap().unwrap();
+        assert_eq!(entry.integrity, first);
+
+        // Later entries for the key are still picked up.
+        insert(&dir, "hello", WriteOpts::new().integrity(second.clone())).unwrap();
+        let entry = find_at(&dir, "hello", offset).unwrap().unwrap();
+        assert_eq!(entry.integrity, second);
+
+        // Stale offsets fall back to a full read of the bucket.
+        let entry = find_at(&dir, "hello", offset + 3).unwrap().unwrap();
+        assert_eq!(entry.integrity, second);
+
+        delete(&dir, "hello").unwrap();
+        assert_eq!(find_at(&dir, "hello", offset).unwrap(), None);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn find_at_offset_async() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert_async(&dir, "hello", WriteOpts::new().integrity(sri.clone()))
+            .await
+            .unwrap();
+        let (_, offset) = insert_detailed_async(
+            &dir,
+            "hello",
+            WriteOpts::new().integrity(sri.clone()).size(5),
+        )
+        .await
+        .unwrap();
+        let entry = find_at_async(&dir, "hello", offset).await.unwrap().unwrap();
+        assert_eq!(entry.size, 5);
+    }
+
     #[test]
     fn find_none() {
         let tmp = tempfile::tempdir().unwrap();
@@ -525,6 +1964,21 @@ mod tests {
         assert_eq!(find(&dir, "hello").unwrap(), None);
     }
 
+    #[test]
+    fn insert_normalized() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let opts = WriteOpts::new()
+            .integrity(sri.clone())
+            .key_normalizer(|key| key.to_lowercase());
+        insert(&dir, "Hello", opts).unwrap();
+        let entry = find(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.key, "hello");
+        assert_eq!(entry.integrity, sri);
+        assert_eq!(find(&dir, "Hello").unwrap(), None);
+    }
+
     #[test]
     fn delete_basic() {
         let tmp = tempfile::tempdir().unwrap();
@@ -537,7 +1991,7 @@ mod tests {
         assert_eq!(find(&dir, "hello").unwrap(), None);
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn delete_async_basic() {
         let tmp = tempfile::tempdir().unwrap();
@@ -556,7 +2010,7 @@ mod tests {
     fn delete_fully() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let content = content_path(&dir, &"sha1-deadbeef".parse().unwrap());
+        let content = content_path(dir.as_path().into(), &"sha1-deadbeef".parse().unwrap());
         fs::create_dir_all(content.parent().unwrap()).unwrap();
         fs::write(content.as_path(), "hello").unwrap();
         let sri: Integrity = "sha1-deadbeef".parse().unwrap();
@@ -570,12 +2024,12 @@ mod tests {
         assert!(!content.exists());
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn delete_fully_async() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let content = content_path(&dir, &"sha1-deadbeef".parse().unwrap());
+        let content = content_path(dir.as_path().into(), &"sha1-deadbeef".parse().unwrap());
         fs::create_dir_all(content.parent().unwrap()).unwrap();
         fs::write(content.as_path(), "hello").unwrap();
         let sri: Integrity = "sha1-deadbeef".parse().unwrap();
@@ -590,6 +2044,194 @@ mod tests {
         assert!(!content.exists());
     }
 
+    #[test]
+    fn delete_compacted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let content = content_path(dir.as_path().into(), &"sha1-deadbeef".parse().unwrap());
+        fs::create_dir_all(content.parent().unwrap()).unwrap();
+        fs::write(content.as_path(), "hello").unwrap();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "other", WriteOpts::new().integrity(sri)).unwrap();
+        // Make "other" share a bucket with "hello".
+        let bucket = bucket_path(dir.as_path().into(), "hello");
+        let other = fs::read_to_string(bucket_path(dir.as_path().into(), "other")).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&bucket)
+            .unwrap()
+            .write_all(other.as_bytes())
+            .unwrap();
+
+        RemoveOpts::new()
+            .compact_bucket(true)
+            .remove_sync(&dir, "hello")
+            .unwrap();
+        assert_eq!(find(&dir, "hello").unwrap(), None);
+        let entries = bucket_entries(&bucket).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "other");
+        assert_eq!(fs::read_to_string(&bucket).unwrap(), other);
+        assert!(content.exists());
+
+        RemoveOpts::new()
+            .compact_bucket(true)
+            .remove_sync(&dir, "other")
+            .unwrap();
+        assert!(!bucket_path(dir.as_path().into(), "other").exists());
+    }
+
+    #[test]
+    fn delete_compacted_concurrent_append() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "other", WriteOpts::new().integrity(sri.clone())).unwrap();
+        let bucket = bucket_path(dir.as_path().into(), "hello");
+        let other = fs::read_to_string(bucket_path(dir.as_path().into(), "other")).unwrap();
+
+        // Another key sharing the bucket, and a fresh write to the key being
+        // compacted, both land while the bucket is being rewritten.
+        compact_with(&dir, "hello", || {
+            fs::OpenOptions::new()
+                .append(true)
+                .open(&bucket)
+                .unwrap()
+                .write_all(other.as_bytes())
+                .unwrap();
+            insert(
+                &dir,
+                "hello",
+                WriteOpts::new().integrity(sri.clone()).time(1),
+            )
+            .unwrap();
+        })
+        .unwrap();
+        let entries = bucket_entries(&bucket).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "other");
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().time, 1);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn delete_compacted_async() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "other", WriteOpts::new().integrity(sri)).unwrap();
+        let bucket = bucket_path(dir.as_path().into(), "hello");
+        let other = fs::read_to_string(bucket_path(dir.as_path().into(), "other")).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&bucket)
+            .unwrap()
+            .write_all(other.as_bytes())
+            .unwrap();
+
+        RemoveOpts::new()
+            .compact_bucket(true)
+            .remove(&dir, "hello")
+            .await
+            .unwrap();
+        assert_eq!(find(&dir, "hello").unwrap(), None);
+        assert_eq!(fs::read_to_string(&bucket).unwrap(), other);
+    }
+
+    #[test]
+    fn large_size() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let size = u64::from(u32::MAX) + 1;
+        insert(&dir, "hello", WriteOpts::new().integrity(sri).size(size)).unwrap();
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().size, size);
+    }
+
+    #[test]
+    fn bucket_path_for_key_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let bucket = crate::bucket_path_for_key(&dir, "hello");
+        assert!(bucket.starts_with(dir.join("index-v5")));
+        assert!(!bucket.exists());
+        crate::write_sync(&dir, "hello", b"hello").unwrap();
+        assert!(fs::read_to_string(&bucket).unwrap().contains("\"hello\""));
+    }
+
+    #[test]
+    fn key_too_long() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let key = "k".repeat(10 * 1024 * 1024);
+        assert!(matches!(
+            crate::write_sync(&dir, &key, b"hello"),
+            Err(Error::KeyTooLong(MAX_KEY_LENGTH, len)) if len == key.len()
+        ));
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        assert!(matches!(
+            insert(&dir, &key, WriteOpts::new().integrity(sri.clone())),
+            Err(Error::KeyTooLong(..))
+        ));
+        assert!(!dir.join("index-v5").exists());
+
+        let key = "k".repeat(MAX_KEY_LENGTH);
+        insert(&dir, &key, WriteOpts::new().integrity(sri)).unwrap();
+        assert_eq!(find(&dir, &key).unwrap().unwrap().key, key);
+    }
+
+    #[test]
+    fn entry_alias() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let entry: Option<crate::Entry> = crate::metadata_sync(&dir, "hello").unwrap();
+        assert_eq!(entry.unwrap().key, "hello");
+    }
+
+    #[test]
+    fn metadata_algorithm() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync_with_algo(Algorithm::Xxh3, &dir, "xxh3", b"hello").unwrap();
+        crate::write_sync(&dir, "default", b"hello").unwrap();
+        let entry = find(&dir, "xxh3").unwrap().unwrap();
+        assert_eq!(entry.algorithm(), Algorithm::Xxh3);
+        let entry = find(&dir, "default").unwrap().unwrap();
+        assert_eq!(entry.algorithm(), Algorithm::Sha256);
+    }
+
+    #[test]
+    fn control_characters_in_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let key = "hello\n\tworld\r\u{0}";
+        insert(
+            &dir,
+            key,
+            WriteOpts::new()
+                .integrity(sri.clone())
+                .metadata(json!({ "line": "one\ntwo\tthree" })),
+        )
+        .unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+
+        let bucket = fs::read_to_string(bucket_path(dir.as_path().into(), key)).unwrap();
+        assert_eq!(bucket.lines().filter(|line| !line.is_empty()).count(), 1);
+        let entry = find(&dir, key).unwrap().unwrap();
+        assert_eq!(entry.key, key);
+        assert_eq!(entry.integrity, sri);
+        assert_eq!(entry.metadata, json!({ "line": "one\ntwo\tthree" }));
+        let mut keys = ls(&dir).map(|entry| entry.unwrap().key).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["hello".to_string(), key.to_string()]);
+    }
+
     #[test]
     fn round_trip() {
         let tmp = tempfile::tempdir().unwrap();
@@ -608,11 +2250,14 @@ mod tests {
                 size: 0,
                 metadata: json!(null),
                 raw_metadata: None,
+                content_encoding: None,
+                mime: None,
+                filename: None,
             }
         );
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn round_trip_async() {
         let tmp = tempfile::tempdir().unwrap();
@@ -635,6 +2280,9 @@ mod tests {
                 size: 0,
                 metadata: json!(null),
                 raw_metadata: None,
+                content_encoding: None,
+                mime: None,
+                filename: None,
             }
         );
     }
@@ -655,21 +2303,102 @@ mod tests {
     }
 
     #[test]
-    fn ls_basic_with_delete() {
+    fn migrate_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let old_index = dir.join("index-v4");
+        let bucket = old_index.join(
+            bucket_path(dir.as_path().into(), "hello")
+                .strip_prefix(dir.join(format!("index-v{INDEX_VERSION}")))
+                .unwrap(),
+        );
+        fs::create_dir_all(bucket.parent().unwrap()).unwrap();
+        let json = MOCK_ENTRY
+            .trim_start()
+            .split_once('\t')
+            .unwrap()
+            .1
+            .replace("}", ",\"content_encoding\":\"gzip\"}");
+        fs::write(bucket, format!("\n{}\t{}", hash_entry(&json), json)).unwrap();
+        assert_eq!(find(&dir, "hello").unwrap(), None);
+
+        assert_eq!(migrate(&dir, "4", true).unwrap(), 1);
+        let entry = find(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.integrity, "sha1-deadbeef".parse().unwrap());
+        assert_eq!(entry.time, 1_234_567);
+        assert_eq!(entry.content_encoding, Some(ContentEncoding::Gzip));
+        assert!(!old_index.exists());
+
+        assert_eq!(migrate(&dir, "4", true).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_rejects_bad_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        let outside = tmp.path().join("x");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        for version in ["", "../x", "4/../../x", "v4"] {
+            assert!(migrate(&dir, version, true).is_err(), "{version:?}");
+        }
+        assert!(outside.exists());
+    }
+
+    /// Writes an entry for `key` into the version 4 index of `dir`.
+    fn write_v4_entry(dir: &Path, key: &str) {
+        let bucket = dir.join("index-v4").join(
+            bucket_path(dir.into(), key)
+                .strip_prefix(index_dir(dir))
+                .unwrap(),
+        );
+        fs::create_dir_all(bucket.parent().unwrap()).unwrap();
+        let json = serde_json::json!({
+            "key": key,
+            "integrity": "sha1-deadbeef",
+            "time": 1_234_567,
+            "size": 0,
+            "metadata": null,
+            "raw_metadata": null,
+        })
+        .to_string();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(bucket)
+            .unwrap();
+        write!(file, "\n{}\t{}", hash_entry(&json), json).unwrap();
+    }
+
+    #[test]
+    fn migrate_twice() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
+        write_v4_entry(&dir, "hello");
+        write_v4_entry(&dir, "world");
         let sri: Integrity = "sha1-deadbeef".parse().unwrap();
-        let time = 1_234_567;
-        let opts = WriteOpts::new().integrity(sri.clone()).time(time);
-        insert(&dir, "hello", opts).unwrap();
-        let opts = WriteOpts::new().integrity(sri).time(time);
-        insert(&dir, "world", opts).unwrap();
+        insert(&dir, "world", WriteOpts::new().integrity(sri).time(1)).unwrap();
 
-        let entries = ls_entries(&dir);
-        assert_eq!(entries, vec![String::from("hello"), String::from("world")]);
+        assert_eq!(migrate(&dir, "4", false).unwrap(), 1);
+        assert_eq!(migrate(&dir, "4", false).unwrap(), 0);
+        let bucket = bucket_path(dir.as_path().into(), "hello");
+        assert_eq!(bucket_entries(&bucket).unwrap().len(), 1);
+        // Entries already in the current index aren't overwritten.
+        assert_eq!(find(&dir, "world").unwrap().unwrap().time, 1);
+    }
 
-        delete(&dir, "hello").unwrap();
-        let entries = ls_entries(&dir);
-        assert_eq!(entries, vec![String::from("world")])
+    #[test]
+    fn migrate_long_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        write_v4_entry(&dir, "hello");
+        write_v4_entry(&dir, &"k".repeat(MAX_KEY_LENGTH + 1));
+
+        assert!(matches!(
+            migrate(&dir, "4", true),
+            Err(Error::KeyTooLong(..))
+        ));
+        assert_eq!(find(&dir, "hello").unwrap(), None);
+        assert!(dir.join("index-v4").exists());
     }
 }
@@ -105,6 +105,18 @@
 //! }
 //! ```
 //!
+//! ### Other async runtimes
+//!
+//! If you're using an executor other than async-std or tokio, such as `smol`,
+//! the `async-fs` feature provides the async APIs without tying them to any
+//! particular runtime:
+//!
+//! ```toml
+//! # Cargo.toml
+//! [dependencies]
+//! cacache = { version = "X.Y.Z", default-features = false, features = ["async-fs", "mmap"] }
+//! ```
+//!
 //! ### Sync API
 //!
 //! There are also sync APIs available if you don't want to use async/await.
@@ -131,6 +143,14 @@
 //! }
 //! ```
 //!
+//! ### Read-only caches
+//!
+//! Reading never writes to the cache, so a cache directory that's mounted or
+//! marked read-only, such as one baked into a base image, can be read from
+//! as usual. Lookups by key or hash, the `Reader`s, and `exists` only ever
+//! open files for reading. Writing, removing, compacting, and probing for
+//! reflink support are what need write access.
+//!
 //! ### Linking to existing files
 //!
 //! The `link_to` feature enables an additional set of APIs for adding
@@ -155,30 +175,75 @@
 
 #[cfg(all(feature = "async-std", feature = "tokio-runtime"))]
 compile_error!("Only either feature \"async-std\" or \"tokio-runtime\" must be enabled for this crate, not both.");
+#[cfg(all(
+    feature = "async-fs",
+    any(feature = "async-std", feature = "tokio-runtime")
+))]
+compile_error!(
+    "Feature \"async-fs\" can't be enabled together with \"async-std\" or \"tokio-runtime\"."
+);
 
 pub use serde_json::Value;
 pub use ssri::{Algorithm, Integrity};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 mod async_lib;
 
+mod cache;
+mod config;
 mod content;
 mod errors;
 pub mod index;
 
+mod gc;
 mod get;
 #[cfg(feature = "link_to")]
 mod linkto;
 mod ls;
+mod migrate;
 mod put;
+mod read_cache;
 mod rm;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub mod runtime;
 
 pub use errors::{Error, Result};
-pub use index::{Metadata, RemoveOpts};
+pub use index::{bucket_path_for_key, ContentEncoding, Entry, Metadata, RemoveOpts};
 
+pub use cache::*;
+pub use config::CacheConfig;
+pub use gc::*;
 pub use get::*;
 #[cfg(feature = "link_to")]
 pub use linkto::*;
 pub use ls::*;
+pub use migrate::*;
 pub use put::*;
+pub use read_cache::*;
 pub use rm::*;
+
+// Only built without an async runtime, to make sure the sync API stands on
+// its own.
+#[cfg(all(
+    test,
+    not(any(feature = "async-std", feature = "tokio", feature = "async-fs"))
+))]
+mod sync_only {
+    #[test]
+    fn sync_api() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"hello").unwrap();
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"hello");
+        assert_eq!(crate::read_hash_sync(&dir, &sri).unwrap(), b"hello");
+        assert!(crate::exists_sync(&dir, &sri));
+        assert_eq!(crate::metadata_sync(&dir, "key").unwrap().unwrap().size, 5);
+        assert_eq!(crate::list_sync(&dir).count(), 1);
+
+        let cache = crate::Cache::open(&dir).unwrap();
+        assert_eq!(cache.read_sync("key").unwrap(), b"hello");
+        cache.remove_sync("key").unwrap();
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+        crate::clear_sync(&dir).unwrap();
+    }
+}
@@ -1,16 +1,18 @@
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::AsyncRead;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::AsyncReadExt;
+use crate::config::CacheDir;
 use crate::content::linkto;
 use crate::errors::{Error, IoErrorExt, Result};
+use crate::put::CommitGuard;
 use crate::{index, WriteOpts};
 use ssri::{Algorithm, Integrity};
 use std::io::Read;
 use std::path::{Path, PathBuf};
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::pin::Pin;
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::task::{Context as TaskContext, Poll};
 
 const BUF_SIZE: usize = 16 * 1024;
@@ -30,7 +32,7 @@ const PROBE_SIZE: usize = 8;
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn link_to<P, K, T>(cache: P, key: K, target: T) -> Result<Integrity>
 where
     P: AsRef<Path>,
@@ -54,7 +56,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn link_to_hash<P, T>(cache: P, target: T) -> Result<Integrity>
 where
     P: AsRef<Path>,
@@ -106,11 +108,63 @@ where
     SyncToLinker::open_hash(cache, target)?.commit()
 }
 
+/// Checks that the file linked into the `cache` under `key` still matches
+/// the integrity it was linked with, by reading all of it again. Fails with
+/// `Error::LinkTargetChanged` if it was modified or removed since. Entries
+/// that weren't linked are checked the same way as with
+/// [`check_hash_sync`](crate::check_hash_sync).
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::link_to_sync("./my-cache", "my-key", "../my-other-files/my-file.tgz")?;
+///     match cacache::verify_link_sync("./my-cache", "my-key") {
+///         Err(cacache::Error::LinkTargetChanged(..)) => println!("my-file.tgz changed!"),
+///         res => res?,
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn verify_link_sync<P, K>(cache: P, key: K) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> Result<()> {
+        let entry = index::find(cache, key)?
+            .ok_or_else(|| Error::EntryNotFound(cache.to_path_buf(), key.into()))?;
+        let dir = CacheDir::from(cache);
+        let res = crate::content::read::check(dir, &entry.integrity).map_err(|e| e.with_key(key));
+        let cpath = crate::content::path::content_path(
+            dir,
+            &crate::content::path::stored_integrity(dir, &entry.integrity),
+        );
+        let target = match std::fs::read_link(&cpath) {
+            Ok(target) => target,
+            // Not a link, so there's no target that could have changed.
+            Err(_) => return res.map(|_| ()),
+        };
+        match res {
+            Ok(_) => Ok(()),
+            Err(Error::ReadIntegrityMismatch(..)) => Err(Error::LinkTargetChanged(
+                cache.to_path_buf(),
+                key.into(),
+                target,
+            )),
+            Err(Error::IoError(e, _)) if e.kind() == std::io::ErrorKind::NotFound => Err(
+                Error::LinkTargetChanged(cache.to_path_buf(), key.into(), target),
+            ),
+            Err(e) => Err(e),
+        }
+    }
+    inner(cache.as_ref(), key.as_ref())
+}
+
 /// Extend the `WriteOpts` struct with factories for creating `ToLinker` and
 /// `SyncToLinker` instances.
 impl WriteOpts {
     /// Opens the target file handle for reading, returning a ToLinker instance.
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     pub async fn link_to<P, K, T>(self, cache: P, key: K, target: T) -> Result<ToLinker>
     where
         P: AsRef<Path>,
@@ -128,12 +182,13 @@ impl WriteOpts {
                 key: Some(String::from(key)),
                 read: 0,
                 linker: linkto::AsyncToLinker::new(
-                    cache,
+                    CacheDir::new(cache, &opts.layout),
                     opts.algorithm.unwrap_or(Algorithm::Sha256),
                     target,
                 )
                 .await?,
                 opts,
+                guard: CommitGuard::armed("ToLinker"),
             })
         }
         inner(self, cache.as_ref(), key.as_ref(), target.as_ref()).await
@@ -141,7 +196,7 @@ impl WriteOpts {
 
     /// Opens the target file handle for reading, without a key, returning a
     /// ToLinker instance.
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     pub async fn link_to_hash<P, T>(self, cache: P, target: T) -> Result<ToLinker>
     where
         P: AsRef<Path>,
@@ -153,12 +208,13 @@ impl WriteOpts {
                 key: None,
                 read: 0,
                 linker: linkto::AsyncToLinker::new(
-                    cache,
+                    CacheDir::new(cache, &opts.layout),
                     opts.algorithm.unwrap_or(Algorithm::Sha256),
                     target,
                 )
                 .await?,
                 opts,
+                guard: CommitGuard::armed("ToLinker"),
             })
         }
         inner(self, cache.as_ref(), target.as_ref()).await
@@ -178,11 +234,12 @@ impl WriteOpts {
                 key: Some(String::from(key)),
                 read: 0,
                 linker: linkto::ToLinker::new(
-                    cache,
+                    CacheDir::new(cache, &opts.layout),
                     opts.algorithm.unwrap_or(Algorithm::Sha256),
                     target,
                 )?,
                 opts,
+                guard: CommitGuard::armed("SyncToLinker"),
             })
         }
         inner(self, cache.as_ref(), key.as_ref(), target.as_ref())
@@ -201,11 +258,12 @@ impl WriteOpts {
                 key: None,
                 read: 0,
                 linker: linkto::ToLinker::new(
-                    cache,
+                    CacheDir::new(cache, &opts.layout),
                     opts.algorithm.unwrap_or(Algorithm::Sha256),
                     target,
                 )?,
                 opts,
+                guard: CommitGuard::armed("SyncToLinker"),
             })
         }
         inner(self, cache.as_ref(), target.as_ref())
@@ -217,25 +275,26 @@ impl WriteOpts {
 ///
 /// Make sure to call `.commit()` when done reading to actually add the file to
 /// the cache.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub struct ToLinker {
     cache: PathBuf,
     key: Option<String>,
-    read: usize,
+    read: u64,
     pub(crate) linker: linkto::AsyncToLinker,
     opts: WriteOpts,
+    guard: CommitGuard,
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncRead for ToLinker {
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut TaskContext<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
         let amt = futures::ready!(Pin::new(&mut self.linker).poll_read(cx, buf))?;
-        self.read += amt;
+        self.read += amt as u64;
         Poll::Ready(Ok(amt))
     }
 
@@ -247,19 +306,19 @@ impl AsyncRead for ToLinker {
     ) -> Poll<tokio::io::Result<()>> {
         let pre_len = buf.filled().len();
         futures::ready!(Pin::new(&mut self.linker).poll_read(cx, buf))?;
-        self.read += buf.filled().len() - pre_len;
+        self.read += (buf.filled().len() - pre_len) as u64;
         Poll::Ready(Ok(()))
     }
 }
 
-fn filesize(target: &Path) -> Result<usize> {
+fn filesize(target: &Path) -> Result<u64> {
     Ok(target
         .metadata()
         .with_context(|| format!("Failed to get metadata of {}", target.display()))?
-        .len() as usize)
+        .len())
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl ToLinker {
     /// Creates a new asynchronous readable file handle into the cache.
     pub async fn open<P, K, T>(cache: P, key: K, target: T) -> Result<Self>
@@ -302,11 +361,17 @@ impl ToLinker {
     /// manually in order to complete the writing process, otherwise everything
     /// will be thrown out.
     pub async fn commit(mut self) -> Result<Integrity> {
+        self.guard.disarm();
         self.consume().await?;
         let linker_sri = self.linker.commit().await?;
         if let Some(sri) = &self.opts.sri {
             if sri.matches(&linker_sri).is_none() {
-                return Err(ssri::Error::IntegrityCheckError(sri.clone(), linker_sri).into());
+                return Err(Error::WriteIntegrityMismatch(
+                    self.cache,
+                    self.key,
+                    sri.clone(),
+                    linker_sri,
+                ));
             }
         } else {
             self.opts.sri = Some(linker_sri.clone());
@@ -323,6 +388,14 @@ impl ToLinker {
         }
     }
 
+    /// Gives up on linking the target file without adding anything to the
+    /// cache. Nothing is written until `commit()`, so this only closes the
+    /// target file, the same as dropping the linker does.
+    pub fn abort(mut self) -> Result<()> {
+        self.guard.disarm();
+        Ok(())
+    }
+
     // "Consume" the remainder of the reader, so that the integrity is properly
     // calculated.
     async fn consume(&mut self) -> Result<()> {
@@ -332,7 +405,7 @@ impl ToLinker {
         if self.context_read(&mut probe).await? > 0 {
             // Make sure all the bytes are read so that the integrity is
             // properly calculated.
-            let mut buf = [0; BUF_SIZE];
+            let mut buf = vec![0; self.opts.read_buffer_size.unwrap_or(BUF_SIZE)];
             while self.context_read(&mut buf).await? > 0 {}
         }
         Ok(())
@@ -353,15 +426,16 @@ impl ToLinker {
 pub struct SyncToLinker {
     cache: PathBuf,
     key: Option<String>,
-    read: usize,
+    read: u64,
     pub(crate) linker: linkto::ToLinker,
     opts: WriteOpts,
+    guard: CommitGuard,
 }
 
 impl std::io::Read for SyncToLinker {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let amt = self.linker.read(buf)?;
-        self.read += amt;
+        self.read += amt as u64;
         Ok(amt)
     }
 }
@@ -442,12 +516,18 @@ impl SyncToLinker {
     /// order to complete the writing process, otherwise everything will be
     /// thrown out.
     pub fn commit(mut self) -> Result<Integrity> {
+        self.guard.disarm();
         self.consume()?;
         let cache = self.cache;
         let linker_sri = self.linker.commit()?;
         if let Some(sri) = &self.opts.sri {
             if sri.matches(&linker_sri).is_none() {
-                return Err(ssri::Error::IntegrityCheckError(sri.clone(), linker_sri).into());
+                return Err(Error::WriteIntegrityMismatch(
+                    cache,
+                    self.key,
+                    sri.clone(),
+                    linker_sri,
+                ));
             }
         } else {
             self.opts.sri = Some(linker_sri.clone());
@@ -464,6 +544,14 @@ impl SyncToLinker {
         }
     }
 
+    /// Gives up on linking the target file without adding anything to the
+    /// cache. Nothing is written until `commit()`, so this only closes the
+    /// target file, the same as dropping the linker does.
+    pub fn abort(mut self) -> Result<()> {
+        self.guard.disarm();
+        Ok(())
+    }
+
     fn consume(&mut self) -> Result<()> {
         // Do a small 'test' read to avoid allocating a larger buffer if it
         // isn't necessary.
@@ -471,7 +559,7 @@ impl SyncToLinker {
         if self.context_read(&mut probe)? > 0 {
             // Make sure all the bytes are read so that the integrity is
             // properly calculated.
-            let mut buf = [0; BUF_SIZE];
+            let mut buf = vec![0; self.opts.read_buffer_size.unwrap_or(BUF_SIZE)];
             while self.context_read(&mut buf)? > 0 {}
         }
         Ok(())
@@ -491,7 +579,9 @@ mod tests {
 
     use super::*;
 
-    #[cfg(feature = "async-std")]
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use async_attributes::test as async_test;
     #[cfg(feature = "tokio")]
     use tokio::test as async_test;
@@ -506,7 +596,7 @@ mod tests {
         target
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_link() {
         let tmp = tempfile::tempdir().unwrap();
@@ -520,7 +610,7 @@ mod tests {
         assert_eq!(buf, b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_link_to_hash() {
         let tmp = tempfile::tempdir().unwrap();
@@ -547,6 +637,33 @@ mod tests {
         assert_eq!(buf, b"hello world");
     }
 
+    #[test]
+    fn test_verify_link_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = create_tmpfile(&tmp, b"hello world");
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::link_to_sync(&dir, "my-key", &target).unwrap();
+        verify_link_sync(&dir, "my-key").unwrap();
+
+        std::fs::write(&target, b"goodbye world").unwrap();
+        match verify_link_sync(&dir, "my-key") {
+            Err(Error::LinkTargetChanged(cache, key, linked)) => {
+                assert_eq!(cache, dir);
+                assert_eq!(key, "my-key");
+                assert_eq!(linked, target);
+            }
+            res => panic!("unexpected result: {res:?}"),
+        }
+
+        std::fs::remove_file(&target).unwrap();
+        assert!(matches!(
+            verify_link_sync(&dir, "my-key"),
+            Err(Error::LinkTargetChanged(..))
+        ));
+    }
+
     #[test]
     fn test_link_to_hash_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -560,7 +677,7 @@ mod tests {
         assert_eq!(buf, b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_open() {
         let tmp = tempfile::tempdir().unwrap();
@@ -579,7 +696,7 @@ mod tests {
         assert_eq!(buf, b"hello world");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_open_hash() {
         let tmp = tempfile::tempdir().unwrap();
@@ -616,6 +733,45 @@ mod tests {
         assert_eq!(buf, b"hello world");
     }
 
+    #[test]
+    fn test_read_buffer_size_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let data = vec![42u8; BUF_SIZE * 3 + 7];
+        let target = create_tmpfile(&tmp, &data);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = WriteOpts::new()
+            .size(data.len() as u64)
+            .read_buffer_size(BUF_SIZE * 2)
+            .link_to_sync(&dir, "my-key", target)
+            .unwrap()
+            .commit()
+            .unwrap();
+        assert_eq!(sri, Integrity::from(&data));
+
+        let buf = crate::read_sync(&dir, "my-key").unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_zero_read_buffer_size_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let data = vec![42u8; PROBE_SIZE * 4 + 3];
+        let target = create_tmpfile(&tmp, &data);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = WriteOpts::new()
+            .read_buffer_size(0)
+            .link_to_sync(&dir, "my-key", target)
+            .unwrap()
+            .commit()
+            .unwrap();
+        assert_eq!(sri, Integrity::from(&data));
+        assert_eq!(crate::read_sync(&dir, "my-key").unwrap(), data);
+    }
+
     #[test]
     fn test_open_hash_sync() {
         let tmp = tempfile::tempdir().unwrap();
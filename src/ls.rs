@@ -1,18 +1,244 @@
 //! Functions for iterating over the cache.
 use std::path::Path;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use std::pin::Pin;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use std::task::{Context as TaskContext, Poll};
 
+use crate::config::CacheDir;
 use crate::errors::Result;
 use crate::index;
 
 /// Returns a synchronous iterator that lists all cache index entries.
-pub fn list_sync<P: AsRef<Path>>(cache: P) -> impl Iterator<Item = Result<index::Metadata>> {
-    index::ls(cache.as_ref())
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let entries = cacache::list_sync("./my-cache").collect::<cacache::Result<Vec<_>>>()?;
+///     for entry in entries {
+///         println!("{}", entry.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn list_sync<P: AsRef<Path>>(cache: P) -> ListIter {
+    list_in(cache.as_ref().into())
+}
+
+/// Same as `list_sync()`, for a cache that may not use the default layout.
+pub(crate) fn list_in(cache: CacheDir<'_>) -> ListIter {
+    ListIter(Box::new(index::ls_in(cache)))
+}
+
+/// Iterator over cache index entries returned by [`list_sync`].
+pub struct ListIter(Box<dyn Iterator<Item = Result<index::Metadata>> + Send>);
+
+impl Iterator for ListIter {
+    type Item = Result<index::Metadata>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+/// Order to sort entries in for [`list_sorted_sync`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// Largest entries first.
+    SizeDesc,
+    /// Smallest entries first.
+    SizeAsc,
+    /// Most recently written entries first.
+    TimeDesc,
+    /// Least recently written entries first.
+    TimeAsc,
+}
+
+/// Lists all cache index entries, sorted by `sort`. Entries that compare
+/// equal are ordered by key, so the result is the same across calls.
+///
+/// Sorting needs every entry in memory anyway, so this collects the whole
+/// listing into a `Vec`.
+///
+/// ## Example
+/// ```no_run
+/// use cacache::SortKey;
+///
+/// fn main() -> cacache::Result<()> {
+///     for entry in cacache::list_sorted_sync("./my-cache", SortKey::SizeDesc)? {
+///         println!("{}: {} bytes", entry.key, entry.size);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn list_sorted_sync<P: AsRef<Path>>(cache: P, sort: SortKey) -> Result<Vec<index::Metadata>> {
+    let mut entries = index::ls(cache.as_ref()).collect::<Result<Vec<_>>>()?;
+    entries.sort_by(|a, b| {
+        match sort {
+            SortKey::SizeDesc => b.size.cmp(&a.size),
+            SortKey::SizeAsc => a.size.cmp(&b.size),
+            SortKey::TimeDesc => b.time.cmp(&a.time),
+            SortKey::TimeAsc => a.time.cmp(&b.time),
+        }
+        .then_with(|| a.key.cmp(&b.key))
+    });
+    Ok(entries)
+}
+
+/// Returns a synchronous iterator over the latest index record for every
+/// key, including keys that have been removed. Removed keys show up as
+/// tombstones, whose `integrity` is `None`.
+///
+/// ## Example
+/// ```no_run
+/// for record in cacache::list_with_tombstones_sync("./my-cache") {
+///     let record = record?;
+///     if record.is_deleted() {
+///         println!("{} was removed at {}", record.key, record.time);
+///     }
+/// }
+/// # Ok::<(), cacache::Error>(())
+/// ```
+pub fn list_with_tombstones_sync<P: AsRef<Path>>(
+    cache: P,
+) -> impl Iterator<Item = Result<index::IndexRecord>> {
+    index::ls_with_tombstones(cache.as_ref())
+}
+
+/// Asynchronously lists a page of up to `limit` cache index entries,
+/// skipping the first `offset`. The ordering is deterministic across calls as
+/// long as the cache isn't written to in between, and the index is only read
+/// as far as needed to fill the page.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let second_page = cacache::list_page("./my-cache", 50, 50).await?;
+///     for entry in second_page {
+///         println!("{}", entry.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn list_page<P: AsRef<Path>>(
+    cache: P,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<index::Metadata>> {
+    index::ls_page_async(cache.as_ref(), offset, limit).await
+}
+
+/// Returns a stream that asynchronously lists all cache index entries, in
+/// the same order as [`list_page`]. The index directory is walked with the
+/// async runtime's own filesystem calls as the stream is polled, so listing
+/// a large cache neither blocks its workers nor holds every entry in memory.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut entries = cacache::list("./my-cache");
+///     while let Some(entry) = entries.next().await {
+///         println!("{}", entry?.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub fn list<P: AsRef<Path>>(cache: P) -> ListStream {
+    list_in_async(cache.as_ref().into())
+}
+
+/// Same as `list()`, for a cache that may not use the default layout.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub(crate) fn list_in_async(cache: CacheDir<'_>) -> ListStream {
+    ListStream(Box::pin(index::ls_in_async(cache)))
+}
+
+/// Stream of cache index entries returned by [`list`].
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub struct ListStream(Pin<Box<dyn futures::Stream<Item = Result<index::Metadata>> + Send>>);
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+impl futures::Stream for ListStream {
+    type Item = Result<index::Metadata>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
+    use async_attributes::test as async_test;
+    #[cfg(feature = "tokio")]
+    use tokio::test as async_test;
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_list_page() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        for i in 0..5 {
+            crate::write(&dir, format!("key{i}"), b"hello")
+                .await
+                .unwrap();
+        }
+        crate::remove(&dir, "key3").await.unwrap();
+
+        let mut keys = Vec::new();
+        for offset in [0, 2, 4] {
+            let page = list_page(&dir, offset, 2).await.unwrap();
+            assert_eq!(page.len(), if offset == 4 { 0 } else { 2 });
+            keys.extend(page.into_iter().map(|entry| entry.key));
+        }
+        let again = list_page(&dir, 0, 10).await.unwrap();
+        assert_eq!(again.into_iter().map(|e| e.key).collect::<Vec<_>>(), keys);
+        keys.sort();
+        assert_eq!(keys, ["key0", "key1", "key2", "key4"]);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_list() {
+        use futures::stream::{StreamExt, TryStreamExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert!(list(&dir).next().await.is_none());
+        for i in 0..20 {
+            crate::write(&dir, format!("key{i}"), b"hello")
+                .await
+                .unwrap();
+        }
+        crate::remove(&dir, "key3").await.unwrap();
+
+        let listed = list(&dir).try_collect::<Vec<_>>().await.unwrap();
+        let page = list_page(&dir, 0, 100).await.unwrap();
+        assert_eq!(listed, page);
+        let mut keys = listed.into_iter().map(|e| e.key).collect::<Vec<_>>();
+        keys.sort();
+        let mut expected = list_sync(&dir)
+            .map(|e| Ok(e?.key))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(keys.len(), 19);
+    }
+
     #[test]
     fn test_list_sync() {
         // check that the public interface to list elements can actually use the
@@ -25,4 +251,77 @@ mod tests {
             .collect::<Result<Vec<_>>>()
             .is_err())
     }
+
+    #[test]
+    fn test_list_sync_named() {
+        struct Listing {
+            entries: ListIter,
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "hello", b"hello").unwrap();
+        let listing = Listing {
+            entries: list_sync(&dir),
+        };
+        let entries: Vec<index::Metadata> = listing.entries.collect::<Result<_>>().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "hello");
+    }
+
+    #[test]
+    fn test_list_sorted_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        for (key, data, time) in [
+            ("b", &b"xx"[..], 3),
+            ("a", b"xxxx", 1),
+            ("d", b"x", 4),
+            ("c", b"xx", 2),
+        ] {
+            let mut writer = crate::WriteOpts::new()
+                .time(time)
+                .open_sync(&dir, key)
+                .unwrap();
+            writer.write_all(data).unwrap();
+            writer.commit().unwrap();
+        }
+        let keys = |sort| {
+            list_sorted_sync(&dir, sort)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.key)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys(SortKey::SizeDesc), ["a", "b", "c", "d"]);
+        assert_eq!(keys(SortKey::SizeAsc), ["d", "b", "c", "a"]);
+        assert_eq!(keys(SortKey::TimeDesc), ["d", "b", "c", "a"]);
+        assert_eq!(keys(SortKey::TimeAsc), ["a", "c", "b", "d"]);
+    }
+
+    #[test]
+    fn test_list_with_tombstones_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "live", b"hello").unwrap();
+        crate::write_sync(&dir, "gone", b"world").unwrap();
+        crate::remove_sync(&dir, "gone").unwrap();
+
+        let live = list_sync(&dir)
+            .map(|x| Ok(x?.key))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(live, ["live"]);
+
+        let mut records = list_with_tombstones_sync(&dir)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].key, "gone");
+        assert!(records[0].is_deleted());
+        assert_eq!(records[1].key, "live");
+        assert!(!records[1].is_deleted());
+    }
 }
@@ -0,0 +1,32 @@
+//! Functions for migrating cache data between format versions.
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::index;
+
+/// Migrates index entries written by an older version of cacache, stored
+/// under `{cache}/index-v{from_version}`, into the current index, so they
+/// don't silently disappear after an index format bump. Returns the number
+/// of entries migrated. Only live entries are carried over, and their
+/// original time, size, and metadata are preserved. Keys that already have
+/// an entry in the current index are skipped, so migrating twice is harmless.
+/// `from_version` must be a plain number, like `"4"`.
+///
+/// If `remove_old` is true, the old index directory is removed once all
+/// entries have been migrated.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let migrated = cacache::migrate_index_sync("./my-cache", "4", true)?;
+///     println!("migrated {migrated} entries");
+///     Ok(())
+/// }
+/// ```
+pub fn migrate_index_sync<P: AsRef<Path>>(
+    cache: P,
+    from_version: &str,
+    remove_old: bool,
+) -> Result<usize> {
+    index::migrate(cache.as_ref(), from_version, remove_old)
+}
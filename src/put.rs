@@ -1,19 +1,23 @@
 //! Functions for writing to cache.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+use std::future::Future;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::pin::Pin;
 
+use serde::Serialize;
 use serde_json::Value;
-use ssri::{Algorithm, Integrity};
+use ssri::{Algorithm, Integrity, IntegrityOpts};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use crate::async_lib::{AsyncWrite, AsyncWriteExt};
+use crate::config::{CacheDir, Layout};
 use crate::content::write;
 use crate::errors::{Error, IoErrorExt, Result};
-use crate::index;
+use crate::index::{self, ContentEncoding, KeyNormalizer, Metadata};
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 use std::task::{Context as TaskContext, Poll};
 
 /// Writes `data` to the `cache`, indexing it under `key`.
@@ -28,7 +32,7 @@ use std::task::{Context as TaskContext, Poll};
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn write<P, D, K>(cache: P, key: K, data: D) -> Result<Integrity>
 where
     P: AsRef<Path>,
@@ -51,7 +55,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn write_with_algo<P, D, K>(
     algo: Algorithm,
     cache: P,
@@ -66,7 +70,7 @@ where
     async fn inner(algo: Algorithm, cache: &Path, key: &str, data: &[u8]) -> Result<Integrity> {
         let mut writer = WriteOpts::new()
             .algorithm(algo)
-            .size(data.len())
+            .size(data.len() as u64)
             .open(cache, key)
             .await?;
         writer.write_all(data).await.with_context(|| {
@@ -77,6 +81,125 @@ where
     inner(algo, cache.as_ref(), key.as_ref(), data.as_ref()).await
 }
 
+/// Writes `data` to the `cache`, indexing it under `key`, taking ownership of
+/// the buffer.
+///
+/// `write()` copies `data` into the writer's own buffer before handing it off
+/// to a blocking thread. This hands the buffer itself off instead, so large
+/// writes don't need twice their size in memory at their peak. The data
+/// still gets copied into the page cache once, on its way to disk, and this
+/// is unavoidable.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let data = vec![0u8; 4 * 1024 * 1024];
+///     cacache::write_owned("./my-cache", "my-key", data).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn write_owned<P, K>(cache: P, key: K, data: Vec<u8>) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    write_owned_inner(cache.as_ref(), key.as_ref(), data)
+        .await
+        .map(|(sri, _)| sri)
+}
+
+/// Writes `data` to the `cache`, indexing it under `key`, and hands `data`
+/// back along with its integrity. This is for write-through caches that
+/// serve what they've just cached: the returned bytes are exactly the ones
+/// that were hashed and committed, without reading them back from disk.
+///
+/// Like [`write_owned`], `data` itself is handed off to the blocking thread
+/// that writes it, rather than being copied.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let fetched = b"hello".to_vec();
+///     let (sri, data) = cacache::write_and_read("./my-cache", "my-key", fetched).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn write_and_read<P, K>(cache: P, key: K, data: Vec<u8>) -> Result<(Integrity, Vec<u8>)>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    write_owned_inner(cache.as_ref(), key.as_ref(), data).await
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+async fn write_owned_inner(cache: &Path, key: &str, data: Vec<u8>) -> Result<(Integrity, Vec<u8>)> {
+    let (cache, key) = (cache.to_path_buf(), key.to_owned());
+    crate::async_lib::run_blocking(move || write_owned_sync(&cache, &key, data)).await
+}
+
+/// Writes and commits `data` through a `SyncWriter`, the same way
+/// `write_sync` does, then hands the buffer back. The async owned writes run
+/// this on a blocking thread, so `data` never has to be copied.
+fn write_owned_sync(cache: &Path, key: &str, data: Vec<u8>) -> Result<(Integrity, Vec<u8>)> {
+    let mut writer = WriteOpts::new()
+        .size(data.len() as u64)
+        .open_sync(cache, key)?;
+    writer.write_all(&data).with_context(|| {
+        format!("Failed to write to cache data for key {key} for cache at {cache:?}")
+    })?;
+    Ok((writer.commit()?, data))
+}
+
+/// Writes `data` to the `cache` once, and indexes it under every one of
+/// `keys`, returning the integrity they all share. This is cheaper than
+/// writing the same data under each key, since it's only hashed and stored
+/// once.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::write_multi_key("./my-cache", &["my-key", "my-alias"], b"hello").await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn write_multi_key<P, K, D>(cache: P, keys: &[K], data: D) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    D: AsRef<[u8]>,
+{
+    let cache = cache.as_ref();
+    let data = data.as_ref();
+    for key in keys {
+        index::validate_key(key.as_ref(), None)?;
+    }
+    let sri = write_hash(cache, data).await?;
+    for key in keys {
+        index::insert_async(
+            cache,
+            key.as_ref(),
+            WriteOpts::new()
+                .integrity(sri.clone())
+                .size(data.len() as u64),
+        )
+        .await?;
+    }
+    Ok(sri)
+}
+
 /// Writes `data` to the `cache`, skipping associating an index key with it.
 ///
 /// ## Example
@@ -89,7 +212,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn write_hash<P, D>(cache: P, data: D) -> Result<Integrity>
 where
     P: AsRef<Path>,
@@ -111,7 +234,7 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn write_hash_with_algo<P, D>(algo: Algorithm, cache: P, data: D) -> Result<Integrity>
 where
     P: AsRef<Path>,
@@ -120,7 +243,7 @@ where
     async fn inner(algo: Algorithm, cache: &Path, data: &[u8]) -> Result<Integrity> {
         let mut writer = WriteOpts::new()
             .algorithm(algo)
-            .size(data.len())
+            .size(data.len() as u64)
             .open_hash(cache)
             .await?;
         writer
@@ -131,25 +254,75 @@ where
     }
     inner(algo, cache.as_ref(), data.as_ref()).await
 }
+
+/// Writes a stream of `chunks` to the `cache`, skipping associating an index
+/// key with it. Integrity is computed incrementally, so the full payload is
+/// never buffered in memory.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let chunks = futures::stream::iter(vec![b"hel".to_vec(), b"lo".to_vec()]);
+///     cacache::write_hash_chunks("./my-cache", chunks).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn write_hash_chunks<P, S>(cache: P, chunks: S) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    S: futures::Stream,
+    S::Item: AsRef<[u8]>,
+{
+    use futures::StreamExt;
+    let cache = cache.as_ref();
+    let mut writer = WriteOpts::new().open_hash(cache).await?;
+    futures::pin_mut!(chunks);
+    while let Some(chunk) = chunks.next().await {
+        writer
+            .write_all(chunk.as_ref())
+            .await
+            .with_context(|| format!("Failed to write to cache data for cache at {cache:?}"))?;
+    }
+    writer.commit().await
+}
+
 /// A reference to an open file writing to the cache.
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+///
+/// `Writer` is `Send`, so it can be held across `.await` points and moved
+/// into spawned tasks on a multithreaded runtime.
+///
+/// Dropping a `Writer` at any point, including in the middle of a write, is
+/// safe: nothing gets added to the index, and its temp file is removed as
+/// soon as any in-flight filesystem operation finishes. A write whose future
+/// was dropped may still complete in the background, though, so after
+/// cancelling a write the `Writer` should be dropped rather than committed.
+/// Dropping only cleans up on a best-effort basis, though; use `abort()` to
+/// wait for the temp file to be removed and find out if that failed.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub struct Writer {
     cache: PathBuf,
     key: Option<String>,
-    written: usize,
+    written: u64,
     pub(crate) writer: write::AsyncWriter,
     opts: WriteOpts,
+    guard: CommitGuard,
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl AsyncWrite for Writer {
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut TaskContext<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
+        self.opts.check_limit(self.written, buf.len())?;
         let amt = futures::ready!(Pin::new(&mut self.writer).poll_write(cx, buf))?;
-        self.written += amt;
+        self.written += amt as u64;
+        self.guard.arm("Writer");
         Poll::Ready(Ok(amt))
     }
 
@@ -157,7 +330,7 @@ impl AsyncWrite for Writer {
         Pin::new(&mut self.writer).poll_flush(cx)
     }
 
-    #[cfg(feature = "async-std")]
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
         Pin::new(&mut self.writer).poll_close(cx)
     }
@@ -171,7 +344,7 @@ impl AsyncWrite for Writer {
     }
 }
 
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 impl Writer {
     /// Creates a new writable file handle into the cache.
     ///
@@ -229,25 +402,302 @@ impl Writer {
     /// verifies data against `size` and `integrity` options, if provided.
     /// Must be called manually in order to complete the writing process,
     /// otherwise everything will be thrown out.
-    pub async fn commit(mut self) -> Result<Integrity> {
+    pub async fn commit(self) -> Result<Integrity> {
+        self.commit_detailed()
+            .await
+            .map(|committed| committed.integrity)
+    }
+
+    /// Throws away everything written so far without adding anything to the
+    /// cache, waiting for the temp file to be removed and returning any error
+    /// from removing it.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    /// use async_std::prelude::*;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::Writer::create("./my-cache", "my-key").await?;
+    ///     fd.write_all(b"hel").await.expect("Failed to write to cache");
+    ///     // Changed our minds.
+    ///     fd.abort().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn abort(mut self) -> Result<()> {
+        self.guard.disarm();
+        self.writer.abort().await
+    }
+
+    /// Same as `commit()`, but also reports whether identical content was
+    /// already present in the cache, in which case nothing new was written
+    /// to the content store.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    /// use async_std::prelude::*;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::Writer::create("./my-cache", "my-key").await?;
+    ///     fd.write_all(b"hello").await.expect("Failed to write to cache");
+    ///     let committed = fd.commit_detailed().await?;
+    ///     if committed.deduplicated {
+    ///         println!("{} was already cached", committed.integrity);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn commit_detailed(mut self) -> Result<Committed> {
+        self.guard.disarm();
         let cache = self.cache;
-        let writer_sri = self.writer.close().await?;
-        if let Some(sri) = &self.opts.sri {
-            if sri.matches(&writer_sri).is_none() {
-                return Err(ssri::Error::IntegrityCheckError(sri.clone(), writer_sri).into());
-            }
+        let (writer_sri, deduplicated) = self.writer.close_detailed().await?;
+        self.opts
+            .check_written(&cache, self.key.as_deref(), &writer_sri, self.written)?;
+        let integrity = if let Some(key) = self.key {
+            index::insert_async(&cache, &key, self.opts).await?
         } else {
-            self.opts.sri = Some(writer_sri.clone());
+            writer_sri
+        };
+        Ok(Committed {
+            integrity,
+            deduplicated,
+        })
+    }
+
+    /// Commits the written data to the content store like `commit()`, but
+    /// never adds it to the index, even if this `Writer` has a key. Returns
+    /// the entry that would have been written instead, so it can be stored
+    /// elsewhere. For hash-only writers, the entry's `key` is empty.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    /// use async_std::prelude::*;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::WriteOpts::new().open_hash("./my-cache").await?;
+    ///     fd.write_all(b"hello").await.expect("Failed to write to cache");
+    ///     let entry = fd.commit_detached().await?;
+    ///     println!("{} ({} bytes)", entry.integrity, entry.size);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn commit_detached(mut self) -> Result<Metadata> {
+        self.guard.disarm();
+        let writer_sri = self.writer.close().await?;
+        self.opts
+            .check_written(&self.cache, self.key.as_deref(), &writer_sri, self.written)?;
+        Ok(self.opts.detached_entry(self.key))
+    }
+
+    /// Same as `commit()`, but also hands back the `WriteOpts` this `Writer`
+    /// was opened with, so they can be used to open another `Writer`.
+    ///
+    /// The underlying temporary file in `{cache}/tmp` is not reusable: it
+    /// gets moved into place as the content file on commit, so each `Writer`
+    /// always creates a fresh one when it's opened.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    /// use async_std::prelude::*;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::Writer::create("./my-cache", "key1").await?;
+    ///     fd.write_all(b"hello").await.expect("Failed to write to cache");
+    ///     let (_sri, opts) = fd.finish().await?;
+    ///     let mut fd = opts.open("./my-cache", "key2").await?;
+    ///     fd.write_all(b"world").await.expect("Failed to write to cache");
+    ///     fd.commit().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn finish(self) -> Result<(Integrity, WriteOpts)> {
+        let opts = self.opts.clone();
+        let sri = self.commit().await?;
+        Ok((sri, opts))
+    }
+}
+
+/// Opens a writer into the `cache` for `key`, split into a write half and a
+/// future that resolves to the committed integrity. Closing the write half
+/// hands it off to the future, which then commits it, so data can be produced
+/// in one task while another awaits the result.
+///
+/// If the write half is dropped without being closed, nothing is committed
+/// and the future resolves to an error.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::io::AsyncWriteExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let (mut fd, sri) = cacache::write_async_reader("./my-cache", "my-key").await?;
+///     async_std::task::spawn(async move {
+///         fd.write_all(b"hello world").await.expect("Failed to write to cache");
+///         fd.close().await.expect("Failed to close cache writer");
+///     });
+///     let sri = sri.await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn write_async_reader<P, K>(cache: P, key: K) -> Result<(SplitWriter, SplitCommit)>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    let writer = Writer::create(cache, key).await?;
+    let (tx, rx) = futures::channel::oneshot::channel::<Writer>();
+    let committed = async move {
+        match rx.await {
+            Ok(writer) => writer.commit().await,
+            Err(_) => Err(Error::IoError(
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "writer dropped"),
+                "Cache writer was dropped before being closed".into(),
+            )),
+        }
+    };
+    Ok((
+        SplitWriter {
+            writer: Some(writer),
+            tx: Some(tx),
+        },
+        SplitCommit(Box::pin(committed)),
+    ))
+}
+
+/// Write half returned by `write_async_reader`. Closing it hands the data
+/// off to the matching `SplitCommit` to be committed.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub struct SplitWriter {
+    writer: Option<Writer>,
+    tx: Option<futures::channel::oneshot::Sender<Writer>>,
+}
+
+/// Future returned by `write_async_reader` that resolves to the integrity of
+/// the data once its `SplitWriter` has been closed and committed.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub struct SplitCommit(Pin<Box<dyn Future<Output = Result<Integrity>> + Send>>);
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+impl Future for SplitCommit {
+    type Output = Result<Integrity>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+impl SplitWriter {
+    fn writer(&mut self) -> std::io::Result<Pin<&mut Writer>> {
+        match &mut self.writer {
+            Some(writer) => Ok(Pin::new(writer)),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "cache writer already closed",
+            )),
         }
-        if let Some(size) = self.opts.size {
-            if size != self.written {
-                return Err(Error::SizeMismatch(size, self.written));
+    }
+
+    fn hand_off(&mut self) -> Poll<std::io::Result<()>> {
+        if let (Some(writer), Some(tx)) = (self.writer.take(), self.tx.take()) {
+            // If the receiving future is gone, there's nobody left to commit
+            // the data for, so it's simply discarded.
+            if let Err(mut writer) = tx.send(writer) {
+                writer.guard.disarm();
             }
         }
-        if let Some(key) = self.key {
-            index::insert_async(&cache, &key, self.opts).await
-        } else {
-            Ok(writer_sri)
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+impl AsyncWrite for SplitWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.writer()?.poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        self.writer()?.poll_flush(cx)
+    }
+
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.hand_off()
+    }
+
+    #[cfg(feature = "tokio")]
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.hand_off()
+    }
+}
+
+/// Warns through `tracing`, when that feature is enabled, if the handle it
+/// belongs to is dropped after data was put into it but before it was
+/// committed or aborted, since that silently throws the data away. Without
+/// the feature, it takes up no space and does nothing.
+#[derive(Default)]
+pub(crate) struct CommitGuard {
+    #[cfg(feature = "tracing")]
+    armed: Option<&'static str>,
+}
+
+impl CommitGuard {
+    /// A guard that warns on drop from the start, naming its handle `kind`.
+    #[cfg(feature = "link_to")]
+    pub(crate) fn armed(kind: &'static str) -> Self {
+        let mut guard = CommitGuard::default();
+        guard.arm(kind);
+        guard
+    }
+
+    /// Makes the guard warn on drop, naming its handle `kind`.
+    #[inline]
+    pub(crate) fn arm(&mut self, _kind: &'static str) {
+        #[cfg(feature = "tracing")]
+        if self.armed.is_none() {
+            self.armed = Some(_kind);
+        }
+    }
+
+    /// Marks the handle as committed or aborted.
+    #[inline]
+    pub(crate) fn disarm(&mut self) {
+        #[cfg(feature = "tracing")]
+        {
+            self.armed = None;
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for CommitGuard {
+    fn drop(&mut self) {
+        if let Some(kind) = self.armed {
+            tracing::warn!(
+                "cacache::{kind} was dropped without calling commit() or abort(), so \
+                 everything written to it was thrown away"
+            );
         }
     }
 }
@@ -300,12 +750,112 @@ where
         writer.write_all(data).with_context(|| {
             format!("Failed to write to cache data for key {key} for cache at {cache:?}")
         })?;
-        writer.written = data.as_ref().len();
+        writer.written = data.as_ref().len() as u64;
         writer.commit()
     }
     inner(algo, cache.as_ref(), key.as_ref(), data.as_ref())
 }
 
+/// Writes `data` to the `cache` synchronously, indexing it under `key`, and
+/// hands `data` back along with its integrity. This is for write-through
+/// caches that serve what they've just cached: the returned bytes are
+/// exactly the ones that were hashed and committed, without reading them
+/// back from disk.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let fetched = b"hello".to_vec();
+///     let (sri, data) = cacache::write_and_read_sync("./my-cache", "my-key", fetched)?;
+///     Ok(())
+/// }
+/// ```
+pub fn write_and_read_sync<P, K>(cache: P, key: K, data: Vec<u8>) -> Result<(Integrity, Vec<u8>)>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    write_owned_sync(cache.as_ref(), key.as_ref(), data)
+}
+
+/// Writes `data` to the `cache` synchronously, once, and indexes it under
+/// every one of `keys`, returning the integrity they all share. This is
+/// cheaper than writing the same data under each key, since it's only hashed
+/// and stored once.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_multi_key_sync("./my-cache", &["my-key", "my-alias"], b"hello")?;
+///     Ok(())
+/// }
+/// ```
+pub fn write_multi_key_sync<P, K, D>(cache: P, keys: &[K], data: D) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    D: AsRef<[u8]>,
+{
+    let cache = cache.as_ref();
+    let data = data.as_ref();
+    for key in keys {
+        index::validate_key(key.as_ref(), None)?;
+    }
+    let sri = write_hash_sync(cache, data)?;
+    for key in keys {
+        index::insert(
+            cache,
+            key.as_ref(),
+            WriteOpts::new()
+                .integrity(sri.clone())
+                .size(data.len() as u64),
+        )?;
+    }
+    Ok(sri)
+}
+
+/// Writes each of `entries`' data to the `cache` synchronously, indexing it
+/// under its key, and returns their integrities in the same order. This is
+/// for writing lots of small entries at once: entries whose keys share an
+/// index bucket are appended to it together, instead of opening it once per
+/// entry.
+///
+/// Every key is checked before anything is written, and all the content is
+/// written before any of it is indexed.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let entries = (0..100).map(|i| (format!("key-{i}"), format!("data-{i}").into_bytes()));
+///     let sris = cacache::write_many_sync("./my-cache", entries)?;
+///     Ok(())
+/// }
+/// ```
+pub fn write_many_sync<P, I>(cache: P, entries: I) -> Result<Vec<Integrity>>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = (String, Vec<u8>)>,
+{
+    fn inner(cache: &Path, entries: Vec<(String, Vec<u8>)>) -> Result<Vec<Integrity>> {
+        for (key, _) in &entries {
+            index::validate_key(key, None)?;
+        }
+        let mut written = Vec::with_capacity(entries.len());
+        for (key, data) in entries {
+            // Tiny writes are cheaper without the memory map `write_hash_sync`
+            // would set up for data of a known size.
+            let mut writer = write::Writer::new(cache.into(), Algorithm::Sha256, None)?;
+            writer.write_all(&data).with_context(|| {
+                format!("Failed to write to cache data for key {key} for cache at {cache:?}")
+            })?;
+            written.push((key, writer.close()?, data.len() as u64));
+        }
+        index::insert_many(cache, &written)?;
+        Ok(written.into_iter().map(|(_, sri, _)| sri).collect())
+    }
+    inner(cache.as_ref(), entries.into_iter().collect())
+}
+
 /// Writes `data` to the `cache` synchronously, skipping associating a key with it.
 ///
 /// ## Example
@@ -344,25 +894,177 @@ where
     fn inner(algo: Algorithm, cache: &Path, data: &[u8]) -> Result<Integrity> {
         let mut writer = WriteOpts::new()
             .algorithm(algo)
-            .size(data.len())
+            .size(data.len() as u64)
             .open_hash_sync(cache)?;
         writer
             .write_all(data)
             .with_context(|| format!("Failed to write to cache data for cache at {cache:?}"))?;
-        writer.written = data.len();
+        writer.written = data.len() as u64;
         writer.commit()
     }
     inner(algo, cache.as_ref(), data.as_ref())
 }
-/// Builder for options and flags for opening a new cache file to write data into.
-#[derive(Clone, Default)]
-pub struct WriteOpts {
-    pub(crate) algorithm: Option<Algorithm>,
+
+/// Writes an iterator of `chunks` to the `cache` synchronously, skipping
+/// associating an index key with it. Integrity is computed incrementally, so
+/// the full payload is never buffered in memory.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let chunks = vec![&b"hel"[..], &b"lo"[..]];
+///     cacache::write_hash_chunks_sync("./my-cache", chunks)?;
+///     Ok(())
+/// }
+/// ```
+pub fn write_hash_chunks_sync<P, I>(cache: P, chunks: I) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let cache = cache.as_ref();
+    let mut writer = WriteOpts::new().open_hash_sync(cache)?;
+    for chunk in chunks {
+        writer
+            .write_all(chunk.as_ref())
+            .with_context(|| format!("Failed to write to cache data for cache at {cache:?}"))?;
+    }
+    writer.commit()
+}
+
+/// Writes the data for `src_key` followed by `extra` into a new entry under
+/// `dst_key`, returning the new entry's integrity. The old data is streamed
+/// straight from the cache into the new entry, so it never has to be held in
+/// memory all at once. The new content is hashed with the same algorithm as
+/// the old.
+///
+/// `src_key` is left as it is, and may be the same key as `dst_key`, in which
+/// case it ends up pointing at the extended data.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::write_sync("./my-cache", "log-1", b"hello")?;
+///     cacache::append_sync("./my-cache", "log-1", b" world", "log-2")?;
+///     assert_eq!(cacache::read_sync("./my-cache", "log-2")?, b"hello world");
+///     Ok(())
+/// }
+/// ```
+pub fn append_sync<P, K, D, J>(cache: P, src_key: K, extra: D, dst_key: J) -> Result<Integrity>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    D: AsRef<[u8]>,
+    J: AsRef<str>,
+{
+    fn inner(cache: &Path, src_key: &str, extra: &[u8], dst_key: &str) -> Result<Integrity> {
+        let mut reader = crate::SyncReader::open(cache, src_key)?;
+        let mut writer = WriteOpts::new()
+            .algorithm(reader.algorithm())
+            .size(reader.expected_size().unwrap_or_default() + extra.len() as u64)
+            .open_sync(cache, dst_key)?;
+        std::io::copy(&mut reader, &mut writer).with_context(|| {
+            format!("Failed to copy cache data for key {src_key} for cache at {cache:?}")
+        })?;
+        reader.check()?;
+        writer.write_all(extra).with_context(|| {
+            format!("Failed to write to cache data for key {dst_key} for cache at {cache:?}")
+        })?;
+        writer.commit()
+    }
+    inner(
+        cache.as_ref(),
+        src_key.as_ref(),
+        extra.as_ref(),
+        dst_key.as_ref(),
+    )
+}
+
+/// The result of committing a writer, as returned by
+/// [`Writer::commit_detailed`] and [`SyncWriter::commit_detailed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Committed {
+    /// Integrity of the committed data.
+    pub integrity: Integrity,
+    /// Whether identical content was already present in the cache.
+    pub deduplicated: bool,
+}
+
+/// Computes the [`Integrity`] that writing `data` with `algo` would produce,
+/// without touching a cache. Combined with [`exists`](crate::exists), this
+/// checks whether some data is already cached before writing it.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::integrity_of(b"hello", cacache::Algorithm::Sha256);
+///     let sri2 = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     assert_eq!(sri, sri2);
+///     Ok(())
+/// }
+/// ```
+pub fn integrity_of<D: AsRef<[u8]>>(data: D, algo: Algorithm) -> Integrity {
+    IntegrityOpts::new()
+        .algorithm(algo)
+        .chain(data.as_ref())
+        .result()
+}
+
+/// Like [`integrity_of`], but streams the data from `reader` instead of
+/// needing it all in memory.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let file = std::fs::File::open("./big-file").expect("failed to open file");
+///     let sri = cacache::integrity_of_reader(file, cacache::Algorithm::Sha256)?;
+///     Ok(())
+/// }
+/// ```
+pub fn integrity_of_reader<R: Read>(mut reader: R, algo: Algorithm) -> Result<Integrity> {
+    let mut builder = IntegrityOpts::new().algorithm(algo);
+    let mut buf = [0u8; 1024 * 8];
+    loop {
+        let read = reader
+            .read(&mut buf)
+            .with_context(|| "Failed to read data to compute its integrity".into())?;
+        if read == 0 {
+            break;
+        }
+        builder.input(&buf[..read]);
+    }
+    Ok(builder.result())
+}
+
+/// Builder for options and flags for opening a new cache file to write data into.
+#[derive(Clone, Default)]
+pub struct WriteOpts {
+    pub(crate) algorithm: Option<Algorithm>,
+    pub(crate) extra_algorithm: Option<Algorithm>,
     pub(crate) sri: Option<Integrity>,
-    pub(crate) size: Option<usize>,
+    pub(crate) trusted_sri: Option<Integrity>,
+    pub(crate) size: Option<u64>,
+    pub(crate) max_size: Option<u64>,
     pub(crate) time: Option<u128>,
     pub(crate) metadata: Option<Value>,
     pub(crate) raw_metadata: Option<Vec<u8>>,
+    pub(crate) content_encoding: Option<ContentEncoding>,
+    pub(crate) mime: Option<String>,
+    pub(crate) filename: Option<String>,
+    pub(crate) metadata_threshold: Option<usize>,
+    #[cfg(feature = "gzip")]
+    pub(crate) compress_metadata: bool,
+    pub(crate) write_buffer_size: Option<usize>,
+    pub(crate) preallocate: bool,
+    pub(crate) space_check_threshold: Option<u64>,
+    pub(crate) content_mode: Option<u32>,
+    pub(crate) key_normalizer: Option<KeyNormalizer>,
+    pub(crate) layout: Layout,
+    pub(crate) fail_on_conflict: bool,
+    pub(crate) verify_existing: bool,
+    #[cfg(feature = "link_to")]
+    pub(crate) read_buffer_size: Option<usize>,
 }
 
 impl WriteOpts {
@@ -372,47 +1074,62 @@ impl WriteOpts {
     }
 
     /// Opens the file handle for writing, returning an Writer instance.
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     pub async fn open<P, K>(self, cache: P, key: K) -> Result<Writer>
     where
         P: AsRef<Path>,
         K: AsRef<str>,
     {
         async fn inner(me: WriteOpts, cache: &Path, key: &str) -> Result<Writer> {
+            index::validate_key(key, me.key_normalizer)?;
+            me.ensure_space(cache)?;
             Ok(Writer {
                 cache: cache.to_path_buf(),
                 key: Some(String::from(key)),
                 written: 0,
                 writer: write::AsyncWriter::new(
-                    cache,
+                    CacheDir::new(cache, &me.layout),
                     me.algorithm.unwrap_or(Algorithm::Sha256),
                     None,
                 )
-                .await?,
+                .await?
+                .with_extra_algorithm(me.extra_algorithm)
+                .with_trusted_integrity(me.trusted_sri.clone())
+                .with_preallocation(me.preallocation_size())?
+                .with_mode(me.content_mode)?
+                .with_verify_existing(me.verify_existing),
                 opts: me,
+                guard: CommitGuard::default(),
             })
         }
         inner(self, cache.as_ref(), key.as_ref()).await
     }
 
     /// Opens the file handle for writing, without a key returning an Writer instance.
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     pub async fn open_hash<P>(self, cache: P) -> Result<Writer>
     where
         P: AsRef<Path>,
     {
         async fn inner(me: WriteOpts, cache: &Path) -> Result<Writer> {
+            me.ensure_space(cache)?;
             Ok(Writer {
                 cache: cache.to_path_buf(),
                 key: None,
                 written: 0,
                 writer: write::AsyncWriter::new(
-                    cache,
+                    CacheDir::new(cache, &me.layout),
                     me.algorithm.unwrap_or(Algorithm::Sha256),
-                    me.size,
+                    me.mmap_size(),
                 )
-                .await?,
+                .await?
+                .with_extra_algorithm(me.extra_algorithm)
+                .with_trusted_integrity(me.trusted_sri.clone())
+                .with_preallocation(me.preallocation_size())?
+                .with_mode(me.content_mode)?
+                .with_verify_existing(me.verify_existing),
                 opts: me,
+                guard: CommitGuard::default(),
             })
         }
         inner(self, cache.as_ref()).await
@@ -425,16 +1142,26 @@ impl WriteOpts {
         K: AsRef<str>,
     {
         fn inner(me: WriteOpts, cache: &Path, key: &str) -> Result<SyncWriter> {
+            index::validate_key(key, me.key_normalizer)?;
+            me.ensure_space(cache)?;
             Ok(SyncWriter {
                 cache: cache.to_path_buf(),
                 key: Some(String::from(key)),
                 written: 0,
-                writer: write::Writer::new(
-                    cache,
+                writer: write::Writer::with_buffer_size(
+                    CacheDir::new(cache, &me.layout),
                     me.algorithm.unwrap_or(Algorithm::Sha256),
-                    me.size,
-                )?,
+                    me.mmap_size(),
+                    me.write_buffer_size
+                        .unwrap_or(write::DEFAULT_WRITE_BUFFER_SIZE),
+                )?
+                .with_extra_algorithm(me.extra_algorithm)
+                .with_trusted_integrity(me.trusted_sri.clone())
+                .with_preallocation(me.preallocation_size())?
+                .with_mode(me.content_mode)?
+                .with_verify_existing(me.verify_existing),
                 opts: me,
+                guard: CommitGuard::default(),
             })
         }
         inner(self, cache.as_ref(), key.as_ref())
@@ -446,16 +1173,25 @@ impl WriteOpts {
         P: AsRef<Path>,
     {
         fn inner(me: WriteOpts, cache: &Path) -> Result<SyncWriter> {
+            me.ensure_space(cache)?;
             Ok(SyncWriter {
                 cache: cache.to_path_buf(),
                 key: None,
                 written: 0,
-                writer: write::Writer::new(
-                    cache,
+                writer: write::Writer::with_buffer_size(
+                    CacheDir::new(cache, &me.layout),
                     me.algorithm.unwrap_or(Algorithm::Sha256),
-                    me.size,
-                )?,
+                    me.mmap_size(),
+                    me.write_buffer_size
+                        .unwrap_or(write::DEFAULT_WRITE_BUFFER_SIZE),
+                )?
+                .with_extra_algorithm(me.extra_algorithm)
+                .with_trusted_integrity(me.trusted_sri.clone())
+                .with_preallocation(me.preallocation_size())?
+                .with_mode(me.content_mode)?
+                .with_verify_existing(me.verify_existing),
                 opts: me,
+                guard: CommitGuard::default(),
             })
         }
         inner(self, cache.as_ref())
@@ -467,25 +1203,264 @@ impl WriteOpts {
         self
     }
 
-    /// Sets the expected size of the data to write. If there's a date size
-    /// mismatch, `put.commit()` will return an error.
-    pub fn size(mut self, size: usize) -> Self {
+    /// Also hashes data with `algo` while writing it, and records that hash in
+    /// the index entry's integrity next to the one for `algorithm()`. Content
+    /// is still stored and checked on read using `algorithm()`, so pairing a
+    /// fast algorithm like xxh3 with sha256 keeps reads fast while still
+    /// allowing cryptographic verification with `verify_crypto_sync`.
+    pub fn extra_algorithm(mut self, algo: Algorithm) -> Self {
+        self.extra_algorithm = Some(algo);
+        self
+    }
+
+    /// Sets the expected size of the data to write. Writing more than that
+    /// fails as soon as it happens, with an IO error wrapping
+    /// `Error::ContentTooLarge`, and writing less makes `put.commit()` return
+    /// `Error::SizeMismatch`. If it's not set, the size of the data actually
+    /// written is recorded instead. Use [`size_max()`](WriteOpts::size_max)
+    /// to only cap the size.
+    pub fn size(mut self, size: u64) -> Self {
         self.size = Some(size);
         self
     }
 
+    /// Sets the most data that may be written. Writing more than that fails
+    /// as soon as it happens, with an IO error wrapping
+    /// `Error::ContentTooLarge`, but any amount up to it can be committed.
+    /// Useful for streamed downloads whose promised length shouldn't be
+    /// trusted to be exact.
+    pub fn size_max(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
     /// Sets arbitrary additional metadata to associate with the index entry.
     pub fn metadata(mut self, metadata: Value) -> Self {
         self.metadata = Some(metadata);
         self
     }
 
+    /// Serializes `metadata` to JSON, and associates that with the index
+    /// entry, same as `metadata()`. Read it back with
+    /// [`Metadata::metadata_as`](crate::Metadata::metadata_as).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use serde_derive::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Origin {
+    ///     url: String,
+    /// }
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let origin = Origin { url: "https://example.com/hello".into() };
+    ///     cacache::WriteOpts::new()
+    ///         .metadata_typed(&origin)?
+    ///         .open_sync("./my-cache", "my-key")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn metadata_typed<T: Serialize + ?Sized>(self, metadata: &T) -> Result<Self> {
+        let metadata = serde_json::to_value(metadata)
+            .with_context(|| "Failed to serialize entry metadata".into())?;
+        Ok(self.metadata(metadata))
+    }
+
     /// Sets arbitrary additional binary metadata to associate with the index entry.
     pub fn raw_metadata(mut self, metadata: Vec<u8>) -> Self {
         self.raw_metadata = Some(metadata);
         self
     }
 
+    /// Records how the data being written is encoded, such as an already
+    /// gzipped tarball. The data is still stored and hashed exactly as it's
+    /// written. The encoding shows up as `Metadata::content_encoding`, and
+    /// tells `read_decompressed_sync()` how to decode it.
+    pub fn content_encoding(mut self, encoding: ContentEncoding) -> Self {
+        self.content_encoding = Some(encoding);
+        self
+    }
+
+    /// Records the MIME type of the data being written, such as the
+    /// `Content-Type` of an HTTP response. It shows up as `Metadata::mime`.
+    pub fn mime<S: Into<String>>(mut self, mime: S) -> Self {
+        self.mime = Some(mime.into());
+        self
+    }
+
+    /// Records the original name of the file the data came from. It shows up
+    /// as `Metadata::filename`.
+    pub fn filename<S: Into<String>>(mut self, filename: S) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    /// Sets how many bytes a `SyncWriter` buffers before writing them out to
+    /// its temporary file, so many small writes get coalesced into fewer
+    /// syscalls. Defaults to 8KiB. A size of 0 disables buffering. Has no
+    /// effect when the data is small enough to be written through a memory
+    /// map.
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.write_buffer_size = Some(size);
+        self
+    }
+
+    /// Preallocates the temporary file for the whole `size()` of the data
+    /// up front, even when it's too large to be written through a memory map.
+    /// This reduces fragmentation for large sequential writes on filesystems
+    /// like ext4 and xfs. Has no effect unless `size()` is also set.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Makes opening the writer fail with `Error::InsufficientSpace` if
+    /// `size()` is at least `threshold` bytes and the filesystem the cache is
+    /// on doesn't have that much room left, instead of running out of space
+    /// partway through the write. Smaller writes skip the check, so they
+    /// don't pay for the extra syscall. Off by default, and a no-op on
+    /// platforms where free space can't be queried.
+    pub fn check_space(mut self, threshold: u64) -> Self {
+        self.space_check_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the permission bits of newly created content files, such as
+    /// `0o644` for a cache shared with other users. By default, they're only
+    /// readable by their owner. Content that's already in the cache is left
+    /// alone. Ignored on platforms other than unix.
+    pub fn content_mode(mut self, mode: u32) -> Self {
+        self.content_mode = Some(mode);
+        self
+    }
+
+    /// Fails if writing `len` more bytes after `written` would go past the
+    /// expected or maximum size.
+    fn check_limit(&self, written: u64, len: usize) -> std::io::Result<()> {
+        let limit = match (self.size, self.max_size) {
+            (Some(size), Some(max)) => size.min(max),
+            (size, max) => match size.or(max) {
+                Some(limit) => limit,
+                None => return Ok(()),
+            },
+        };
+        let total = written.saturating_add(len as u64);
+        if total > limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                Error::ContentTooLarge(limit, total),
+            ));
+        }
+        Ok(())
+    }
+
+    fn preallocation_size(&self) -> Option<u64> {
+        self.size.filter(|_| self.preallocate)
+    }
+
+    /// Fails if `check_space()` applies to this write and `cache` doesn't
+    /// have room for it.
+    fn ensure_space(&self, cache: &Path) -> Result<()> {
+        let size = match (self.size, self.space_check_threshold) {
+            (Some(size), Some(threshold)) if size >= threshold => size,
+            _ => return Ok(()),
+        };
+        let available = write::available_space(cache).with_context(|| {
+            format!(
+                "Failed to check free space for cache at {}",
+                cache.display()
+            )
+        })?;
+        match available {
+            Some(available) if available < size => Err(Error::InsufficientSpace(
+                cache.to_path_buf(),
+                size,
+                available,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a finished write against these options, and fills in the
+    /// integrity and size of what was written if they weren't set.
+    fn check_written(
+        &mut self,
+        cache: &Path,
+        key: Option<&str>,
+        writer_sri: &Integrity,
+        written: u64,
+    ) -> Result<()> {
+        if let Some(sri) = &self.sri {
+            if sri.matches(writer_sri).is_none() {
+                return Err(Error::WriteIntegrityMismatch(
+                    cache.to_path_buf(),
+                    key.map(String::from),
+                    sri.clone(),
+                    writer_sri.clone(),
+                ));
+            }
+        } else {
+            self.sri = Some(writer_sri.clone());
+        }
+        match self.size {
+            Some(size) if size != written => Err(Error::SizeMismatch(size, written)),
+            Some(_) => Ok(()),
+            // Record how much was written, so truncated content can be told
+            // apart from corrupted content when it's read back.
+            None => {
+                self.size = Some(written);
+                Ok(())
+            }
+        }
+    }
+
+    /// Index entry for `key` that these options would be inserted as, once
+    /// `check_written()` has filled them in.
+    fn detached_entry(self, key: Option<String>) -> Metadata {
+        let key = key.map_or_else(String::new, |key| match self.key_normalizer {
+            Some(normalize) => normalize(&key),
+            None => key,
+        });
+        Metadata {
+            key,
+            integrity: self.sri.expect("integrity is set by check_written()"),
+            time: self.time.unwrap_or_else(index::now),
+            size: self.size.unwrap_or(0),
+            metadata: self.metadata.unwrap_or(Value::Null),
+            raw_metadata: self.raw_metadata,
+            content_encoding: self.content_encoding,
+            mime: self.mime,
+            filename: self.filename,
+        }
+    }
+
+    /// The expected size, if it's known and small enough to be mapped into
+    /// memory on this platform.
+    fn mmap_size(&self) -> Option<usize> {
+        self.size.and_then(|size| usize::try_from(size).ok())
+    }
+
+    /// Stores `metadata` outside of the index bucket, as its own
+    /// content-addressed entry, whenever its serialized JSON is larger than
+    /// `threshold` bytes. The bucket line only keeps a reference to it, so
+    /// repeatedly overwriting a key doesn't duplicate large metadata.
+    pub fn external_metadata(mut self, threshold: usize) -> Self {
+        self.metadata_threshold = Some(threshold);
+        self
+    }
+
+    /// Gzips this entry's `metadata` and `raw_metadata` in its index bucket
+    /// line, as long as that actually makes the line smaller. Lookups
+    /// decompress them again transparently. Only builds with the `gzip`
+    /// feature can read metadata stored this way; looking the entry up
+    /// anywhere else fails with an error.
+    #[cfg(feature = "gzip")]
+    pub fn compress_metadata(mut self, compress: bool) -> Self {
+        self.compress_metadata = compress;
+        self
+    }
+
     /// Sets the specific time in unix milliseconds to associate with this
     /// entry. This is usually automatically set to the write time, but can be
     /// useful to change for tests and such.
@@ -494,6 +1469,58 @@ impl WriteOpts {
         self
     }
 
+    /// Sets a function used to normalize the key before it's hashed and
+    /// stored in the index. Set through `Cache::key_normalizer`, which also
+    /// normalizes the keys it looks up and removes.
+    pub(crate) fn key_normalizer(mut self, normalizer: KeyNormalizer) -> Self {
+        self.key_normalizer = Some(normalizer);
+        self
+    }
+
+    /// Sets the layout of the cache being written to, for caches opened
+    /// with a `CacheConfig`.
+    pub(crate) fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Refuses to index the written data if its key already points to
+    /// different content, failing with `Error::KeyConflict` instead, so each
+    /// key can only ever be set to a single piece of content. Writing the
+    /// same content again, or writing to a removed key, is still allowed.
+    /// Defaults to false.
+    ///
+    /// Content is compared by integrity, so the same data hashed with a
+    /// different algorithm counts as a conflict. The check and the write
+    /// aren't atomic, so two concurrent writers can still both succeed.
+    /// Content written before a conflict is detected stays in the cache
+    /// until it's garbage collected.
+    pub fn fail_on_conflict(mut self, fail_on_conflict: bool) -> Self {
+        self.fail_on_conflict = fail_on_conflict;
+        self
+    }
+
+    /// Reads back content that's already in the cache under the same hash
+    /// before trusting it, instead of assuming it's identical to what was
+    /// just written. If it doesn't match, which can only be down to on-disk
+    /// corruption or tampering, it's replaced with the newly written data.
+    /// Defaults to false, since it costs a full read of the existing
+    /// content.
+    pub fn verify_existing(mut self, verify: bool) -> Self {
+        self.verify_existing = verify;
+        self
+    }
+
+    /// Sets the size of the buffer used to read through a linked target file
+    /// when calculating its integrity. Larger buffers can speed up linking
+    /// very large files. Defaults to 16KiB. A size of 0 is treated as 1, since
+    /// the file can't be read through an empty buffer.
+    #[cfg(feature = "link_to")]
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = Some(size.max(1));
+        self
+    }
+
     /// Sets the expected integrity hash of the written data. If there's a
     /// mismatch between this Integrity and the one calculated by the write,
     /// `put.commit()` will error.
@@ -501,21 +1528,42 @@ impl WriteOpts {
         self.sri = Some(sri);
         self
     }
+
+    /// Trusts that the written data has integrity `sri`, skipping hashing
+    /// entirely and storing the data at the content address for `sri`. This
+    /// saves CPU time when the hash is already known, such as when a
+    /// registry provides it alongside a large artifact.
+    ///
+    /// **This is unsafe to use with untrusted data.** Unlike `integrity()`,
+    /// the data is never verified: if it doesn't actually match `sri`, the
+    /// cache will end up holding corrupted content under that address, and
+    /// the corruption will only be caught when that content is read back.
+    pub fn trust_integrity(mut self, sri: Integrity) -> Self {
+        self.trusted_sri = Some(sri);
+        self
+    }
 }
 
 /// A reference to an open file writing to the cache.
+///
+/// Dropping a `SyncWriter` without committing it throws away everything
+/// written to it, removing its temp file on a best-effort basis. Use
+/// `abort()` instead to find out if removing it failed.
 pub struct SyncWriter {
     cache: PathBuf,
     key: Option<String>,
-    written: usize,
+    written: u64,
     pub(crate) writer: write::Writer,
     opts: WriteOpts,
+    guard: CommitGuard,
 }
 
 impl Write for SyncWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.opts.check_limit(self.written, buf.len())?;
         let written = self.writer.write(buf)?;
-        self.written += written;
+        self.written += written as u64;
+        self.guard.arm("SyncWriter");
         Ok(written)
     }
     fn flush(&mut self) -> std::io::Result<()> {
@@ -580,37 +1628,192 @@ impl SyncWriter {
     /// verifies data against `size` and `integrity` options, if provided.
     /// Must be called manually in order to complete the writing process,
     /// otherwise everything will be thrown out.
-    pub fn commit(mut self) -> Result<Integrity> {
+    pub fn commit(self) -> Result<Integrity> {
+        self.commit_detailed().map(|committed| committed.integrity)
+    }
+
+    /// Throws away everything written so far without adding anything to the
+    /// cache, removing the temp file and returning any error from removing
+    /// it.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::io::prelude::*;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::SyncWriter::create("./my-cache", "my-key")?;
+    ///     fd.write_all(b"hel").expect("Failed to write to cache");
+    ///     // Changed our minds.
+    ///     fd.abort()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn abort(mut self) -> Result<()> {
+        self.guard.disarm();
+        self.writer.abort()
+    }
+
+    /// Same as `commit()`, but also reports whether identical content was
+    /// already present in the cache, in which case nothing new was written
+    /// to the content store.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::io::prelude::*;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::SyncWriter::create("./my-cache", "my-key")?;
+    ///     fd.write_all(b"hello").expect("Failed to write to cache");
+    ///     let committed = fd.commit_detailed()?;
+    ///     if committed.deduplicated {
+    ///         println!("{} was already cached", committed.integrity);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn commit_detailed(mut self) -> Result<Committed> {
+        self.guard.disarm();
         let cache = self.cache;
-        let writer_sri = self.writer.close()?;
-        if let Some(sri) = &self.opts.sri {
-            if sri.matches(&writer_sri).is_none() {
-                return Err(ssri::Error::IntegrityCheckError(sri.clone(), writer_sri).into());
-            }
-        } else {
-            self.opts.sri = Some(writer_sri.clone());
-        }
-        if let Some(size) = self.opts.size {
-            if size != self.written {
-                return Err(Error::SizeMismatch(size, self.written));
-            }
-        }
-        if let Some(key) = self.key {
-            index::insert(&cache, &key, self.opts)
+        let (writer_sri, deduplicated) = self.writer.close_detailed()?;
+        self.opts
+            .check_written(&cache, self.key.as_deref(), &writer_sri, self.written)?;
+        let integrity = if let Some(key) = self.key {
+            index::insert(&cache, &key, self.opts)?
         } else {
-            Ok(writer_sri)
-        }
+            writer_sri
+        };
+        Ok(Committed {
+            integrity,
+            deduplicated,
+        })
+    }
+
+    /// Commits the written data to the content store like `commit()`, but
+    /// never adds it to the index, even if this `SyncWriter` has a key.
+    /// Returns the entry that would have been written instead, so it can be
+    /// stored elsewhere. For hash-only writers, the entry's `key` is empty.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::io::prelude::*;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::WriteOpts::new().open_hash_sync("./my-cache")?;
+    ///     fd.write_all(b"hello").expect("Failed to write to cache");
+    ///     let entry = fd.commit_detached()?;
+    ///     println!("{} ({} bytes)", entry.integrity, entry.size);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn commit_detached(mut self) -> Result<Metadata> {
+        self.guard.disarm();
+        let writer_sri = self.writer.close()?;
+        self.opts
+            .check_written(&self.cache, self.key.as_deref(), &writer_sri, self.written)?;
+        Ok(self.opts.detached_entry(self.key))
+    }
+
+    /// Same as `commit()`, but also hands back the `WriteOpts` this
+    /// `SyncWriter` was opened with, so they can be used to open another
+    /// `SyncWriter`.
+    ///
+    /// The underlying temporary file in `{cache}/tmp` is not reusable: it
+    /// gets moved into place as the content file on commit, so each
+    /// `SyncWriter` always creates a fresh one when it's opened.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::io::prelude::*;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let mut fd = cacache::SyncWriter::create("./my-cache", "key1")?;
+    ///     fd.write_all(b"hello").expect("Failed to write to cache");
+    ///     let (_sri, opts) = fd.finish()?;
+    ///     let mut fd = opts.open_sync("./my-cache", "key2")?;
+    ///     fd.write_all(b"world").expect("Failed to write to cache");
+    ///     fd.commit()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn finish(self) -> Result<(Integrity, WriteOpts)> {
+        let opts = self.opts.clone();
+        let sri = self.commit()?;
+        Ok((sri, opts))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    #[cfg(feature = "async-std")]
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use async_attributes::test as async_test;
     #[cfg(feature = "tokio")]
     use tokio::test as async_test;
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    static_assertions::assert_impl_all!(crate::Writer: Send);
+    static_assertions::assert_impl_all!(crate::SyncWriter: Send);
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn writer_across_spawn() {
+        use tokio::io::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::Writer::create(&dir, "hello").await.unwrap();
+        writer.write_all(b"hel").await.unwrap();
+        let sri = tokio::spawn(async move {
+            tokio::task::yield_now().await;
+            writer.write_all(b"lo").await.unwrap();
+            writer.commit().await.unwrap()
+        })
+        .await
+        .unwrap();
+        assert_eq!(sri, crate::write_hash(&dir, b"hello").await.unwrap());
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn split_writer() {
+        use crate::async_lib::AsyncWriteExt;
+        #[cfg(feature = "async-std")]
+        use async_std::task::spawn;
+        #[cfg(feature = "tokio")]
+        use tokio::spawn;
+        // async-fs has no executor to spawn on, so drive it on a thread.
+        #[cfg(feature = "async-fs")]
+        let spawn = |fut| std::thread::spawn(|| futures::executor::block_on(fut));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let (mut writer, committed) = crate::write_async_reader(&dir, "hello").await.unwrap();
+        let consumer = spawn(committed);
+        writer.write_all(b"hello").await.unwrap();
+        #[cfg(any(feature = "async-std", feature = "async-fs"))]
+        writer.close().await.unwrap();
+        #[cfg(feature = "tokio")]
+        writer.shutdown().await.unwrap();
+        #[cfg(not(feature = "async-fs"))]
+        let sri = consumer.await;
+        #[cfg(feature = "async-fs")]
+        let sri = consumer.join();
+        #[cfg(any(feature = "tokio", feature = "async-fs"))]
+        let sri = sri.unwrap();
+        assert_eq!(
+            sri.unwrap(),
+            crate::write_hash(&dir, b"hello").await.unwrap()
+        );
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+
+        let (writer, committed) = crate::write_async_reader(&dir, "dropped").await.unwrap();
+        drop(writer);
+        assert!(committed.await.is_err());
+        assert!(crate::metadata(&dir, "dropped").await.unwrap().is_none());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn round_trip() {
         let tmp = tempfile::tempdir().unwrap();
@@ -620,6 +1823,525 @@ mod tests {
         assert_eq!(data, b"hello");
     }
 
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn write_owned() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_owned(&dir, "hello", b"hello".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(sri, crate::write(&dir, "other", b"hello").await.unwrap());
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+        let entry = crate::metadata(&dir, "hello").await.unwrap().unwrap();
+        assert_eq!(entry.size, 5);
+
+        // The key is checked before any content gets written.
+        let key = "k".repeat(crate::index::MAX_KEY_LENGTH + 1);
+        let goodbye = crate::integrity_of(b"goodbye", crate::Algorithm::Sha256);
+        assert!(matches!(
+            crate::write_owned(&dir, &key, b"goodbye".to_vec()).await,
+            Err(crate::Error::KeyTooLong(..))
+        ));
+        assert!(!crate::exists(&dir, &goodbye).await);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn hash_chunks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let chunks = futures::stream::iter(vec![b"hel".to_vec(), b"lo".to_vec()]);
+        let sri = crate::write_hash_chunks(&dir, chunks).await.unwrap();
+        assert_eq!(sri, crate::write_hash(&dir, b"hello").await.unwrap());
+        assert_eq!(crate::read_hash(&dir, &sri).await.unwrap(), b"hello");
+    }
+
+    #[test]
+    fn hash_chunks_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_hash_chunks_sync(&dir, [&b"hel"[..], &b"lo"[..]]).unwrap();
+        assert_eq!(sri, crate::write_hash_sync(&dir, b"hello").unwrap());
+        assert_eq!(crate::read_hash_sync(&dir, &sri).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn write_multi_key_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let keys = ["a", "b", "c"];
+        let sri = crate::write_multi_key_sync(&dir, &keys, b"hello").unwrap();
+        for key in keys {
+            assert_eq!(crate::read_sync(&dir, key).unwrap(), b"hello");
+            let entry = crate::metadata_sync(&dir, key).unwrap().unwrap();
+            assert_eq!(entry.integrity, sri);
+        }
+        let files = walkdir::WalkDir::new(dir.join("content-v2"))
+            .into_iter()
+            .filter(|entry| entry.as_ref().unwrap().file_type().is_file())
+            .count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn write_many_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let entries = (0..20)
+            .map(|i| (format!("key-{i}"), format!("data-{i}").into_bytes()))
+            .chain([("key-0".to_string(), b"again".to_vec())]);
+        let sris = crate::write_many_sync(&dir, entries).unwrap();
+        assert_eq!(sris.len(), 21);
+        for (i, sri) in sris.iter().enumerate().take(20).skip(1) {
+            let (key, data) = (format!("key-{i}"), format!("data-{i}").into_bytes());
+            let entry = crate::metadata_sync(&dir, &key).unwrap().unwrap();
+            assert_eq!(&entry.integrity, sri);
+            assert_eq!(entry.size, data.len() as u64);
+            assert_eq!(crate::read_sync(&dir, &key).unwrap(), data);
+        }
+        // Later entries for the same key win, just like separate writes.
+        assert_eq!(crate::read_sync(&dir, "key-0").unwrap(), b"again");
+
+        let long_key = "k".repeat(crate::index::MAX_KEY_LENGTH + 1);
+        let entries = [
+            ("fine".to_string(), b"fine".to_vec()),
+            (long_key, b"too long".to_vec()),
+        ];
+        assert!(matches!(
+            crate::write_many_sync(&dir, entries),
+            Err(crate::Error::KeyTooLong(..))
+        ));
+        assert!(crate::metadata_sync(&dir, "fine").unwrap().is_none());
+        let fine = crate::integrity_of(b"fine", crate::Algorithm::Sha256);
+        assert!(!crate::exists_sync(&dir, &fine));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn write_multi_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let keys = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let sri = crate::write_multi_key(&dir, &keys, b"hello").await.unwrap();
+        for key in &keys {
+            assert_eq!(crate::read(&dir, key).await.unwrap(), b"hello");
+            assert_eq!(
+                crate::metadata(&dir, key).await.unwrap().unwrap().integrity,
+                sri
+            );
+        }
+    }
+
+    #[test]
+    fn append_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync_with_algo(crate::Algorithm::Xxh3, &dir, "log", b"hello").unwrap();
+        let sri = crate::append_sync(&dir, "log", b" world", "log-2").unwrap();
+        assert_eq!(
+            sri,
+            crate::integrity_of(b"hello world", crate::Algorithm::Xxh3)
+        );
+        assert_eq!(crate::read_sync(&dir, "log").unwrap(), b"hello");
+        assert_eq!(crate::read_sync(&dir, "log-2").unwrap(), b"hello world");
+
+        crate::append_sync(&dir, "log", b"!", "log").unwrap();
+        assert_eq!(crate::read_sync(&dir, "log").unwrap(), b"hello!");
+        assert!(matches!(
+            crate::append_sync(&dir, "missing", b"!", "log-3"),
+            Err(crate::Error::EntryNotFound(..))
+        ));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn commit_detailed_dedup() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::Writer::create(&dir, "hello").await.unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        assert!(!writer.commit_detailed().await.unwrap().deduplicated);
+        let mut writer = crate::Writer::create(&dir, "world").await.unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        assert!(writer.commit_detailed().await.unwrap().deduplicated);
+    }
+
+    #[test]
+    fn commit_detailed_dedup_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::SyncWriter::create(&dir, "hello").unwrap();
+        writer.write_all(b"hello").unwrap();
+        let committed = writer.commit_detailed().unwrap();
+        assert!(!committed.deduplicated);
+        let mut writer = crate::SyncWriter::create(&dir, "world").unwrap();
+        writer.write_all(b"hello").unwrap();
+        let dup = writer.commit_detailed().unwrap();
+        assert!(dup.deduplicated);
+        assert_eq!(dup.integrity, committed.integrity);
+    }
+
+    #[test]
+    fn verify_existing_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::integrity_of(b"hello", crate::Algorithm::Sha256);
+        let cpath = crate::content_path(&dir, &sri);
+        std::fs::create_dir_all(cpath.parent().unwrap()).unwrap();
+        std::fs::write(&cpath, b"jello").unwrap();
+
+        // By default, content already in place is trusted as is.
+        let mut writer = crate::SyncWriter::create(&dir, "hello").unwrap();
+        writer.write_all(b"hello").unwrap();
+        assert!(writer.commit_detailed().unwrap().deduplicated);
+        assert_eq!(std::fs::read(&cpath).unwrap(), b"jello");
+
+        let mut writer = crate::WriteOpts::new()
+            .verify_existing(true)
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        assert!(!writer.commit_detailed().unwrap().deduplicated);
+        assert_eq!(std::fs::read(&cpath).unwrap(), b"hello");
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"hello");
+
+        // Intact content is still deduplicated.
+        let mut writer = crate::WriteOpts::new()
+            .verify_existing(true)
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        assert!(writer.commit_detailed().unwrap().deduplicated);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn verify_existing() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::integrity_of(b"hello", crate::Algorithm::Sha256);
+        let cpath = crate::content_path(&dir, &sri);
+        std::fs::create_dir_all(cpath.parent().unwrap()).unwrap();
+        std::fs::write(&cpath, b"jello").unwrap();
+
+        let mut writer = crate::WriteOpts::new()
+            .verify_existing(true)
+            .open(&dir, "hello")
+            .await
+            .unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        assert!(!writer.commit_detailed().await.unwrap().deduplicated);
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+    }
+
+    #[test]
+    fn commit_detached_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new().open_hash_sync(&dir).unwrap();
+        writer.write_all(b"hello world").unwrap();
+        let entry = writer.commit_detached().unwrap();
+        assert_eq!(entry.size, 11);
+        assert_eq!(entry.key, "");
+        assert_eq!(entry.integrity, crate::Integrity::from(b"hello world"));
+        assert_eq!(
+            crate::read_hash_sync(&dir, &entry.integrity).unwrap(),
+            b"hello world"
+        );
+
+        // Keyed writers don't touch the index either.
+        let mut writer = crate::SyncWriter::create(&dir, "key").unwrap();
+        writer.write_all(b"hello").unwrap();
+        assert_eq!(writer.commit_detached().unwrap().key, "key");
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn commit_detached() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new().open_hash(&dir).await.unwrap();
+        writer.write_all(b"hello world").await.unwrap();
+        let entry = writer.commit_detached().await.unwrap();
+        assert_eq!(entry.size, 11);
+        assert_eq!(
+            crate::read_hash(&dir, &entry.integrity).await.unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[test]
+    fn every_algorithm() {
+        use ssri::Algorithm;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        for algo in [
+            Algorithm::Sha512,
+            Algorithm::Sha384,
+            Algorithm::Sha256,
+            Algorithm::Sha1,
+            Algorithm::Xxh3,
+        ] {
+            let sri = crate::write_sync_with_algo(algo, &dir, "hello", b"hello").unwrap();
+            assert_eq!(sri.pick_algorithm(), algo);
+        }
+    }
+
+    #[test]
+    fn tiny_writes_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let mut writer = crate::WriteOpts::new()
+            .write_buffer_size(512)
+            .open_sync(&dir, "hello")
+            .unwrap();
+        for byte in &data {
+            writer.write_all(std::slice::from_ref(byte)).unwrap();
+        }
+        let sri = writer.commit().unwrap();
+        assert_eq!(sri, ssri::Integrity::from(&data));
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), data);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn preallocated_write() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let data = vec![42u8; 2 * 1024 * 1024];
+        let mut writer = crate::WriteOpts::new()
+            .size(data.len() as u64)
+            .preallocate(true)
+            .open(&dir, "hello")
+            .await
+            .unwrap();
+        writer.write_all(&data).await.unwrap();
+        let sri = writer.commit().await.unwrap();
+        assert_eq!(sri, ssri::Integrity::from(&data));
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), data);
+    }
+
+    #[test]
+    fn integrity_mismatch_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let wanted = ssri::Integrity::from(b"goodbye");
+        let mut writer = crate::WriteOpts::new()
+            .integrity(wanted.clone())
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        match writer.commit() {
+            Err(crate::Error::WriteIntegrityMismatch(cache, key, w, a)) => {
+                assert_eq!(cache, dir);
+                assert_eq!(key.as_deref(), Some("hello"));
+                assert_eq!(w, wanted);
+                assert_eq!(a, ssri::Integrity::from(b"hello"));
+            }
+            res => panic!("unexpected result: {res:?}"),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn warn_on_uncommitted_drop() {
+        use std::io::Write;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        struct WarnCounter(Arc<AtomicUsize>);
+
+        impl tracing::Subscriber for WarnCounter {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+                tracing::span::Id::from_u64(1)
+            }
+            fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+            fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+            fn event(&self, event: &tracing::Event<'_>) {
+                if *event.metadata().level() == tracing::Level::WARN {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            fn enter(&self, _: &tracing::span::Id) {}
+            fn exit(&self, _: &tracing::span::Id) {}
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let warnings = Arc::new(AtomicUsize::new(0));
+        tracing::subscriber::with_default(WarnCounter(warnings.clone()), || {
+            let mut writer = crate::SyncWriter::create(&dir, "dropped").unwrap();
+            writer.write_all(b"hello").unwrap();
+            drop(writer);
+            assert_eq!(warnings.load(Ordering::SeqCst), 1);
+
+            // Nothing was written, so there's nothing to lose.
+            drop(crate::SyncWriter::create(&dir, "unused").unwrap());
+
+            let mut writer = crate::SyncWriter::create(&dir, "committed").unwrap();
+            writer.write_all(b"hello").unwrap();
+            writer.commit().unwrap();
+
+            let mut writer = crate::SyncWriter::create(&dir, "aborted").unwrap();
+            writer.write_all(b"hello").unwrap();
+            writer.abort().unwrap();
+        });
+        assert_eq!(warnings.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn size_limits_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .size(5)
+            .open_sync(&dir, "exact")
+            .unwrap();
+        writer.write_all(b"hel").unwrap();
+        let err = writer.write_all(b"lo world").unwrap_err();
+        let inner = err.into_inner().unwrap();
+        assert!(matches!(
+            inner.downcast_ref::<crate::Error>(),
+            Some(crate::Error::ContentTooLarge(5, 11))
+        ));
+
+        let mut writer = crate::WriteOpts::new()
+            .size(5)
+            .open_sync(&dir, "short")
+            .unwrap();
+        writer.write_all(b"hel").unwrap();
+        assert!(matches!(
+            writer.commit(),
+            Err(crate::Error::SizeMismatch(5, 3))
+        ));
+        assert!(crate::metadata_sync(&dir, "short").unwrap().is_none());
+
+        let mut writer = crate::WriteOpts::new()
+            .size_max(5)
+            .open_sync(&dir, "max")
+            .unwrap();
+        writer.write_all(b"hey").unwrap();
+        assert!(writer.write_all(b"!!!").is_err());
+        writer.commit().unwrap();
+        let entry = crate::metadata_sync(&dir, "max").unwrap().unwrap();
+        assert_eq!(entry.size, 3);
+        assert_eq!(crate::read_sync(&dir, "max").unwrap(), b"hey");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_space_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache");
+        let huge = u64::MAX / 2;
+        match crate::WriteOpts::new()
+            .size(huge)
+            .check_space(1024)
+            .open_sync(&dir, "huge")
+        {
+            Err(crate::Error::InsufficientSpace(path, needed, _)) => {
+                assert_eq!(path, dir);
+                assert_eq!(needed, huge);
+            }
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+
+        // Writes below the threshold aren't checked.
+        assert!(crate::WriteOpts::new()
+            .size(huge)
+            .check_space(u64::MAX)
+            .open_sync(&dir, "huge")
+            .is_ok());
+
+        let mut writer = crate::WriteOpts::new()
+            .size(5)
+            .check_space(1)
+            .open_sync(&dir, "small")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.commit().unwrap();
+        assert_eq!(crate::read_sync(&dir, "small").unwrap(), b"hello");
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn size_limits() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .size_max(5)
+            .open(&dir, "max")
+            .await
+            .unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        assert!(writer.write_all(b"!").await.is_err());
+        writer.commit().await.unwrap();
+        assert_eq!(crate::read(&dir, "max").await.unwrap(), b"hello");
+    }
+
+    #[test]
+    fn trust_integrity_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = ssri::Integrity::from(b"hello");
+        let mut writer = crate::WriteOpts::new()
+            .trust_integrity(sri.clone())
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        assert_eq!(writer.commit().unwrap(), sri);
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"hello");
+
+        // Lies aren't caught until the content is read back.
+        let lie = ssri::Integrity::from(b"goodbye");
+        let mut writer = crate::WriteOpts::new()
+            .trust_integrity(lie.clone())
+            .open_sync(&dir, "goodbye")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        assert_eq!(writer.commit().unwrap(), lie);
+        assert!(matches!(
+            crate::read_sync(&dir, "goodbye"),
+            Err(crate::Error::ReadIntegrityMismatch(..))
+        ));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn trust_integrity() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = ssri::Integrity::from(b"hello");
+        let mut writer = crate::WriteOpts::new()
+            .trust_integrity(sri.clone())
+            .open(&dir, "hello")
+            .await
+            .unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        assert_eq!(writer.commit().await.unwrap(), sri);
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+    }
+
     #[test]
     fn round_trip_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -629,6 +2351,50 @@ mod tests {
         assert_eq!(data, b"hello");
     }
 
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn finish_reopen() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .algorithm(crate::Algorithm::Xxh3)
+            .open(&dir, "hello")
+            .await
+            .unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        let (sri, opts) = writer.finish().await.unwrap();
+        assert_eq!(sri.pick_algorithm(), crate::Algorithm::Xxh3);
+
+        let mut writer = opts.open(&dir, "world").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        let sri = writer.commit().await.unwrap();
+        assert_eq!(sri.pick_algorithm(), crate::Algorithm::Xxh3);
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), b"hello");
+        assert_eq!(crate::read(&dir, "world").await.unwrap(), b"world");
+    }
+
+    #[test]
+    fn finish_reopen_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .algorithm(crate::Algorithm::Xxh3)
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        let (sri, opts) = writer.finish().unwrap();
+        assert_eq!(sri.pick_algorithm(), crate::Algorithm::Xxh3);
+
+        let mut writer = opts.open_sync(&dir, "world").unwrap();
+        writer.write_all(b"world").unwrap();
+        let sri = writer.commit().unwrap();
+        assert_eq!(sri.pick_algorithm(), crate::Algorithm::Xxh3);
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"hello");
+        assert_eq!(crate::read_sync(&dir, "world").unwrap(), b"world");
+    }
+
     #[test]
     fn hash_write_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -643,7 +2409,7 @@ mod tests {
         assert_eq!(result, original, "we did not read back what we wrote");
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn hash_write_async() {
         let tmp = tempfile::tempdir().unwrap();
@@ -659,4 +2425,170 @@ mod tests {
             String::from_utf8(bytes).expect("we wrote valid utf8 but did not read valid utf8 back");
         assert_eq!(result, original, "we did not read back what we wrote");
     }
+
+    #[test]
+    fn integrity_of_matches_write() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello world").unwrap();
+        assert_eq!(
+            crate::integrity_of(b"hello world", crate::Algorithm::Sha256),
+            sri
+        );
+        assert_eq!(
+            crate::integrity_of_reader(&b"hello world"[..], crate::Algorithm::Sha256).unwrap(),
+            sri
+        );
+        assert!(crate::exists_sync(
+            &dir,
+            &crate::integrity_of(b"hello world", crate::Algorithm::Sha256)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn content_mode_sync() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::WriteOpts::new()
+            .content_mode(0o644)
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        let sri = writer.commit().unwrap();
+        let cpath = crate::content_path(&dir, &sri);
+        let mode = std::fs::metadata(cpath).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644);
+    }
+
+    #[test]
+    fn write_and_read_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let (sri, data) = crate::write_and_read_sync(&dir, "hello", b"hello".to_vec()).unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(sri, crate::integrity_of(&data, crate::Algorithm::Sha256));
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), data);
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn write_and_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let (sri, data) = crate::write_and_read(&dir, "hello", b"hello".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(sri, crate::integrity_of(&data, crate::Algorithm::Sha256));
+        assert_eq!(crate::read(&dir, "hello").await.unwrap(), data);
+    }
+
+    #[test]
+    fn abort_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::SyncWriter::create(&dir, "hello").unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.abort().unwrap();
+        assert_eq!(std::fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+        assert!(crate::metadata_sync(&dir, "hello").unwrap().is_none());
+        assert!(!crate::exists_sync(
+            &dir,
+            &crate::integrity_of(b"hello", crate::Algorithm::Sha256)
+        ));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn abort() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut writer = crate::Writer::create(&dir, "hello").await.unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        writer.abort().await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+        assert!(crate::metadata(&dir, "hello").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn fail_on_conflict_sync() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let write = |data: &[u8]| {
+            let mut writer = crate::WriteOpts::new()
+                .fail_on_conflict(true)
+                .open_sync(&dir, "hello")
+                .unwrap();
+            writer.write_all(data).unwrap();
+            writer.commit()
+        };
+        let sri = write(b"hello").unwrap();
+        // Writing the same content again is fine.
+        assert_eq!(write(b"hello").unwrap(), sri);
+        match write(b"world") {
+            Err(crate::Error::KeyConflict(_, key, existing, _)) => {
+                assert_eq!(key, "hello");
+                assert_eq!(existing, sri);
+            }
+            other => panic!("expected a key conflict, got {other:?}"),
+        }
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"hello");
+        // Without the option, the key can be overwritten as usual.
+        crate::write_sync(&dir, "hello", b"world").unwrap();
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"world");
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn fail_on_conflict() {
+        use crate::async_lib::AsyncWriteExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "hello", b"hello").await.unwrap();
+        let mut writer = crate::WriteOpts::new()
+            .fail_on_conflict(true)
+            .open(&dir, "hello")
+            .await
+            .unwrap();
+        writer.write_all(b"world").await.unwrap();
+        assert!(matches!(
+            writer.commit().await,
+            Err(crate::Error::KeyConflict(..))
+        ));
+    }
+
+    #[test]
+    fn metadata_typed() {
+        #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+        struct Origin {
+            url: String,
+            etag: Option<String>,
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let origin = Origin {
+            url: "https://example.com/hello".into(),
+            etag: None,
+        };
+        let writer = crate::WriteOpts::new()
+            .metadata_typed(&origin)
+            .unwrap()
+            .open_sync(&dir, "hello")
+            .unwrap();
+        writer.commit().unwrap();
+
+        let entry = crate::metadata_sync(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.metadata_as::<Origin>().unwrap(), origin);
+        assert!(matches!(
+            entry.metadata_as::<Vec<String>>(),
+            Err(crate::Error::SerdeError(..))
+        ));
+    }
 }
@@ -0,0 +1,209 @@
+//! An in-memory layer for repeatedly reading the same content.
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use ssri::Integrity;
+
+use crate::content::read;
+use crate::errors::{Error, Result};
+use crate::index;
+
+/// Keeps recently read content in memory, so reading it again doesn't need
+/// to touch the disk or hash it all over again.
+///
+/// Content is keyed by its integrity, and only verified data is ever kept.
+/// Once more than `capacity` pieces of content are held, the least recently
+/// used one is dropped. Looking content up by key still reads its index
+/// entry, which is usually much smaller than the content itself.
+///
+/// This is entirely separate from the on-disk cache, and it's up to the
+/// caller to keep it around for as long as it's useful. Content removed from
+/// the disk cache stays available through a `ReadCache` that already holds it.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let hot = cacache::ReadCache::new(16);
+///     for _ in 0..1000 {
+///         let data = hot.get_sync("./my-cache", "my-key")?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ReadCache {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<Integrity, Slot>,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Slot {
+    data: Arc<[u8]>,
+    last_used: u64,
+}
+
+impl State {
+    fn get(&mut self, sri: &Integrity) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(sri).map(|slot| {
+            slot.last_used = clock;
+            slot.data.clone()
+        })
+    }
+
+    fn insert(&mut self, capacity: usize, sri: Integrity, data: Arc<[u8]>) {
+        if capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        let last_used = self.clock;
+        self.entries.insert(sri, Slot { data, last_used });
+        while self.entries.len() > capacity {
+            // Capacities are expected to be small, so a scan is cheaper
+            // than keeping a separate recency list up to date on every hit.
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, slot)| slot.last_used)
+                .map(|(sri, _)| sri.clone());
+            match oldest {
+                Some(sri) => self.entries.remove(&sri),
+                None => break,
+            };
+        }
+    }
+}
+
+impl ReadCache {
+    /// Creates an empty `ReadCache` that holds at most `capacity` pieces of
+    /// content at a time.
+    pub fn new(capacity: usize) -> ReadCache {
+        ReadCache {
+            capacity,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Number of pieces of content currently held in memory.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Whether no content is currently held in memory.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads the content indexed under `key` in `cache`, from memory if it's
+    /// there, or from disk otherwise, in which case it's then kept around.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn get<P, K>(&self, cache: P, key: K) -> Result<Arc<[u8]>>
+    where
+        P: AsRef<Path>,
+        K: AsRef<str>,
+    {
+        let (cache, key) = (cache.as_ref(), key.as_ref());
+        match index::find_async(cache, key).await? {
+            Some(entry) => {
+                if let Some(data) = self.cached(&entry.integrity) {
+                    return Ok(data);
+                }
+                let data = read::read_sized_async(cache.into(), &entry.integrity, entry.size)
+                    .await
+                    .map_err(|e| e.with_key(key))?;
+                Ok(self.keep(entry.integrity, data))
+            }
+            None => Err(Error::EntryNotFound(cache.to_path_buf(), key.into())),
+        }
+    }
+
+    /// Reads the content for `sri` in `cache`, from memory if it's there, or
+    /// from disk otherwise, in which case it's then kept around.
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    pub async fn get_hash<P: AsRef<Path>>(&self, cache: P, sri: &Integrity) -> Result<Arc<[u8]>> {
+        if let Some(data) = self.cached(sri) {
+            return Ok(data);
+        }
+        let data = read::read_async(cache.as_ref().into(), sri).await?;
+        Ok(self.keep(sri.clone(), data))
+    }
+
+    /// Reads the content indexed under `key` in `cache` synchronously, from
+    /// memory if it's there, or from disk otherwise, in which case it's then
+    /// kept around.
+    pub fn get_sync<P, K>(&self, cache: P, key: K) -> Result<Arc<[u8]>>
+    where
+        P: AsRef<Path>,
+        K: AsRef<str>,
+    {
+        let (cache, key) = (cache.as_ref(), key.as_ref());
+        match index::find(cache, key)? {
+            Some(entry) => {
+                if let Some(data) = self.cached(&entry.integrity) {
+                    return Ok(data);
+                }
+                let data = read::read_sized(cache.into(), &entry.integrity, entry.size)
+                    .map_err(|e| e.with_key(key))?;
+                Ok(self.keep(entry.integrity, data))
+            }
+            None => Err(Error::EntryNotFound(cache.to_path_buf(), key.into())),
+        }
+    }
+
+    /// Reads the content for `sri` in `cache` synchronously, from memory if
+    /// it's there, or from disk otherwise, in which case it's then kept
+    /// around.
+    pub fn get_hash_sync<P: AsRef<Path>>(&self, cache: P, sri: &Integrity) -> Result<Arc<[u8]>> {
+        if let Some(data) = self.cached(sri) {
+            return Ok(data);
+        }
+        let data = read::read(cache.as_ref().into(), sri)?;
+        Ok(self.keep(sri.clone(), data))
+    }
+
+    fn cached(&self, sri: &Integrity) -> Option<Arc<[u8]>> {
+        self.state.lock().unwrap().get(sri)
+    }
+
+    fn keep(&self, sri: Integrity, data: Vec<u8>) -> Arc<[u8]> {
+        let data: Arc<[u8]> = data.into();
+        self.state
+            .lock()
+            .unwrap()
+            .insert(self.capacity, sri, data.clone());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_cache_hits_skip_disk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "hello", b"hello").unwrap();
+        crate::write_sync(&dir, "world", b"world").unwrap();
+
+        let hot = ReadCache::new(1);
+        assert_eq!(&*hot.get_sync(&dir, "hello").unwrap(), b"hello");
+        // Content that's in memory is served even once it's gone from disk.
+        crate::remove_hash_sync(&dir, &sri).unwrap();
+        assert_eq!(&*hot.get_sync(&dir, "hello").unwrap(), b"hello");
+        assert_eq!(&*hot.get_hash_sync(&dir, &sri).unwrap(), b"hello");
+
+        // Over capacity, the least recently used content is dropped.
+        assert_eq!(&*hot.get_sync(&dir, "world").unwrap(), b"world");
+        assert_eq!(hot.len(), 1);
+        assert!(hot.get_sync(&dir, "hello").is_err());
+    }
+}
@@ -1,16 +1,52 @@
 //! Functions for removing things from the cache.
 use std::fs;
-use std::path::Path;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
 use ssri::Integrity;
+use walkdir::WalkDir;
 
+use crate::config::CONFIG_FILE;
 use crate::content::rm;
-use crate::errors::{IoErrorExt, Result};
-use crate::index;
+use crate::errors::{Error, IoErrorExt, Result};
+use crate::index::{self, Metadata};
+
+/// Summary of a [`clear_detailed_sync`] run.
+#[derive(Debug, Default)]
+pub struct ClearStats {
+    /// Number of files that were removed.
+    pub removed: usize,
+    /// Files and directories that couldn't be removed, along with why. These
+    /// are left in place.
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl ClearStats {
+    fn record(&mut self, path: PathBuf, is_dir: bool, res: std::io::Result<()>) {
+        match res {
+            Ok(()) if !is_dir => self.removed += 1,
+            Ok(()) => {}
+            Err(e) => {
+                let context = format!("Failed to remove {}", path.display());
+                self.failed.push((path, Error::IoError(e, context)));
+            }
+        }
+    }
+
+    /// Directories holding something that couldn't be removed will fail to be
+    /// removed too, but that's already been reported.
+    fn holds_failure(&self, dir: &Path) -> bool {
+        self.failed.iter().any(|(path, _)| path.starts_with(dir))
+    }
+}
 
 /// Removes an individual index metadata entry. The associated content will be
 /// left in the cache.
 ///
+/// Removing a key with no live entry is a no-op, and doesn't add anything to
+/// the index. Use [`remove_detailed`] to find out whether an entry was
+/// actually removed.
+///
 /// ## Example
 /// ```no_run
 /// use async_std::prelude::*;
@@ -31,13 +67,47 @@ use crate::index;
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn remove<P, K>(cache: P, key: K) -> Result<()>
 where
     P: AsRef<Path>,
     K: AsRef<str>,
 {
-    index::delete_async(cache.as_ref(), key.as_ref()).await
+    remove_detailed(cache, key).await.map(|_| ())
+}
+
+/// Removes an individual index metadata entry, returning the entry that was
+/// removed, or `None` if there was no live entry for `key`. Nothing is
+/// written to the index in that case. If the entry's metadata was stored
+/// outside the index and can't be read anymore, the entry is still removed,
+/// and is returned with `Value::Null` metadata.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     if cacache::remove_detailed("./my-cache", "my-key").await?.is_some() {
+///         println!("invalidated my-key");
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn remove_detailed<P, K>(cache: P, key: K) -> Result<Option<Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    async fn inner(cache: &Path, key: &str) -> Result<Option<Metadata>> {
+        let entry = index::find_lenient_async(cache.into(), key).await?;
+        if entry.is_some() {
+            index::delete_async(cache, key).await?;
+        }
+        Ok(entry)
+    }
+    inner(cache.as_ref(), key.as_ref()).await
 }
 
 /// Removes an individual content entry. Any index entries pointing to this
@@ -64,9 +134,86 @@ where
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn remove_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()> {
-    rm::rm_async(cache.as_ref(), sri).await
+    rm::rm_async(cache.as_ref().into(), sri).await
+}
+
+/// Removes an individual index entry, along with its associated content if no
+/// other live index entry still refers to that content.
+///
+/// ## Example
+/// ```no_run
+/// use async_std::prelude::*;
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///
+///     cacache::purge("./my-cache", "my-key").await?;
+///
+///     // These fail:
+///     cacache::read("./my-cache", "my-key").await?;
+///     cacache::read_hash("./my-cache", &sri).await?;
+///
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn purge<P, K>(cache: P, key: K) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    async fn inner(cache: &Path, key: &str) -> Result<()> {
+        if let Some(entry) = index::find_async(cache, key).await? {
+            index::delete_async(cache, key).await?;
+            let (root, sri) = (cache.to_path_buf(), entry.integrity.clone());
+            let referenced =
+                crate::async_lib::run_blocking(move || is_referenced(&root, &sri)).await?;
+            if !referenced {
+                rm::rm_async(cache.into(), &entry.integrity).await?;
+            }
+        }
+        Ok(())
+    }
+    inner(cache.as_ref(), key.as_ref()).await
+}
+
+/// Removes an individual index entry along with its associated content. This
+/// is the same as `RemoveOpts::new().remove_fully(true).remove(cache, key)`.
+///
+/// Unlike [`purge`], the content is removed even if other keys still point
+/// to it, and those entries will fail to read afterwards.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///
+///     cacache::remove_fully("./my-cache", "my-key").await?;
+///
+///     // These fail:
+///     cacache::read("./my-cache", "my-key").await?;
+///     cacache::read_hash("./my-cache", &sri).await?;
+///
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn remove_fully<P, K>(cache: P, key: K) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    index::RemoveOpts::new()
+        .remove_fully(true)
+        .remove(cache, key)
+        .await
 }
 
 /// Removes entire contents of the cache, including temporary files, the entry
@@ -91,22 +238,27 @@ pub async fn remove_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()
 ///     Ok(())
 /// }
 /// ```
-#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
 pub async fn clear<P: AsRef<Path>>(cache: P) -> Result<()> {
     async fn inner(cache: &Path) -> Result<()> {
-        for entry in cache
-            .read_dir()
-            .with_context(|| {
+        let entries = match crate::async_lib::read_dir_sorted(cache).await {
+            // There's nothing to clear if the cache was never created.
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            entries => entries.with_context(|| {
                 format!(
                     "Failed to read directory contents while clearing cache, at {}",
                     cache.display()
                 )
-            })?
-            .flatten()
-        {
-            crate::async_lib::remove_dir_all(entry.path())
-                .await
-                .with_context(|| format!("Failed to clear cache at {}", cache.display()))?;
+            })?,
+        };
+        // There are only a handful of top-level directories, so they're all
+        // removed at once, each on its own blocking thread.
+        let removals = entries
+            .into_iter()
+            .filter(|(path, _)| path.file_name() != Some(CONFIG_FILE.as_ref()))
+            .map(|(path, _)| crate::async_lib::remove_dir_all(path));
+        for res in futures::future::join_all(removals).await {
+            res.with_context(|| format!("Failed to clear cache at {}", cache.display()))?;
         }
         Ok(())
     }
@@ -116,6 +268,10 @@ pub async fn clear<P: AsRef<Path>>(cache: P) -> Result<()> {
 /// Removes an individual index entry synchronously. The associated content
 /// will be left in the cache.
 ///
+/// Removing a key with no live entry is a no-op, and doesn't add anything to
+/// the index. Use [`remove_detailed_sync`] to find out whether an entry was
+/// actually removed.
+///
 /// ## Example
 /// ```no_run
 /// use std::io::Read;
@@ -139,7 +295,37 @@ where
     P: AsRef<Path>,
     K: AsRef<str>,
 {
-    index::delete(cache.as_ref(), key.as_ref())
+    remove_detailed_sync(cache, key).map(|_| ())
+}
+
+/// Removes an individual index metadata entry synchronously, returning the
+/// entry that was removed, or `None` if there was no live entry for `key`.
+/// Nothing is written to the index in that case. If the entry's metadata was
+/// stored outside the index and can't be read anymore, the entry is still
+/// removed, and is returned with `Value::Null` metadata.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     if cacache::remove_detailed_sync("./my-cache", "my-key")?.is_some() {
+///         println!("invalidated my-key");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn remove_detailed_sync<P, K>(cache: P, key: K) -> Result<Option<Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> Result<Option<Metadata>> {
+        let entry = index::find_lenient(cache.into(), key)?;
+        if entry.is_some() {
+            index::delete(cache, key)?;
+        }
+        Ok(entry)
+    }
+    inner(cache.as_ref(), key.as_ref())
 }
 
 /// Removes an individual content entry synchronously. Any index entries
@@ -165,7 +351,190 @@ where
 /// }
 /// ```
 pub fn remove_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()> {
-    rm::rm(cache.as_ref(), sri)
+    rm::rm(cache.as_ref().into(), sri)
+}
+
+/// Removes an individual index entry synchronously, along with its
+/// associated content if no other live index entry still refers to that
+/// content.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Read;
+///
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///
+///     cacache::purge_sync("./my-cache", "my-key")?;
+///
+///     // These fail:
+///     cacache::read_sync("./my-cache", "my-key")?;
+///     cacache::read_hash_sync("./my-cache", &sri)?;
+///
+///     Ok(())
+/// }
+/// ```
+pub fn purge_sync<P, K>(cache: P, key: K) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    fn inner(cache: &Path, key: &str) -> Result<()> {
+        if let Some(entry) = index::find(cache, key)? {
+            index::delete(cache, key)?;
+            if !is_referenced(cache, &entry.integrity)? {
+                rm::rm(cache.into(), &entry.integrity)?;
+            }
+        }
+        Ok(())
+    }
+    inner(cache.as_ref(), key.as_ref())
+}
+
+/// Removes an individual index entry synchronously, along with its
+/// associated content. This is the same as
+/// `RemoveOpts::new().remove_fully(true).remove_sync(cache, key)`.
+///
+/// Unlike [`purge_sync`], the content is removed even if other keys still
+/// point to it, and those entries will fail to read afterwards.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///
+///     cacache::remove_fully_sync("./my-cache", "my-key")?;
+///
+///     // These fail:
+///     cacache::read_sync("./my-cache", "my-key")?;
+///     cacache::read_hash_sync("./my-cache", &sri)?;
+///
+///     Ok(())
+/// }
+/// ```
+pub fn remove_fully_sync<P, K>(cache: P, key: K) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    index::RemoveOpts::new()
+        .remove_fully(true)
+        .remove_sync(cache, key)
+}
+
+/// Removes entire contents of the cache, like [`clear`], but keeps going past
+/// files that can't be removed instead of stopping at the first one. Returns
+/// how many files were removed, and which ones failed.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::clear_detailed("./my-cache").await?;
+///     for (path, err) in stats.failed {
+///         eprintln!("couldn't remove {}: {err}", path.display());
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+pub async fn clear_detailed<P: AsRef<Path>>(cache: P) -> Result<ClearStats> {
+    async fn inner(cache: &Path) -> Result<ClearStats> {
+        // Walking the cache is blocking, so it's kept off the async runtime.
+        let root = cache.to_path_buf();
+        let (targets, mut stats) = crate::async_lib::run_blocking(move || {
+            let mut stats = ClearStats::default();
+            let targets = clear_targets(&root, &mut stats)?;
+            Ok((targets, stats))
+        })
+        .await?;
+        for (path, is_dir) in targets {
+            if !is_dir {
+                let res = crate::async_lib::remove_file(&path).await;
+                stats.record(path, false, res);
+            } else if !stats.holds_failure(&path) {
+                let res = crate::async_lib::remove_dir(&path).await;
+                stats.record(path, true, res);
+            }
+        }
+        Ok(stats)
+    }
+    inner(cache.as_ref()).await
+}
+
+/// Removes entire contents of the cache synchronously, like [`clear_sync`],
+/// but keeps going past files that can't be removed instead of stopping at
+/// the first one. Returns how many files were removed, and which ones
+/// failed.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::clear_detailed_sync("./my-cache")?;
+///     for (path, err) in stats.failed {
+///         eprintln!("couldn't remove {}: {err}", path.display());
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn clear_detailed_sync<P: AsRef<Path>>(cache: P) -> Result<ClearStats> {
+    clear_with(cache.as_ref(), |path, is_dir| {
+        if is_dir {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        }
+    })
+}
+
+/// Does the work of [`clear_detailed_sync`], using `remove` to get rid of
+/// each file and directory so tests can make removal fail.
+fn clear_with(
+    cache: &Path,
+    remove: impl Fn(&Path, bool) -> std::io::Result<()>,
+) -> Result<ClearStats> {
+    let mut stats = ClearStats::default();
+    for (path, is_dir) in clear_targets(cache, &mut stats)? {
+        if !is_dir || !stats.holds_failure(&path) {
+            let res = remove(&path, is_dir);
+            stats.record(path, is_dir, res);
+        }
+    }
+    Ok(stats)
+}
+
+/// Lists everything under `cache`, children before their parents, along with
+/// whether each one is a directory. Anything that can't be listed is recorded
+/// as a failure.
+fn clear_targets(cache: &Path, stats: &mut ClearStats) -> Result<Vec<(PathBuf, bool)>> {
+    match cache.read_dir() {
+        // There's nothing to clear if the cache was never created.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        res => res.with_context(|| {
+            format!(
+                "Failed to read directory contents while clearing cache, at {}",
+                cache.display()
+            )
+        })?,
+    };
+    let mut targets = Vec::new();
+    let config = cache.join(CONFIG_FILE);
+    for entry in WalkDir::new(cache).min_depth(1).contents_first(true) {
+        match entry {
+            Ok(entry) if entry.path() == config => {}
+            Ok(entry) => {
+                let is_dir = entry.file_type().is_dir();
+                targets.push((entry.into_path(), is_dir));
+            }
+            Err(e) => {
+                let path = e.path().unwrap_or(cache).to_path_buf();
+                stats.record(path, true, Err(e.into()));
+            }
+        }
+    }
+    Ok(targets)
 }
 
 /// Removes entire contents of the cache synchronously, including temporary
@@ -190,15 +559,19 @@ pub fn remove_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()>
 /// ```
 pub fn clear_sync<P: AsRef<Path>>(cache: P) -> Result<()> {
     fn inner(cache: &Path) -> Result<()> {
-        for entry in cache
-            .read_dir()
-            .with_context(|| {
+        let entries = match cache.read_dir() {
+            // There's nothing to clear if the cache was never created.
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            entries => entries.with_context(|| {
                 format!(
                     "Failed to read directory contents while clearing cache, at {}",
                     cache.display()
                 )
-            })?
+            })?,
+        };
+        for entry in entries
             .flatten()
+            .filter(|entry| entry.file_name() != CONFIG_FILE)
         {
             fs::remove_dir_all(entry.path())
                 .with_context(|| format!("Failed to clear cache at {}", cache.display()))?;
@@ -208,15 +581,25 @@ pub fn clear_sync<P: AsRef<Path>>(cache: P) -> Result<()> {
     inner(cache.as_ref())
 }
 
+/// Returns true if any live index entry points at `sri`, either as its
+/// content or as its external metadata. Only the index itself is read.
+fn is_referenced(cache: &Path, sri: &Integrity) -> Result<bool> {
+    Ok(index::referenced_content(cache.into())?
+        .iter()
+        .any(|referenced| referenced.matches(sri).is_some()))
+}
+
 #[cfg(test)]
 mod tests {
 
-    #[cfg(feature = "async-std")]
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
     use async_attributes::test as async_test;
     #[cfg(feature = "tokio")]
     use tokio::test as async_test;
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_remove() {
         futures::executor::block_on(async {
@@ -234,7 +617,7 @@ mod tests {
         });
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_remove_data() {
         futures::executor::block_on(async {
@@ -252,7 +635,7 @@ mod tests {
         });
     }
 
-    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
     #[async_test]
     async fn test_clear() {
         futures::executor::block_on(async {
@@ -267,9 +650,89 @@ mod tests {
 
             let data_exists = crate::exists(&dir, &sri).await;
             assert!(!data_exists);
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
         });
     }
 
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_clear_missing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("never-created");
+        crate::clear(&dir).await.unwrap();
+        crate::clear_sync(&dir).unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_remove_detailed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert!(crate::remove_detailed(&dir, "key").await.unwrap().is_none());
+
+        let sri = crate::write(&dir, "key", b"my-data").await.unwrap();
+        let removed = crate::remove_detailed(&dir, "key").await.unwrap().unwrap();
+        assert_eq!(removed.integrity, sri);
+        assert!(crate::remove_detailed(&dir, "key").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_remove_detailed_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert!(crate::remove_detailed_sync(&dir, "key").unwrap().is_none());
+        // No tombstone gets written for a key that was never there.
+        assert!(!dir.join("index-v5").exists());
+
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        let removed = crate::remove_detailed_sync(&dir, "key").unwrap().unwrap();
+        assert_eq!(removed.integrity, sri);
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+
+        // Removing it again is a no-op too, rather than another tombstone.
+        let index_size = || {
+            walkdir::WalkDir::new(dir.join("index-v5"))
+                .into_iter()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .sum::<u64>()
+        };
+        let before = index_size();
+        crate::remove_sync(&dir, "key").unwrap();
+        assert!(crate::remove_detailed_sync(&dir, "key").unwrap().is_none());
+        assert_eq!(index_size(), before);
+    }
+
+    #[test]
+    fn test_remove_missing_external_metadata_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::WriteOpts::new()
+            .metadata(serde_json::json!({ "etag": "abc" }))
+            .external_metadata(0)
+            .open_sync(&dir, "key")
+            .and_then(|mut writer| {
+                std::io::Write::write_all(&mut writer, b"my-data").unwrap();
+                writer.commit()
+            })
+            .unwrap();
+        // Lose the metadata blob, but keep the data.
+        for file in walkdir::WalkDir::new(dir.join("content-v2")) {
+            let file = file.unwrap();
+            if file.file_type().is_file()
+                && crate::content::path::path_integrity(file.path()) != Some(sri.clone())
+            {
+                std::fs::remove_file(file.path()).unwrap();
+            }
+        }
+        assert!(crate::metadata_sync(&dir, "key").is_err());
+
+        let removed = crate::remove_detailed_sync(&dir, "key").unwrap().unwrap();
+        assert_eq!(removed.integrity, sri);
+        assert_eq!(removed.metadata, serde_json::Value::Null);
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+    }
+
     #[test]
     fn test_remove_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -300,6 +763,109 @@ mod tests {
         assert!(!data_exists);
     }
 
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "async-fs"))]
+    #[async_test]
+    async fn test_purge() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "key", b"my-data").await.unwrap();
+        crate::write(&dir, "other", b"my-data").await.unwrap();
+
+        crate::purge(&dir, "key").await.unwrap();
+        assert!(crate::metadata(&dir, "key").await.unwrap().is_none());
+        assert!(crate::exists(&dir, &sri).await);
+
+        crate::purge(&dir, "other").await.unwrap();
+        assert!(crate::metadata(&dir, "other").await.unwrap().is_none());
+        assert!(!crate::exists(&dir, &sri).await);
+    }
+
+    #[test]
+    fn test_purge_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "other", b"my-data").unwrap();
+
+        crate::purge_sync(&dir, "key").unwrap();
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+        assert!(crate::exists_sync(&dir, &sri));
+
+        crate::purge_sync(&dir, "other").unwrap();
+        assert!(crate::metadata_sync(&dir, "other").unwrap().is_none());
+        assert!(!crate::exists_sync(&dir, &sri));
+    }
+
+    #[test]
+    fn test_purge_ignores_external_metadata_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::WriteOpts::new()
+            .metadata(serde_json::json!({ "etag": "abc" }))
+            .external_metadata(0)
+            .open_sync(&dir, "other")
+            .and_then(|mut writer| {
+                std::io::Write::write_all(&mut writer, b"other-data").unwrap();
+                writer.commit()
+            })
+            .unwrap();
+        // Lose "other"'s metadata blob. Purging "key" only needs to know
+        // what "other" refers to, not what its metadata says.
+        let blob = crate::index::find(&dir, "other").unwrap().unwrap();
+        let blob_sri =
+            crate::write_hash_sync(&dir, serde_json::to_vec(&blob.metadata).unwrap()).unwrap();
+        std::fs::remove_file(crate::content_path(&dir, &blob_sri)).unwrap();
+        assert!(crate::metadata_sync(&dir, "other").is_err());
+
+        crate::purge_sync(&dir, "key").unwrap();
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+        assert!(!crate::exists_sync(&dir, &sri));
+    }
+
+    #[test]
+    fn test_clear_detailed_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        let locked = dir.join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::write(locked.join("stuck"), b"stuck").unwrap();
+
+        let stats = super::clear_with(&dir, |path, is_dir| {
+            if path.ends_with("stuck") {
+                Err(crate::errors::io_error("nope"))
+            } else if is_dir {
+                std::fs::remove_dir(path)
+            } else {
+                std::fs::remove_file(path)
+            }
+        })
+        .unwrap();
+
+        assert_eq!(stats.failed.len(), 1);
+        assert_eq!(stats.failed[0].0, locked.join("stuck"));
+        assert!(stats.removed >= 2);
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+        assert!(!crate::exists_sync(&dir, &sri));
+        assert!(locked.join("stuck").exists());
+    }
+
+    #[test]
+    fn test_remove_fully_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "other", b"my-data").unwrap();
+
+        crate::remove_fully_sync(&dir, "key").unwrap();
+
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_none());
+        assert!(!crate::exists_sync(&dir, &sri));
+        // Shared content goes too.
+        assert!(crate::read_sync(&dir, "other").is_err());
+    }
+
     #[test]
     fn test_clear_sync() {
         let tmp = tempfile::tempdir().unwrap();
@@ -0,0 +1,115 @@
+//! Helpers for calling the async API from synchronous code.
+//!
+//! Every async function in this crate has a `_sync` counterpart, and those
+//! should be preferred from synchronous code: they don't need a runtime at
+//! all. When only the async API will do, such as for the split `Writer` or
+//! `read_stream()`, [`block_on`] runs a future to completion on whichever
+//! runtime this crate was built for.
+//!
+//! Going the other way, the `_sync` functions block on filesystem calls, so
+//! async code with many of them to make should move them onto a blocking
+//! thread pool rather than calling them directly.
+use std::future::Future;
+
+/// Runs `future` to completion, blocking the current thread until it's done.
+///
+/// Unlike calling a runtime's own `block_on`, this is safe to call from
+/// inside a running executor, including a tokio runtime, where it would
+/// normally panic. In that case, the future is driven on a separate thread
+/// instead, which is why it must be `Send`.
+///
+/// Under tokio, this uses a shared current-thread runtime, created the first
+/// time it's needed.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let data = cacache::runtime::block_on(cacache::read("./my-cache", "my-key"))?;
+///     Ok(())
+/// }
+/// ```
+pub fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    if in_executor() {
+        std::thread::scope(|s| {
+            s.spawn(|| run(future))
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    } else {
+        run(future)
+    }
+}
+
+#[cfg(feature = "async-std")]
+fn in_executor() -> bool {
+    // async-std supports nested calls to its own `block_on`.
+    false
+}
+
+#[cfg(feature = "async-std")]
+fn run<F: Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+}
+
+#[cfg(feature = "async-fs")]
+fn in_executor() -> bool {
+    futures::executor::enter().is_err()
+}
+
+#[cfg(feature = "async-fs")]
+fn run<F: Future>(future: F) -> F::Output {
+    futures::executor::block_on(future)
+}
+
+#[cfg(feature = "tokio")]
+fn in_executor() -> bool {
+    tokio::runtime::Handle::try_current().is_ok()
+}
+
+#[cfg(feature = "tokio")]
+fn run<F: Future>(future: F) -> F::Output {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .expect("Failed to start a tokio runtime")
+        })
+        .block_on(future)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "async-fs")]
+    use crate::async_lib::test_runtime as async_std;
+    #[cfg(any(feature = "async-std", feature = "async-fs"))]
+    use async_attributes::test as async_test;
+    #[cfg(feature = "tokio")]
+    use tokio::test as async_test;
+
+    #[test]
+    fn block_on_outside_runtime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        block_on(crate::write(&dir, "key", b"hello")).unwrap();
+        assert_eq!(block_on(crate::read(&dir, "key")).unwrap(), b"hello");
+    }
+
+    #[async_test]
+    async fn block_on_inside_runtime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "key", b"hello").await.unwrap();
+        let data = block_on(async {
+            // Nesting works too.
+            block_on(crate::read(&dir, "key"))
+        });
+        assert_eq!(data.unwrap(), b"hello");
+    }
+}